@@ -6,31 +6,27 @@ use flume::{Sender, Receiver, TryRecvError};
 
 use sdl2::audio::{AudioQueue, AudioSpecDesired, AudioDevice};
 use sdl2::AudioSubsystem;
-use crate::apu::filter::Filter;
+use crate::apu::audio_sink::{AudioFormat, AudioSink};
 use crate::apu::callback::Callback;
 use std::rc::Rc;
 use std::cell::RefCell;
 use std::borrow::Borrow;
 
-const AUDIO_HERTZ: u16 = 44100;
 const BUFFER_SIZE: usize = 512;
 
-pub struct Audio {
-    tx: Sender<f32>,
+pub struct SdlAudioSink {
+    // the device has to close before the sender goes away, or the callback sees a disconnect
     playback: AudioDevice<Callback>,
-
-    // filters
-    highPassFilter1: Filter,
-    highPassFilter2: Filter,
-    lowPassFilter: Filter,
+    tx: Sender<f32>,
+    format: AudioFormat,
 }
 
-impl Audio {
-    pub fn new(audioSystem: Rc<RefCell<AudioSubsystem>>) -> Self {
+impl SdlAudioSink {
+    pub fn new(audioSystem: Rc<RefCell<AudioSubsystem>>, format: AudioFormat) -> Self {
 
         let specs = AudioSpecDesired {
-            freq: Some(AUDIO_HERTZ as i32),
-            channels: Some(1),
+            freq: Some(format.sampleRate as i32),
+            channels: Some(format.channels.count() as u8),
             samples: Some(BUFFER_SIZE as u16)
         };
 
@@ -44,37 +40,24 @@ impl Audio {
         }).unwrap();
         playback.resume();
 
-        Audio {
-            tx,
+        SdlAudioSink {
             playback,
-            highPassFilter1: Filter::HighPassFilter(AUDIO_HERTZ as f32, 90 as f32),
-            highPassFilter2: Filter::HighPassFilter(AUDIO_HERTZ as f32, 440 as f32),
-            lowPassFilter: Filter::LowPassFilter(AUDIO_HERTZ as f32, 14000 as f32),
+            tx,
+            format,
         }
     }
+}
 
-    pub fn pushSample(&mut self, sample: f32) -> () {
-        let filteredSample = self.filterSample(sample);
-        self.tx.send(filteredSample);
-        // self.buffer[self.bufferIdx] = filteredSample;
-        // self.bufferIdx += 1;
-        //
-        // if self.bufferIdx == BUFFER_SIZE {
-        //     self.queue.queue(self.buffer.as_slice());
-        //     self.bufferIdx = 0;
-        // }
+impl AudioSink for SdlAudioSink {
+    fn pushSample(&mut self, sample: f32) -> () {
+        self.tx.send(sample);
     }
 
-    fn filterSample(&mut self, sample: f32) -> f32 {
-        let mut fSample = self.highPassFilter1.Step(sample);
-        fSample = self.highPassFilter2.Step(fSample);
-        fSample = self.lowPassFilter.Step(fSample);
-        return fSample;
+    fn getFormat(&self) -> AudioFormat {
+        return self.format;
     }
-}
 
-// impl Drop for Audio {
-//     fn drop(&mut self) {
-//         self.playback.close_and_get_callback();
-//     }
-// }
+    fn queuedSamples(&self) -> Option<usize> {
+        return Some(self.tx.len());
+    }
+}
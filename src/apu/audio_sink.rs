@@ -0,0 +1,180 @@
+#![allow(non_snake_case)]
+#![allow(warnings)]
+
+use std::cell::RefCell;
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::rc::Rc;
+use crate::apu::utils::AUDIO_HERTZ;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum AudioChannels {
+    Mono,
+    Stereo,
+}
+
+impl AudioChannels {
+    pub fn count(&self) -> u16 {
+        return match self {
+            AudioChannels::Mono => 1,
+            AudioChannels::Stereo => 2,
+        };
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct AudioFormat {
+    pub sampleRate: u32,
+    pub channels: AudioChannels,
+}
+
+impl Default for AudioFormat {
+    fn default() -> Self {
+        AudioFormat {
+            sampleRate: AUDIO_HERTZ as u32,
+            channels: AudioChannels::Mono,
+        }
+    }
+}
+
+// Anything that can consume the APU's filtered output. Stereo samples arrive interleaved, left first.
+pub trait AudioSink {
+    fn pushSample(&mut self, sample: f32) -> ();
+
+    // the APU renders at whatever rate and channel count the sink asks for
+    fn getFormat(&self) -> AudioFormat;
+
+    // samples pushed but not played yet, for sinks that feed a real device
+    fn queuedSamples(&self) -> Option<usize> {
+        return None;
+    }
+}
+
+// Throws every sample away. Handy for headless runs on machines without a sound device.
+pub struct NullAudioSink {
+    format: AudioFormat,
+}
+
+impl NullAudioSink {
+    pub fn new() -> Self {
+        NullAudioSink {
+            format: AudioFormat::default(),
+        }
+    }
+
+    pub fn withFormat(format: AudioFormat) -> Self {
+        NullAudioSink {
+            format,
+        }
+    }
+}
+
+impl AudioSink for NullAudioSink {
+    fn pushSample(&mut self, sample: f32) -> () {}
+
+    fn getFormat(&self) -> AudioFormat {
+        return self.format;
+    }
+}
+
+// Collects samples into a shared buffer so they can be inspected after the core is built.
+pub struct BufferAudioSink {
+    buffer: Rc<RefCell<Vec<f32>>>,
+    format: AudioFormat,
+}
+
+impl BufferAudioSink {
+    pub fn new() -> Self {
+        return BufferAudioSink::withFormat(AudioFormat::default());
+    }
+
+    pub fn withFormat(format: AudioFormat) -> Self {
+        BufferAudioSink {
+            buffer: Rc::new(RefCell::new(Vec::new())),
+            format,
+        }
+    }
+
+    pub fn getBuffer(&self) -> Rc<RefCell<Vec<f32>>> {
+        return self.buffer.clone();
+    }
+}
+
+impl AudioSink for BufferAudioSink {
+    fn pushSample(&mut self, sample: f32) -> () {
+        self.buffer.borrow_mut().push(sample);
+    }
+
+    fn getFormat(&self) -> AudioFormat {
+        return self.format;
+    }
+}
+
+const WAV_HEADER_SIZE: u32 = 44;
+
+// Writes 16-bit PCM. The RIFF and data sizes are patched in when the sink is dropped.
+pub struct WavAudioSink {
+    writer: BufWriter<File>,
+    numSamples: u32,
+    format: AudioFormat,
+}
+
+impl WavAudioSink {
+    pub fn new(path: &Path, format: AudioFormat) -> io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        let sampleRate = format.sampleRate;
+        let channels = format.channels.count();
+
+        writer.write_all(b"RIFF")?;
+        writer.write_all(&0u32.to_le_bytes())?;
+        writer.write_all(b"WAVE")?;
+
+        writer.write_all(b"fmt ")?;
+        writer.write_all(&16u32.to_le_bytes())?;
+        writer.write_all(&1u16.to_le_bytes())?;              // PCM
+        writer.write_all(&channels.to_le_bytes())?;
+        writer.write_all(&sampleRate.to_le_bytes())?;
+        writer.write_all(&(sampleRate * channels as u32 * 2).to_le_bytes())?;  // byte rate
+        writer.write_all(&(channels * 2).to_le_bytes())?;                      // block align
+        writer.write_all(&16u16.to_le_bytes())?;             // bits per sample
+
+        writer.write_all(b"data")?;
+        writer.write_all(&0u32.to_le_bytes())?;
+
+        Ok(WavAudioSink {
+            writer,
+            numSamples: 0,
+            format,
+        })
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        let dataSize = self.numSamples * 2;
+        self.writer.seek(SeekFrom::Start(4))?;
+        self.writer.write_all(&(WAV_HEADER_SIZE - 8 + dataSize).to_le_bytes())?;
+        self.writer.seek(SeekFrom::Start(40))?;
+        self.writer.write_all(&dataSize.to_le_bytes())?;
+        self.writer.flush()
+    }
+}
+
+impl AudioSink for WavAudioSink {
+    fn pushSample(&mut self, sample: f32) -> () {
+        let pcm = (sample.max(-1.0).min(1.0) * i16::MAX as f32) as i16;
+        if self.writer.write_all(&pcm.to_le_bytes()).is_ok() {
+            self.numSamples += 1;
+        }
+    }
+
+    fn getFormat(&self) -> AudioFormat {
+        return self.format;
+    }
+}
+
+impl Drop for WavAudioSink {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
@@ -0,0 +1,146 @@
+#![allow(non_snake_case)]
+#![allow(warnings)]
+
+use std::f64::consts::PI;
+
+// kernel resolution between output samples, and its length either side of the step
+const PHASES: usize = 32;
+const HALF_WIDTH: usize = 8;
+const WIDTH: usize = HALF_WIDTH * 2;
+
+// fraction of the output Nyquist frequency the kernel lets through
+const CUTOFF: f64 = 0.9;
+
+// Band-limited step synthesis, in the style of Shay Green's blip_buf. The APU reports each change
+// in its output level as a delta at a CPU clock; every delta is spread over the nearby output
+// samples with a windowed sinc step, so nothing above the output Nyquist frequency aliases back down.
+// Samples come out once the frame is ended and they can no longer receive contributions.
+pub struct BlipBuffer {
+    kernel: Vec<[f32; WIDTH]>,
+    buffer: Vec<f32>,
+    samplesPerClock: f64,
+    frameStart: f64,    // output position of clock 0 in the current frame
+    integrator: f32,
+}
+
+impl BlipBuffer {
+    pub fn new(clockRate: f64, sampleRate: f64) -> Self {
+        BlipBuffer {
+            kernel: BlipBuffer::buildKernel(),
+            buffer: vec![0.0; WIDTH],
+            samplesPerClock: sampleRate / clockRate,
+            frameStart: 0.0,
+            integrator: 0.0,
+        }
+    }
+
+    pub fn setRates(&mut self, clockRate: f64, sampleRate: f64) -> () {
+        self.samplesPerClock = sampleRate / clockRate;
+    }
+
+    pub fn addDelta(&mut self, clockTime: u32, delta: f32) -> () {
+        let pos = self.frameStart + clockTime as f64 * self.samplesPerClock;
+        let mut whole = pos as usize;
+        let mut phase = ((pos - whole as f64) * PHASES as f64).round() as usize;
+        if phase == PHASES {
+            whole += 1;
+            phase = 0;
+        }
+
+        if self.buffer.len() < whole + WIDTH {
+            self.buffer.resize(whole + WIDTH, 0.0);
+        }
+
+        let kernel = &self.kernel[phase];
+        for (i, el) in kernel.iter().enumerate() {
+            self.buffer[whole + i] += delta * el;
+        }
+    }
+
+    // moves time forward by however many clocks the frame lasted
+    pub fn endFrame(&mut self, clocks: u32) -> () {
+        self.frameStart += clocks as f64 * self.samplesPerClock;
+    }
+
+    pub fn samplesAvailable(&self) -> usize {
+        return self.frameStart as usize;
+    }
+
+    // Appends every finished sample to `out`. Deltas have been summed into steps by the time they come out.
+    pub fn readSamples(&mut self, out: &mut Vec<f32>) -> () {
+        let count = self.samplesAvailable();
+        if self.buffer.len() < count + WIDTH {
+            self.buffer.resize(count + WIDTH, 0.0);
+        }
+
+        for el in self.buffer.drain(0..count) {
+            self.integrator += el;
+            out.push(self.integrator);
+        }
+
+        self.frameStart -= count as f64;
+    }
+
+    // Each phase is an impulse (a sinc through a Blackman window) centred HALF_WIDTH samples in,
+    // normalised so a delta always adds up to exactly itself once integrated.
+    fn buildKernel() -> Vec<[f32; WIDTH]> {
+        let mut kernel = vec![[0.0; WIDTH]; PHASES];
+
+        for phase in 0..PHASES {
+            let offset = phase as f64 / PHASES as f64;
+            let mut taps = [0.0f64; WIDTH];
+
+            for i in 0..WIDTH {
+                let t = i as f64 - (HALF_WIDTH as f64 - 1.0) - offset;
+                let sinc = if t == 0.0 { CUTOFF } else { (PI * t * CUTOFF).sin() / (PI * t) };
+                let window = if t.abs() >= HALF_WIDTH as f64 {
+                    0.0
+                } else {
+                    0.42 + 0.5 * (PI * t / HALF_WIDTH as f64).cos() + 0.08 * (2.0 * PI * t / HALF_WIDTH as f64).cos()
+                };
+                taps[i] = sinc * window;
+            }
+
+            let sum: f64 = taps.iter().sum();
+            for i in 0..WIDTH {
+                kernel[phase][i] = (taps[i] / sum) as f32;
+            }
+        }
+
+        return kernel;
+    }
+}
+
+#[cfg(test)]
+mod BlipBufferSpec {
+    use super::*;
+
+    #[test]
+    fn stepSettlesAtDelta() -> () {
+        let mut blip = BlipBuffer::new(1789773.0, 44100.0);
+        blip.addDelta(100, 0.5);
+        blip.endFrame(29781);
+
+        let mut out = Vec::new();
+        blip.readSamples(&mut out);
+
+        assert_eq!(out.len(), 733);
+        assert!(out[0].abs() < 0.01);
+        assert!((out[out.len() - 1] - 0.5).abs() < 0.0001);
+    }
+
+    #[test]
+    fn keepsFractionalTime() -> () {
+        let mut blip = BlipBuffer::new(1789773.0, 48000.0);
+        let mut total = 0;
+        for _ in 0..60 {
+            blip.endFrame(29830);
+            let mut out = Vec::new();
+            blip.readSamples(&mut out);
+            total += out.len();
+        }
+
+        // 60 frames of 29830 clocks is just under a second
+        assert_eq!(total, (60.0 * 29830.0 * 48000.0 / 1789773.0) as usize);
+    }
+}
@@ -8,8 +8,7 @@ use std::rc::Rc;
 use std::sync::{Arc, Mutex};
 
 use lazy_static::lazy_static;
-
-use audio::Audio;
+use ndarray::Array1;
 
 use crate::clock::Clocked;
 use crate::data_bus::DataBus;
@@ -17,8 +16,11 @@ use crate::apu::noise::Noise;
 use crate::apu::pulse::Pulse;
 use crate::apu::triangle::Triangle;
 use crate::apu::dmc::DMC;
+use crate::apu::audio_sink::{AudioChannels, AudioFormat, AudioSink};
+use crate::apu::blip_buffer::BlipBuffer;
+use crate::apu::filter::Filter;
+use crate::region::Region;
 use utils::*;
-use sdl2::AudioSubsystem;
 use crate::save_load::{ApuData, DMCData, NoiseData, PulseData, TriangleData};
 
 pub mod utils;
@@ -28,8 +30,12 @@ pub mod triangle;
 pub mod noise;
 pub mod dmc;
 pub mod audio;
+pub mod audio_sink;
+pub mod blip_buffer;
 pub mod callback;
 
+// joins between stretched frames are crossfaded over this fraction of a frame
+const STRETCH_OVERLAP_DIVISOR: usize = 8;
 
 /*
  |  0   1   2   3   4   5   6   7    8   9   A   B   C   D   E   F
@@ -49,15 +55,35 @@ pub struct Apu {
     fiveStep: bool,
     frameInterrupt: bool,
     inhibitInterrupt: bool,
-    audio: Audio,
+    samples: Vec<f32>,
+    audioSink: Box<dyn AudioSink>,
+
+    // band-limited output, one per speaker
+    outputs: Vec<OutputChannel>,
+    audioFormat: AudioFormat,
+    cpuHertz: f64,
+    rateAdjust: f64,
+    frameClock: u32,
+    muted: bool,
+    speed: f64,
+    stretchDebt: f64,
+    stretchPending: Vec<f32>,   // the last frame's samples, held until we know what followed them
+    stretchCarry: Vec<f32>,     // what came after the frame played last, to crossfade out of
 
     lengthTable: Vec<u8>,
     pulseTable: Vec<f32>,
     tndTable: Vec<f32>,
+
+    // region timing
+    frameSteps: [u16; 5],
+    noiseTimerTable: &'static Array1<u16>,
+    dmcRateTable: &'static Array1<u16>,
 }
 
 impl Apu {
-    pub fn new(dataBus: Rc<RefCell<DataBus>>, audioSystem: Rc<RefCell<AudioSubsystem>>) -> Self {
+    pub fn new(dataBus: Rc<RefCell<DataBus>>, audioSink: Box<dyn AudioSink>) -> Self {
+        let audioFormat = audioSink.getFormat();
+
         /*
         table:  .byte 10, 254, 20,  2, 40,  4, 80,  6
     .byte 160,  8, 60, 10, 14, 12, 26, 14
@@ -92,10 +118,66 @@ impl Apu {
             fiveStep: false,
             frameInterrupt: false,
             inhibitInterrupt: false,
-            audio: Audio::new(audioSystem),
+            samples: Vec::new(),
+            outputs: (0..audioFormat.channels.count()).map(|_| OutputChannel::new(&audioFormat)).collect(),
+            audioFormat,
+            audioSink,
+            cpuHertz: Region::Ntsc.cpuHertz(),
+            rateAdjust: 1.0,
+            frameClock: 0,
+            muted: false,
+            speed: 1.0,
+            stretchDebt: 0.0,
+            stretchPending: Vec::new(),
+            stretchCarry: Vec::new(),
             lengthTable: Vec::from(lengthTable),
             pulseTable: pulseTable,
             tndTable: tndTable,
+            frameSteps: Region::Ntsc.frameCounterSteps(),
+            noiseTimerTable: Region::Ntsc.noiseTimerTable(),
+            dmcRateTable: Region::Ntsc.dmcRateTable(),
+        }
+    }
+
+    pub fn setRegion(&mut self, region: Region) -> () {
+        self.frameSteps = region.frameCounterSteps();
+        self.noiseTimerTable = region.noiseTimerTable();
+        self.dmcRateTable = region.dmcRateTable();
+        self.cpuHertz = region.cpuHertz();
+        self.updateOutputRates();
+    }
+
+    // a small factor on the output rate, see Nes::setAudioRateAdjust
+    pub fn setRateAdjust(&mut self, adjust: f64) -> () {
+        self.rateAdjust = adjust;
+        self.updateOutputRates();
+    }
+
+    // samples are still generated while muted, they just don't reach the sink
+    pub fn setMuted(&mut self, muted: bool) -> () {
+        self.muted = muted;
+    }
+
+    pub fn isMuted(&self) -> bool {
+        return self.muted;
+    }
+
+    // How fast the console is running compared to real time. Away from 1.0 the audio keeps its pitch
+    // by dropping whole frames of it (faster) or playing them more than once (slower), crossfading
+    // each join. That holds every frame back until the next one is generated.
+    pub fn setSpeed(&mut self, speed: f64) -> () {
+        if speed != self.speed {
+            self.speed = speed;
+            self.stretchDebt = 0.0;
+            self.stretchPending.clear();
+            self.stretchCarry.clear();
+        }
+    }
+
+    fn updateOutputRates(&mut self) -> () {
+        let sampleRate = self.audioFormat.sampleRate as f64 * self.rateAdjust;
+        for output in self.outputs.iter_mut() {
+            output.blip.setRates(self.cpuHertz, sampleRate);
         }
     }
 
@@ -203,9 +285,6 @@ impl Apu {
             fiveStep: self.fiveStep,
             frameInterrupt: self.frameInterrupt,
             inhibitInterrupt: self.inhibitInterrupt,
-            lengthTable: self.lengthTable.clone(),
-            pulseTable: self.pulseTable.clone(),
-            tndTable: self.tndTable.clone()
             
         }
     }
@@ -215,9 +294,6 @@ impl Apu {
         self.fiveStep = data.fiveStep;
         self.frameInterrupt = data.frameInterrupt;
         self.inhibitInterrupt = data.inhibitInterrupt;
-        self.lengthTable = data.lengthTable.clone();
-        self.pulseTable = data.pulseTable.clone();
-        self.tndTable = data.tndTable.clone();
         
         // pulse 1
         self.pulse1.isChannelOne = data.pulse1.isChannelOne;
@@ -357,13 +433,13 @@ impl Apu {
                 self.noise.writeEnvelopeVolumeCounter(data);
             }
             0x400E => {
-                self.noise.writeLoopNoise(data, NOISE_TIMER_TABLE[(data & 15) as usize]);
+                self.noise.writeLoopNoise(data, self.noiseTimerTable[(data & 15) as usize]);
             }
             0x400F => {
                 self.noise.writeLengthCounter( self.lengthTable[(data >> 3) as usize]);
             }
             0x4010 => {
-                self.dmc.writeIrqLoopFreq(data, DMC_RATE_TABLE[(data & 15) as usize]);
+                self.dmc.writeIrqLoopFreq(data, self.dmcRateTable[(data & 15) as usize]);
             }
             0x4011 => {
                 self.dmc.writeLoadCounter(data);
@@ -458,15 +534,109 @@ impl Apu {
         return status;
     }
 
-    pub fn addSampleToBuffer(&mut self) -> () {
-        let pulseOut: f32 = self.pulseTable[(self.pulse1.output() + self.pulse2.output()) as usize];
-        let tndOut: f32 = self.tndTable[
-            (3 * self.triangle.output()) as usize +
-                (2 * self.noise.output()) as usize +
-                (self.dmc.output()) as usize
-            ];
+    // Level of each speaker after the non-linear mixer. In stereo pulse 1 leans left and pulse 2 right.
+    fn mixLevels(&mut self) -> [f32; 2] {
+        let pulse1 = self.pulse1.output() as f32;
+        let pulse2 = self.pulse2.output() as f32;
+        let tnd = (3 * self.triangle.output()) as usize + (2 * self.noise.output()) as usize + self.dmc.output() as usize;
+        let tndOut = self.tndTable[tnd];
+
+        return match self.audioFormat.channels {
+            AudioChannels::Mono => {
+                let level = self.pulseTable[(pulse1 + pulse2) as usize] + tndOut;
+                [level, level]
+            }
+            AudioChannels::Stereo => {
+                [
+                    Apu::mixPulse(1.5 * pulse1 + 0.5 * pulse2) + tndOut,
+                    Apu::mixPulse(0.5 * pulse1 + 1.5 * pulse2) + tndOut,
+                ]
+            }
+        };
+    }
+
+    // same curve as pulseTable, for inputs that aren't whole numbers
+    fn mixPulse(input: f32) -> f32 {
+        if input == 0.0 {
+            return 0.0;
+        }
+        return 95.52 / (8128.0 / input + 100.0);
+    }
+
+    // Hands every finished sample to the sink. Call once the frame's CPU cycles have all run.
+    pub fn endFrame(&mut self) -> () {
+        let mut channels: Vec<Vec<f32>> = Vec::with_capacity(self.outputs.len());
+        for output in self.outputs.iter_mut() {
+            output.blip.endFrame(self.frameClock);
+            let mut samples = Vec::new();
+            output.blip.readSamples(&mut samples);
+            for sample in samples.iter_mut() {
+                *sample = output.filter(*sample);
+            }
+            channels.push(samples);
+        }
+        self.frameClock = 0;
+
+        // interleave, left first
+        let numSamples = channels.iter().map(|samples| samples.len()).min().unwrap_or(0);
+        let frameStart = self.samples.len();
+        for idx in 0..numSamples {
+            for samples in channels.iter() {
+                self.samples.push(samples[idx]);
+            }
+        }
+
+        if self.muted {
+            return;
+        }
+
+        if self.speed == 1.0 {
+            for sample in self.samples[frameStart..].iter() {
+                self.audioSink.pushSample(*sample);
+            }
+            return;
+        }
+
+        // the frame before this one gets played now that we have what followed it
+        let previous = std::mem::replace(&mut self.stretchPending, self.samples[frameStart..].to_vec());
+        if previous.is_empty() {
+            return;
+        }
+
+        // each emulated frame is owed 1 / speed frames of real time
+        let overlap = (numSamples / STRETCH_OVERLAP_DIVISOR) * self.outputs.len();
+        self.stretchDebt += 1.0 / self.speed;
+        while self.stretchDebt >= 1.0 {
+            self.stretchDebt -= 1.0;
+            self.pushCrossfaded(&previous);
+            self.stretchCarry = self.stretchPending[..overlap.min(self.stretchPending.len())].to_vec();
+        }
+    }
+
+    // Overlap-adds the start of `samples` onto what really followed the last frame played, so jumping
+    // between frames that weren't next to each other doesn't click. The output is as long as `samples`.
+    fn pushCrossfaded(&mut self, samples: &[f32]) -> () {
+        let numChannels = self.outputs.len();
+        let overlap = self.stretchCarry.len().min(samples.len());
+        let overlapFrames = (overlap / numChannels) as f32;
+
+        for (idx, sample) in samples.iter().enumerate() {
+            if idx < overlap {
+                let fadeIn = ((idx / numChannels) as f32 + 0.5) / overlapFrames;
+                self.audioSink.pushSample(self.stretchCarry[idx] * (1.0 - fadeIn) + *sample * fadeIn);
+            }
+            else {
+                self.audioSink.pushSample(*sample);
+            }
+        }
+    }
+
+    pub fn queuedSamples(&self) -> Option<usize> {
+        return self.audioSink.queuedSamples();
+    }
 
-        self.audio.pushSample(pulseOut + tndOut);
+    pub fn takeSamples(&mut self) -> Vec<f32> {
+        return std::mem::take(&mut self.samples);
     }
 
     fn quarterStep(&mut self) -> () {
@@ -491,18 +661,19 @@ impl Apu {
 
 impl Clocked for Apu {
     fn cycle(&mut self) {
+        let [stepOne, stepTwo, stepThree, stepFour, stepFive] = self.frameSteps;
         match self.frame {
-            STEP_ONE_CYCLE => {
+            frame if frame == stepOne => {
                 self.quarterStep();
             }
-            STEP_TWO_CYCLE => {
+            frame if frame == stepTwo => {
                 self.halfStep();
                 self.quarterStep();
             }
-            STEP_THREE_CYCLE => {
+            frame if frame == stepThree => {
                 self.quarterStep();
             }
-            STEP_FOUR_CYCLE => {
+            frame if frame == stepFour => {
                 if !self.fiveStep {
                     self.halfStep();
                     self.quarterStep();
@@ -513,7 +684,7 @@ impl Clocked for Apu {
                     self.frame = 0;
                 }
             }
-            STEP_FIVE_CYCLE => {
+            frame if frame == stepFive => {
                 if self.fiveStep {
                     self.quarterStep();
                     self.halfStep();
@@ -532,6 +703,43 @@ impl Clocked for Apu {
         }
         self.triangle.clockTimer();
 
+        let levels = self.mixLevels();
+        for (output, level) in self.outputs.iter_mut().zip(levels.iter()) {
+            if *level != output.lastLevel {
+                output.blip.addDelta(self.frameClock, *level - output.lastLevel);
+                output.lastLevel = *level;
+            }
+        }
+        self.frameClock += 1;
+
         self.frame += 1;
     }
 }
+
+struct OutputChannel {
+    blip: BlipBuffer,
+    lastLevel: f32,
+    highPassFilter1: Filter,
+    highPassFilter2: Filter,
+    lowPassFilter: Filter,
+}
+
+impl OutputChannel {
+    fn new(format: &AudioFormat) -> Self {
+        let sampleHertz = format.sampleRate as f32;
+        OutputChannel {
+            blip: BlipBuffer::new(Region::Ntsc.cpuHertz(), format.sampleRate as f64),
+            lastLevel: 0.0,
+            highPassFilter1: Filter::HighPassFilter(sampleHertz, 90 as f32),
+            highPassFilter2: Filter::HighPassFilter(sampleHertz, 440 as f32),
+            lowPassFilter: Filter::LowPassFilter(sampleHertz, 14000 as f32),
+        }
+    }
+
+    fn filter(&mut self, sample: f32) -> f32 {
+        let mut fSample = self.highPassFilter1.Step(sample);
+        fSample = self.highPassFilter2.Step(fSample);
+        fSample = self.lowPassFilter.Step(fSample);
+        return fSample;
+    }
+}
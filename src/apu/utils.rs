@@ -9,6 +9,13 @@ pub const STEP_THREE_CYCLE: u16 = 11186;
 pub const STEP_FOUR_CYCLE: u16 = 14914;
 pub const STEP_FOUR_CYCLE_PLUS_ONE: u16 = 14915;
 pub const STEP_FIVE_CYCLE: u16 = 18641;
+// PAL frame counter steps, same units as the NTSC ones above
+pub const PAL_STEP_ONE_CYCLE: u16 = 4157;
+pub const PAL_STEP_TWO_CYCLE: u16 = 8313;
+pub const PAL_STEP_THREE_CYCLE: u16 = 12470;
+pub const PAL_STEP_FOUR_CYCLE: u16 = 16626;
+pub const PAL_STEP_FIVE_CYCLE: u16 = 20783;
+
 pub const CPU_TICK_TIME: f64 = 1.0 / 1789773.0;
 pub const CPU_FREQ: f64 = 1789773.0;
 pub const AUDIO_HERTZ: u16 = 44100;
@@ -45,4 +52,12 @@ lazy_static! {
 	pub static ref DMC_RATE_TABLE: Array1<u16> = array![
 		428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54
 	];
+
+	pub static ref PAL_NOISE_TIMER_TABLE: Array1<u16> = array![
+		4, 8, 14, 30, 60, 88, 118, 148, 188, 236, 354, 472, 708, 944, 1890, 3778
+	];
+
+	pub static ref PAL_DMC_RATE_TABLE: Array1<u16> = array![
+		398, 354, 316, 298, 276, 236, 210, 198, 176, 148, 132, 118, 98, 78, 66, 50
+	];
 }
@@ -0,0 +1,130 @@
+#![allow(non_snake_case)]
+#![allow(warnings)]
+
+use crate::save_load::VausData;
+
+// The knob's usable travel. Arkanoid reads roughly this range from one end stop to the other.
+pub const POSITION_MIN: u8 = 0x62;
+pub const POSITION_MAX: u8 = 0xF2;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum VausKind {
+    // NES version in port 2: fire button on D3, knob on D4
+    Nes,
+    // Famicom version on the expansion port: fire button on D1 of $4016, knob on D1 of $4017
+    Famicom,
+}
+
+// The Vaus paddle that came with Arkanoid. Strobing $4016 latches the knob's potentiometer into a
+// shift register, which is then read out inverted, most significant bit first.
+pub struct Vaus {
+    kind: VausKind,
+    position: u8,
+    fire: bool,
+    shift: u8,
+    strobe: bool,
+}
+
+impl Vaus {
+    pub fn new(kind: VausKind) -> Self {
+        Vaus {
+            kind,
+            position: ((POSITION_MIN as u16 + POSITION_MAX as u16) / 2) as u8,
+            fire: false,
+            shift: 0,
+            strobe: false,
+        }
+    }
+
+    pub fn getKind(&self) -> VausKind {
+        return self.kind;
+    }
+
+    pub fn getPosition(&self) -> u8 {
+        return self.position;
+    }
+
+    pub fn setPosition(&mut self, position: i32) -> () {
+        self.position = position.clamp(POSITION_MIN as i32, POSITION_MAX as i32) as u8;
+    }
+
+    // turns the knob by `delta`, positive to the right
+    pub fn turn(&mut self, delta: i32) -> () {
+        self.setPosition(self.position as i32 + delta);
+    }
+
+    pub fn setFire(&mut self, pressed: bool) -> () {
+        self.fire = pressed;
+    }
+
+    pub fn saveState(&self) -> VausData {
+        VausData {
+            shift: self.shift,
+            strobe: self.strobe,
+        }
+    }
+
+    pub fn loadState(&mut self, data: &VausData) -> () {
+        self.shift = data.shift;
+        self.strobe = data.strobe;
+    }
+
+    pub fn writeState(&mut self, val: u8) -> () {
+        self.strobe = val & 1 == 1;
+        if self.strobe {
+            self.shift = self.position;
+        }
+    }
+
+    // `port` is 0 for $4016 and 1 for $4017. Only the bits the paddle drives are returned.
+    pub fn getState(&mut self, port: u8) -> u8 {
+        if self.strobe {
+            self.shift = self.position;
+        }
+
+        let fire = self.fire as u8;
+        return match (self.kind, port & 1) {
+            (VausKind::Nes, 0) => 0,
+            (VausKind::Nes, _) => fire << 3 | self.shiftOut() << 4,
+            (VausKind::Famicom, 0) => fire << 1,
+            (VausKind::Famicom, _) => self.shiftOut() << 1,
+        };
+    }
+
+    fn shiftOut(&mut self) -> u8 {
+        let bit = !self.shift >> 7 & 1;
+        if !self.strobe {
+            self.shift <<= 1;
+        }
+        return bit;
+    }
+}
+
+#[cfg(test)]
+mod VausSpec {
+    use super::*;
+
+    #[test]
+    fn sendsInvertedPosition() -> () {
+        let mut vaus = Vaus::new(VausKind::Nes);
+        vaus.setPosition(0xA5);
+        vaus.setFire(true);
+        vaus.writeState(1);
+        vaus.writeState(0);
+
+        let bits: Vec<u8> = (0..8).map(|_| vaus.getState(1)).collect();
+        assert!(bits.iter().all(|bits| bits & 0x08 != 0));
+        let read = bits.iter().fold(0u8, |acc, bits| acc << 1 | (bits >> 4 & 1));
+        assert_eq!(read, !0xA5);
+        assert_eq!(vaus.getState(0), 0);
+    }
+
+    #[test]
+    fn staysWithinTravel() -> () {
+        let mut vaus = Vaus::new(VausKind::Famicom);
+        vaus.turn(-1000);
+        assert_eq!(vaus.getPosition(), POSITION_MIN);
+        vaus.turn(1000);
+        assert_eq!(vaus.getPosition(), POSITION_MAX);
+    }
+}
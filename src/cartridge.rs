@@ -1,143 +1,149 @@
 #![allow(non_snake_case)]
 #![allow(warnings)]
 
+use std::error::Error;
 use std::path::{PathBuf, Path};
-use std::fs;
-use std::mem;
-use std::fs::File;
-use std::io::{BufReader, Read};
-use std::ptr::{slice_from_raw_parts, slice_from_raw_parts_mut};
+use std::{fmt, fs, io};
 use crate::mappers::mapper0::Mapper0;
 use crate::mappers::mapper::{Mapper, MirrorType};
-use crate::mappers::mapper::MirrorType::{Vertical, Horizontal};
 use crate::mappers::mapper_one::Mapper1;
 use crate::mappers::mapper2::Mapper2;
 use crate::mappers::mapper3::Mapper3;
 use crate::mappers::mapper_four::Mapper4;
-use crate::save_load::{CartData, CartHeaderData, MapperData};
+use crate::rom_header::{RomHeader, CHR_BANK_SIZE, HEADER_SIZE, PRG_BANK_SIZE};
+use crate::save_load::{CartData, MapperData};
+use md5::{Digest, Md5};
 
 const PRG_RAM_START: u16 = 0x6000;
 const PRG_RAM_END: u16 = 0x7FFF;
 
-enum FileType {
-    AiNES,  // Archaic iNES
-    iNES,
-    NES2
+const TRAINER_SIZE: usize = 512;
+
+#[derive(Debug)]
+pub enum CartridgeError {
+    Io(io::Error),
+    BadMagic,
+    TruncatedHeader,
+    TruncatedPrg { expected: usize, found: usize },
+    TruncatedChr { expected: usize, found: usize },
+    UnsupportedMapper(u16),
+    UnsupportedFormat(&'static str),
+    BatterySave(io::Error),     // the outgoing game's .sav couldn't be written, so it's still loaded
 }
 
-#[repr(C, packed)]
-#[derive(Debug, Copy, Clone)]
-struct Header {
-    name: [u8; 4],
-    prgSize: u8,
-    chrSize: u8,
-    mapper1: u8,
-    mapper2: u8,
-    prgRam: u8,
-    tvSys1: u8,
-    tvSys2: u8,
-    unused: [u8; 5],
+impl fmt::Display for CartridgeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CartridgeError::Io(err) => write!(f, "Could not read ROM: {}", err),
+            CartridgeError::BadMagic => write!(f, "Not an NES ROM (missing iNES header)"),
+            CartridgeError::TruncatedHeader => write!(f, "ROM header is truncated"),
+            CartridgeError::TruncatedPrg { expected, found } => {
+                write!(f, "PRG ROM is truncated: expected {} bytes, found {}", expected, found)
+            }
+            CartridgeError::TruncatedChr { expected, found } => {
+                write!(f, "CHR ROM is truncated: expected {} bytes, found {}", expected, found)
+            }
+            CartridgeError::UnsupportedMapper(id) => write!(f, "Mapper {} is not supported", id),
+            CartridgeError::UnsupportedFormat(format) => write!(f, "Unsupported ROM format: {}", format),
+            CartridgeError::BatterySave(err) => write!(f, "Could not write the current game's battery save: {}", err),
+        }
+    }
+}
+
+impl Error for CartridgeError {}
+
+impl From<io::Error> for CartridgeError {
+    fn from(err: io::Error) -> Self {
+        CartridgeError::Io(err)
+    }
 }
 
 pub struct Cartridge {
-    header: Header,
-    mapperId: u8,
-    numPrgBanks: u8,
-    numChrBanks: u8,
     vPrgMem: Vec<u8>,
     vChrMem: Vec<u8>,
     pMapper: Box<dyn Mapper>,
-    hasPrgRam: bool,
+    romHeader: RomHeader,
+    romHash: u32,
+    romMd5: [u8; 16],
 }
 
 impl Cartridge {
-    pub fn new(romPath: &Path) -> Self {
-        let mut fileBuf = fs::read(romPath).unwrap();
-
-        // load header using C-like memory management
-        let mut header: Header = unsafe { mem::zeroed() };
-        let headerSize = mem::size_of::<Header>();
-
-        unsafe {
-            let headerSlice = slice_from_raw_parts_mut(&mut header as *mut _ as *mut u8, headerSize).as_mut().unwrap();
-            fileBuf.as_slice().read_exact(headerSlice);
-        }
+    pub fn fromPath(romPath: &Path) -> Result<Self, CartridgeError> {
+        let fileBuf = fs::read(romPath)?;
+        return Cartridge::fromBytes(fileBuf.as_slice());
+    }
 
-        // get the rest of the cartridge details
-        let mut fIter = fileBuf.iter();
-        for _ in 0..headerSize { fIter.next(); }
+    pub fn fromBytes(fileBuf: &[u8]) -> Result<Self, CartridgeError> {
+        let romHeader = RomHeader::fromBytes(fileBuf)?;
 
         // don't care about trainer data
-        if header.mapper1 & 0x04 == 0x04 {
-            for _ in 0..512 { fIter.next(); }
+        let mut offset = HEADER_SIZE;
+        if romHeader.hasTrainer {
+            offset += TRAINER_SIZE;
         }
 
-        let mapperId = (header.mapper2 & 0b11110000) | (header.mapper1 >> 4);
-
-
-        let mut numPrgBanks: u8 = 0;
-        let mut numChrBanks: u8 = 0;
-        let mut prgMem: Vec<u8> = vec![0];
-        let mut chrMem: Vec<u8> = vec![0];
-
-        let mut fileType: FileType = FileType::AiNES;
-        if header.mapper2 & 0xC == 0x8 { fileType = FileType::NES2; }
-        if header.mapper2 & 0xC == 0x0 && !header.unused.iter().any(|el| *el != 0) { fileType = FileType::iNES; }
-
-        match fileType {
-            FileType::AiNES => {
-
+        let prgLen = romHeader.prgRomSize;
+        let prgMem = match offset.checked_add(prgLen).and_then(|end| fileBuf.get(offset..end)) {
+            Some(prg) => prg.to_vec(),
+            None => {
+                return Err(CartridgeError::TruncatedPrg { expected: prgLen, found: fileBuf.len().saturating_sub(offset) });
             }
-            FileType::iNES => {
-                numPrgBanks = header.prgSize;
-                numChrBanks = header.chrSize;
-
-                prgMem.resize(numPrgBanks as usize * 0x4000, 0);
-                for i in 0..prgMem.len() { prgMem[i] = *fIter.next().unwrap(); }
-
-                if numChrBanks == 0 {
-                    chrMem.resize(0x2000, 0);
-                }
-                else {
-                    chrMem.resize(numChrBanks as usize * 0x2000, 0);
-                }
+        };
+        offset += prgLen;
 
-                let mut idx: usize = 0;
-                while let Some(el) = fIter.next() {
-                    chrMem[idx] = *el;
-                    idx += 1;
+        let chrMem = if romHeader.chrRomSize == 0 {
+            // CHR RAM, assume the usual 8K if the header doesn't say
+            let chrRamSize = romHeader.chrRamSize + romHeader.chrNvramSize;
+            vec![0; if chrRamSize == 0 { 0x2000 } else { chrRamSize }]
+        }
+        else {
+            let chrLen = romHeader.chrRomSize;
+            match offset.checked_add(chrLen).and_then(|end| fileBuf.get(offset..end)) {
+                Some(chr) => chr.to_vec(),
+                None => {
+                    return Err(CartridgeError::TruncatedChr { expected: chrLen, found: fileBuf.len().saturating_sub(offset) });
                 }
             }
-            FileType::NES2 => {
-
-            }
-        }
+        };
 
-        let mut mapper: Option<Box<dyn Mapper>> = None;
-        let mirror: MirrorType = if header.mapper1 & 0x01 == 1 { Vertical } else { Horizontal };
+        let mapper: Box<dyn Mapper> = match romHeader.mapperId {
+            0 => { Box::new(Mapper0::new(&romHeader)) }
+            1 => { Box::new(Mapper1::new(&romHeader)) }
+            2 => { Box::new(Mapper2::new(&romHeader)) }
+            3 => { Box::new(Mapper3::new(&romHeader)) }
+            4 => { Box::new(Mapper4::new(&romHeader)) }
+            mapperId => { return Err(CartridgeError::UnsupportedMapper(mapperId)); }
+        };
 
-        match mapperId {
-            0 => { mapper = Some(Box::new(Mapper0::new(numPrgBanks, numChrBanks, mirror))) }
-            1 => { mapper = Some(Box::new(Mapper1::new(numPrgBanks, numChrBanks, mirror))) }
-            2 => { mapper = Some(Box::new(Mapper2::new(numPrgBanks, numChrBanks, mirror))) }
-            3 => { mapper = Some(Box::new(Mapper3::new(mirror))) }
-            4 => { mapper = Some(Box::new(Mapper4::new(numPrgBanks, numChrBanks, mirror))) }
-            _ => panic!("Unknown mapper: {}", mapperId)
+        let mut hasher = crc32fast::Hasher::new();
+        hasher.update(&prgMem);
+        if romHeader.chrRomSize > 0 {
+            hasher.update(&chrMem);
         }
+        let romHash = hasher.finalize();
 
-        let hasPrgRam = header.mapper1 & 2 == 2;
-
+        let mut romData = prgMem.clone();
+        if romHeader.chrRomSize > 0 {
+            romData.extend_from_slice(&chrMem);
+        }
+        let romMd5: [u8; 16] = Md5::digest(&romData).into();
+
+        // the mappers work in whole banks, so pad out any partial one left by an NES 2.0 exponent size
+        let mut prgMem = prgMem;
+        prgMem.resize(romHeader.numPrgBanks() * PRG_BANK_SIZE, 0);
+        let mut chrMem = chrMem;
+        if romHeader.chrRomSize > 0 {
+            chrMem.resize(romHeader.numChrBanks() * CHR_BANK_SIZE, 0);
+        }
 
-        return Cartridge {
-            header,
-            mapperId,
-            numChrBanks,
-            numPrgBanks,
+        return Ok(Cartridge {
             vPrgMem: prgMem,
             vChrMem: chrMem,
-            pMapper: mapper.unwrap(),
-            hasPrgRam,
-        };
+            pMapper: mapper,
+            romHeader,
+            romHash,
+            romMd5,
+        });
     }
 
     #[inline]
@@ -204,47 +210,56 @@ impl Cartridge {
         return false;
     }
 
+    pub fn hasBattery(&self) -> bool {
+        return self.romHeader.hasBattery;
+    }
+
+    pub fn getBatteryRam(&self) -> Option<&Vec<u8>> {
+        if !self.hasBattery() {
+            return None;
+        }
+        return self.pMapper.getPrgRam();
+    }
+
+    pub fn loadBatteryRam(&mut self, data: &[u8]) -> () {
+        if self.hasBattery() {
+            self.pMapper.loadPrgRam(data);
+        }
+    }
+
+    pub fn getRomHeader(&self) -> &RomHeader {
+        return &self.romHeader;
+    }
+
     pub fn getMirrorType(&self) -> MirrorType {
         return self.pMapper.getMirrorType();
     }
     
     pub fn saveState(&self) -> CartData {
-        CartData{
-            header: CartHeaderData {
-                name: self.header.name,
-                prgSize: self.header.prgSize,
-                chrSize: self.header.chrSize,
-                mapper1: self.header.mapper1,
-                mapper2: self.header.mapper2,
-                prgRam: self.header.prgRam,
-                tvSys1: self.header.tvSys1,
-                tvSys2: self.header.tvSys2,
-                unused: self.header.unused
-            },
-            mapperId: self.mapperId,
-            numPrgBanks: self.numPrgBanks,
-            numChrBanks: self.numChrBanks,
-            vChrMem: self.vChrMem.clone(),
-            hasPrgRam: self.hasPrgRam
+        CartData {
+            vChrMem: if self.hasChrRam() { Some(self.vChrMem.clone()) } else { None },
         }
     }
-    
+
     pub fn loadState(&mut self, data: &CartData) -> () {
-        self.header.name = data.header.name;
-        self.header.prgSize = data.header.prgSize;
-        self.header.chrSize = data.header.chrSize;
-        self.header.mapper1 = data.header.mapper1;
-        self.header.mapper2 = data.header.mapper2;
-        self.header.prgRam = data.header.prgRam;
-        self.header.tvSys1 = data.header.tvSys1;
-        self.header.tvSys2 = data.header.tvSys2;
-        self.header.unused = data.header.unused;
-
-        self.mapperId = data.mapperId;
-        self.numPrgBanks = data.numPrgBanks;
-        self.numChrBanks = data.numChrBanks;
-        self.vChrMem = data.vChrMem.clone();
-        self.hasPrgRam = data.hasPrgRam;
+        if let (true, Some(chrMem)) = (self.hasChrRam(), &data.vChrMem) {
+            let len = chrMem.len().min(self.vChrMem.len());
+            self.vChrMem[..len].copy_from_slice(&chrMem[..len]);
+        }
+    }
+
+    fn hasChrRam(&self) -> bool {
+        return self.romHeader.chrRomSize == 0;
+    }
+
+    // crc32 of the PRG and CHR ROM, used to tie save states to the game they came from
+    pub fn getRomHash(&self) -> u32 {
+        return self.romHash;
+    }
+
+    // MD5 of the same data, which is how FCEUX identifies a game in its movie files
+    pub fn getRomMd5(&self) -> [u8; 16] {
+        return self.romMd5;
     }
 
     pub fn saveMapperState(&self) -> MapperData {
@@ -254,4 +269,51 @@ impl Cartridge {
     pub fn loadMapperState(&mut self, data: &MapperData) -> () {
         self.pMapper.loadState(data);
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod CartridgeSpec {
+    use super::*;
+
+    fn makeRom(prgBanks: u8, chrBanks: u8, mapper: u8) -> Vec<u8> {
+        let mut rom = vec![0x4E, 0x45, 0x53, 0x1A, prgBanks, chrBanks, (mapper & 0x0F) << 4, mapper & 0xF0];
+        rom.resize(HEADER_SIZE, 0);
+        rom.resize(HEADER_SIZE + prgBanks as usize * 16384 + chrBanks as usize * 8192, 0);
+        return rom;
+    }
+
+    #[test]
+    fn happyPath() -> () {
+        assert!(Cartridge::fromBytes(&makeRom(1, 1, 0)).is_ok());
+    }
+
+    #[test]
+    fn badMagic() -> () {
+        let mut rom = makeRom(1, 1, 0);
+        rom[0] = 0;
+        assert!(matches!(Cartridge::fromBytes(&rom), Err(CartridgeError::BadMagic)));
+    }
+
+    #[test]
+    fn truncatedPrg() -> () {
+        let rom = makeRom(2, 0, 0);
+        assert!(matches!(Cartridge::fromBytes(&rom[..HEADER_SIZE + 100]), Err(CartridgeError::TruncatedPrg { .. })));
+    }
+
+    #[test]
+    fn batteryRam() -> () {
+        let mut rom = makeRom(2, 1, 1);
+        rom[6] |= 0x02;
+        let mut cart = Cartridge::fromBytes(&rom).unwrap();
+        cart.loadBatteryRam(&[1, 2, 3]);
+        assert_eq!(cart.getBatteryRam().unwrap()[..3], [1, 2, 3]);
+
+        let cart = Cartridge::fromBytes(&makeRom(2, 1, 1)).unwrap();
+        assert!(cart.getBatteryRam().is_none());
+    }
+
+    #[test]
+    fn unsupportedMapper() -> () {
+        assert!(matches!(Cartridge::fromBytes(&makeRom(1, 1, 5)), Err(CartridgeError::UnsupportedMapper(5))));
+    }
+}
@@ -4,19 +4,20 @@
 
 extern crate sdl2;
 
-use crate::cpu::Cpu;
-use crate::ppu::Ppu;
-use crate::cartridge::Cartridge;
-use crate::data_bus::DataBus;
-use crate::clock::Clocked;
 use std::rc::Rc;
 use std::cell::RefCell;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
 use sdl2::AudioSubsystem;
-use crate::ppu_bus::PpuBus;
-use crate::controller::Controller;
-use crate::apu::Apu;
-use crate::save_load::SaveState;
+use crate::apu::audio::SdlAudioSink;
+use crate::apu::audio_sink::AudioFormat;
+use crate::frame_pacer::{audioRateAdjust, FramePacer};
+use crate::movie::Movie;
+use crate::nes::Nes;
+use crate::region::Region;
+use crate::input_setup::InputSetup;
+use crate::controller::InputProvider;
+use crate::keyboard_input::KeyboardInput;
 use pixels::{Pixels, SurfaceTexture};
 use rfd::FileDialog;
 use winit::dpi::LogicalSize;
@@ -29,9 +30,9 @@ use winit::window::{Window, WindowBuilder};
 use winit_input_helper::WinitInputHelper;
 use crate::gui::Gui;
 use crate::gui_commands::GuiCommands;
-
-const CPU_HERTZ_PER_CYCLE: f64 = 1.0 / 1789773.0;
-const AUDIO_HERTZ_PER_SAMPLE: f64 = 1.0 / 44100.0;
+use crate::state_slots::{StateSlots, NUM_SLOTS};
+use crate::settings::{KeyTarget, Settings};
+use crate::trace_logger::{TraceFilter, TraceLogger};
 
 const SCREEN_WIDTH: u32 = 768;
 const SCREEN_HEIGHT: u32 = 720;
@@ -39,12 +40,29 @@ const SCREEN_HEIGHT: u32 = 720;
 const PIXEL_WIDTH: u32 = 256;
 const PIXEL_HEIGHT: u32 = 240;
 
+// how far the Arkanoid paddle's knob turns per update while a direction key is held
+const PADDLE_KEY_SPEED: i32 = 3;
+
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum GameState {
     NotLoaded,
     Loaded
 }
 
+// Everything the command line can change about how the console runs.
+pub struct ConsoleOptions {
+    pub rom: Option<PathBuf>,
+    pub saveDirectory: Option<PathBuf>,
+    pub region: Region,
+    pub inputSetup: InputSetup,
+    pub audioSync: bool,
+    pub audioFormat: AudioFormat,
+    pub rewindSeconds: f64,
+    pub rewindInterval: u32,
+    pub fastForwardSpeed: f64,
+    pub muteFastForward: bool,
+}
+
 pub struct Console {
     window: Window,
     eventLoop: Option<EventLoop<()>>,
@@ -52,16 +70,26 @@ pub struct Console {
     gui: Gui,
     guiCommands: Rc<RefCell<GuiCommands>>,
     audioSystem: Rc<RefCell<AudioSubsystem>>,
-    cpu: Rc<RefCell<Cpu>>,
-    ppu: Rc<RefCell<Ppu>>,
-    apu: Rc<RefCell<Apu>>,
-    bus: Rc<RefCell<DataBus>>,
-    cartridge: Option<Rc<RefCell<Cartridge>>>,
-    gameState: GameState
+    nes: Nes,
+    keyboard: KeyboardInput,
+    settings: Settings,
+    settingsPath: Option<PathBuf>,
+    gameState: GameState,
+    options: ConsoleOptions,
+    slots: Option<StateSlots>,
+    currentSlot: usize,
+    undoState: Option<Vec<u8>>,
+    romPath: Option<PathBuf>,
+    moviePath: Option<PathBuf>,
+    movieReadOnly: bool,
+    paused: bool,
+    frameAdvance: bool,
+    speedPercent: u32,
+    fastForwardLocked: bool
 }
 
 impl Console {
-    pub fn new(game: Option<&str>) -> Self {
+    pub fn new(options: ConsoleOptions) -> Self {
         let sdl = sdl2::init().unwrap();
         let audioSystem = Rc::new(RefCell::new(sdl.audio().unwrap()));
 
@@ -81,61 +109,386 @@ impl Console {
         let scale = window.scale_factor();
         let texture = SurfaceTexture::new(windowSize.width, windowSize.height, &window);
         let pixels = Pixels::new(PIXEL_WIDTH, PIXEL_HEIGHT, texture).unwrap();
-        let gui = Gui::new(windowSize.width, windowSize.height, scale as f32, guiCommands.clone(), &pixels);
 
 
-        let bus = Rc::new(RefCell::new(DataBus::new()));
-        bus.borrow_mut().attachController1(Rc::new(RefCell::new(Controller::new())));
-        let cpu = Rc::new(RefCell::new(Cpu::new(bus.clone())));
-        bus.borrow_mut().attachCpu(cpu.clone());
-        let apu = Rc::new(RefCell::new(Apu::new(bus.clone(), audioSystem.clone())));
-        bus.borrow_mut().attachApu(apu.clone());
-
-        let mut ppuBus = PpuBus::new();
-        let mut cartridge: Option<Rc<RefCell<Cartridge>>> = None;
-        let mut gameState = GameState::NotLoaded;
-
-        if game.is_some() {
-            cartridge = Some(Rc::new(RefCell::new(Cartridge::new(Path::new(game.unwrap())))));
-            bus.borrow_mut().attachCartridge(cartridge.as_ref().unwrap().clone());
-            ppuBus.attachCartridge(cartridge.as_ref().unwrap().clone());
-            cpu.borrow_mut().init();
-            gameState = GameState::Loaded;
-        }
-
-        let ppu = Rc::new(RefCell::new(Ppu::new(bus.clone(), ppuBus)));
-        bus.borrow_mut().attachPpu(ppu.clone());
-
+        let gui = Gui::new(windowSize.width, windowSize.height, scale as f32, guiCommands.clone(), &pixels);
 
-        Console {
+        let nes = Console::createNes(&audioSystem, &options);
+        let rom = options.rom.clone();
+
+        // a missing file just means nothing has been rebound yet
+        let settingsPath = Settings::defaultPath();
+        let (settings, settingsError) = match &settingsPath {
+            Some(path) => match Settings::load(path) {
+                Ok(settings) => (settings, None),
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => (Settings::default(), None),
+                Err(err) => (Settings::default(), Some(format!("Could not read {}, using the default keys: {}", path.display(), err))),
+            },
+            None => (Settings::default(), Some(String::from("Could not find a config directory, key bindings won't be saved"))),
+        };
+
+        let mut console = Console {
             window,
             eventLoop: Some(eventLoop),
             pixels,
             gui,
             guiCommands,
             audioSystem,
-            cpu,
-            ppu,
-            apu,
-            bus,
-            cartridge,
-            gameState
+            nes,
+            keyboard: KeyboardInput::new(),
+            settings,
+            settingsPath,
+            gameState: GameState::NotLoaded,
+            options,
+            slots: None,
+            currentSlot: 0,
+            undoState: None,
+            romPath: None,
+            moviePath: None,
+            movieReadOnly: true,
+            paused: false,
+            frameAdvance: false,
+            speedPercent: 100,
+            fastForwardLocked: false
+        };
+
+        console.applySettings();
+        if let Some(err) = settingsError {
+            console.gui.showError(err);
+        }
+
+        if let Some(rom) = rom {
+            console.loadGame(&rom);
+        }
+
+        return console;
+    }
+
+    fn loadGame(&mut self, rom: &Path) -> () {
+        self.stopMovie();
+        if let Err(err) = self.nes.loadCartridge(rom) {
+            self.gui.showError(err.to_string());
+            return;
+        }
+
+        let statesRoot = match &self.options.saveDirectory {
+            Some(directory) => directory.join("states"),
+            None => home::home_dir().unwrap_or_default().join(".rustynes").join("states"),
+        };
+        self.slots = Some(StateSlots::new(&statesRoot, rom, self.nes.getRomHash().unwrap()));
+        self.undoState = None;
+        self.romPath = Some(rom.to_path_buf());
+        self.paused = false;
+        // the turbo rate is counted in frames, which depends on the region
+        self.keyboard.setTurboRate(self.settings.turboRate, self.nes.getFrameRate());
+        self.gameState = GameState::Loaded;
+        self.refreshSlots();
+    }
+
+    fn refreshSlots(&mut self) -> () {
+        let infos = (0..NUM_SLOTS)
+            .map(|slot| self.slots.as_ref().and_then(|slots| slots.getInfo(slot)))
+            .collect();
+        self.gui.setSlots(infos, self.currentSlot, self.undoState.is_some());
+    }
+
+    fn saveSlot(&mut self, slot: usize) -> () {
+        let slots = match &self.slots {
+            Some(slots) => slots,
+            None => { return; }
+        };
+
+        let result = self.nes.saveStateToBytes()
+            .map_err(|err| err.to_string())
+            .and_then(|state| slots.save(slot, &state, self.nes.getFrameBuffer()).map_err(|err| err.to_string()));
+
+        match result {
+            Ok(()) => {
+                self.currentSlot = slot;
+                self.refreshSlots();
+            }
+            Err(err) => { self.gui.showError(format!("Could not save slot {}: {}", slot, err)); }
+        }
+    }
+
+    fn loadSlot(&mut self, slot: usize) -> () {
+        let state = match self.slots.as_ref().map(|slots| slots.load(slot)) {
+            Some(Ok(state)) => state,
+            Some(Err(err)) => {
+                self.gui.showError(format!("Could not load slot {}: {}", slot, err));
+                return;
+            }
+            None => { return; }
+        };
+
+        // keep where we were so a mistaken load can be taken back
+        let undoState = self.nes.saveStateToBytes().ok();
+        match self.nes.loadStateFromBytes(&state) {
+            Ok(()) => {
+                self.undoState = undoState;
+                self.currentSlot = slot;
+                self.refreshSlots();
+            }
+            Err(err) => { self.gui.showError(format!("Could not load slot {}: {}", slot, err)); }
+        }
+    }
+
+    fn undoLoad(&mut self) -> () {
+        if let Some(state) = self.undoState.take() {
+            if let Err(err) = self.nes.loadStateFromBytes(&state) {
+                self.gui.showError(err.to_string());
+            }
+            self.refreshSlots();
+        }
+    }
+
+    fn selectSlot(&mut self, slot: usize) -> () {
+        self.currentSlot = slot % NUM_SLOTS;
+        self.refreshSlots();
+    }
+
+    // pushes the key bindings out to the keyboard and the rebinding dialog
+    fn applySettings(&mut self) -> () {
+        for (port, bindings) in self.settings.controllers.iter().enumerate() {
+            self.keyboard.setBindings(port as u8, *bindings);
+        }
+        self.keyboard.setTurboRate(self.settings.turboRate, self.nes.getFrameRate());
+        self.gui.setSettings(&self.settings);
+    }
+
+    fn setKey(&mut self, target: KeyTarget, key: VirtualKeyCode) -> () {
+        self.settings.setKey(target, key);
+        self.saveSettings();
+    }
+
+    fn setTurboRate(&mut self, rate: u32) -> () {
+        self.settings.turboRate = rate;
+        self.saveSettings();
+    }
+
+    fn resetKeys(&mut self) -> () {
+        self.settings = Settings::default();
+        self.saveSettings();
+    }
+
+    fn saveSettings(&mut self) -> () {
+        self.applySettings();
+        let path = match &self.settingsPath {
+            Some(path) => path,
+            None => {
+                self.gui.showError(String::from("Could not save key bindings, there is no config directory"));
+                return;
+            }
+        };
+        if let Err(err) = self.settings.save(path) {
+            self.gui.showError(format!("Could not save {}: {}", path.display(), err));
+        }
+    }
+
+    // save, load, previous/next slot and undo load, F5/F8/F6/F7/F9 unless rebound
+    fn handleSlotHotkeys(&mut self, input: &WinitInputHelper) -> () {
+        let hotkeys = self.settings.hotkeys;
+        if input.key_pressed(hotkeys.saveSlot) {
+            self.saveSlot(self.currentSlot);
+        }
+        if input.key_pressed(hotkeys.loadSlot) {
+            self.loadSlot(self.currentSlot);
+        }
+        if input.key_pressed(hotkeys.previousSlot) {
+            self.selectSlot(self.currentSlot + NUM_SLOTS - 1);
+        }
+        if input.key_pressed(hotkeys.nextSlot) {
+            self.selectSlot(self.currentSlot + 1);
+        }
+        if input.key_pressed(hotkeys.undoLoad) {
+            self.undoLoad();
+        }
+    }
+
+    // pause, frame advance (pausing first) and stepping down through the slow motion speeds, P/\/- unless rebound
+    fn handleSpeedHotkeys(&mut self, input: &WinitInputHelper) -> () {
+        let hotkeys = self.settings.hotkeys;
+        if input.key_pressed(hotkeys.pause) {
+            self.paused = !self.paused;
+        }
+        if input.key_pressed(hotkeys.frameAdvance) {
+            self.advanceFrame();
+        }
+        if input.key_pressed(hotkeys.slowMotion) {
+            self.speedPercent = match self.speedPercent {
+                100 => 50,
+                50 => 25,
+                _ => 100,
+            };
+        }
+    }
+
+    // The Zapper follows the mouse, with the left button as its trigger.
+    fn updateZapper(&mut self, input: &WinitInputHelper) -> () {
+        if self.nes.getInputSetup() != InputSetup::Zapper {
+            return;
+        }
+
+        let overGui = self.gui.wantsPointer();
+        let aim = input.mouse()
+            .filter(|_| !overGui)
+            .and_then(|pos| self.pixels.window_pos_to_pixel(pos).ok())
+            .map(|(x, y)| (x as u16, y as u16));
+        let trigger = !overGui && input.mouse_held(0);
+
+        self.nes.setZapper(aim, trigger);
+    }
+
+    // The Arkanoid paddle's knob follows sideways mouse movement, or player 1's left and right.
+    // Either the left mouse button or player 1's A fires.
+    fn updatePaddle(&mut self, input: &WinitInputHelper) -> () {
+        let setup = self.nes.getInputSetup();
+        if setup != InputSetup::Arkanoid && setup != InputSetup::FamicomArkanoid {
+            return;
+        }
+
+        let overGui = self.gui.wantsPointer();
+        let toNesX = |pos: (f32, f32)| match self.pixels.window_pos_to_pixel(pos) {
+            Ok((x, _)) => x as isize,
+            Err((x, _)) => x,
+        };
+        let mouseTurn = match input.mouse() {
+            Some((x, y)) if !overGui => {
+                let (dx, dy) = input.mouse_diff();
+                (toNesX((x, y)) - toNesX((x - dx, y - dy))) as i32
+            }
+            _ => 0,
+        };
+
+        let buttons = self.keyboard.getButtons(0);
+        let keyTurn = (buttons.right as i32 - buttons.left as i32) * PADDLE_KEY_SPEED;
+        let fire = buttons.a || (!overGui && input.mouse_held(0));
+
+        self.nes.setPaddle(mouseTurn + keyTurn, fire);
+    }
+
+    fn advanceFrame(&mut self) -> () {
+        self.paused = true;
+        self.frameAdvance = true;
+    }
+
+    fn createNes(audioSystem: &Rc<RefCell<AudioSubsystem>>, options: &ConsoleOptions) -> Nes {
+        let mut nes = Nes::new(Box::new(SdlAudioSink::new(audioSystem.clone(), options.audioFormat)));
+        nes.setSaveDirectory(options.saveDirectory.clone());
+        nes.setRegion(options.region);
+        nes.setInputSetup(options.inputSetup);
+        nes.setRewindInterval(options.rewindInterval);
+        nes.setRewindSeconds(options.rewindSeconds);
+        return nes;
+    }
+
+    fn recordMovie(&mut self, fromPowerOn: bool) -> () {
+        let path = FileDialog::new()
+            .add_filter("movie", &["fm2"])
+            .set_directory(home::home_dir().unwrap())
+            .save_file();
+
+        let path = match path {
+            Some(path) => path,
+            None => { return; }
+        };
+
+        self.stopMovie();
+        let romFilename = self.romPath.as_ref()
+            .and_then(|rom| rom.file_stem())
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_default();
+
+        match self.nes.recordMovie(romFilename, fromPowerOn) {
+            Ok(()) => { self.moviePath = Some(path); }
+            Err(err) => { self.gui.showError(err.to_string()); }
+        }
+    }
+
+    fn playMovie(&mut self) -> () {
+        let path = FileDialog::new()
+            .add_filter("movie", &["fm2"])
+            .set_directory(home::home_dir().unwrap())
+            .pick_file();
+
+        let path = match path {
+            Some(path) => path,
+            None => { return; }
+        };
+
+        self.stopMovie();
+        let result = Movie::load(&path).and_then(|mut movie| {
+            movie.setReadOnly(self.movieReadOnly);
+            self.nes.playMovie(movie)
+        });
+
+        match result {
+            Ok(()) => { self.moviePath = Some(path); }
+            Err(err) => { self.gui.showError(err.to_string()); }
+        }
+    }
+
+    // writes the movie out if anything was recorded into it
+    fn stopMovie(&mut self) -> () {
+        let movie = self.nes.stopMovie();
+        let path = self.moviePath.take();
+        if let (Some(mut movie), Some(path)) = (movie, path) {
+            if movie.isModified() {
+                if let Err(err) = movie.save(&path) {
+                    self.gui.showError(err.to_string());
+                }
+            }
+        }
+    }
+
+    fn startTrace(&mut self, filter: TraceFilter) -> () {
+        let path = FileDialog::new()
+            .add_filter("trace log", &["log", "txt"])
+            .set_directory(home::home_dir().unwrap())
+            .save_file();
+
+        let path = match path {
+            Some(path) => path,
+            None => { return; }
+        };
+
+        let result = TraceLogger::create(&path, filter).and_then(|tracer| self.nes.startTrace(tracer));
+        if let Err(err) = result {
+            self.gui.showError(err.to_string());
+        }
+    }
+
+    fn stopTrace(&mut self) -> () {
+        if let Err(err) = self.nes.stopTrace() {
+            self.gui.showError(err.to_string());
+        }
+    }
+
+    fn setMovieReadOnly(&mut self, readOnly: bool) -> () {
+        self.movieReadOnly = readOnly;
+        if let Some(movie) = self.nes.getMovieMut() {
+            movie.setReadOnly(readOnly);
         }
     }
 
     fn returnToSplashScreen(&mut self) -> () {
-        self.bus = Rc::new(RefCell::new(DataBus::new()));
-        self.bus.borrow_mut().attachController1(Rc::new(RefCell::new(Controller::new())));
-        self.cpu = Rc::new(RefCell::new(Cpu::new(self.bus.clone())));
-        self.bus.borrow_mut().attachCpu(self.cpu.clone());
-        self.apu = Rc::new(RefCell::new(Apu::new(self.bus.clone(), self.audioSystem.clone())));
-        self.bus.borrow_mut().attachApu(self.apu.clone());
-        
-        let ppuBus = PpuBus::new();
-        self.ppu = Rc::new(RefCell::new(Ppu::new(self.bus.clone(), ppuBus)));
-        self.bus.borrow_mut().attachPpu(self.ppu.clone());
-        
+        self.stopMovie();
+        self.stopTrace();
+        self.gui.setMovieStatus(None, self.movieReadOnly);
+        self.flushBatteryRam();
+        self.nes = Console::createNes(&self.audioSystem, &self.options);
+        // the new console has no debugger
+        self.gui.hideDebugger();
         self.gameState = GameState::NotLoaded;
+        self.slots = None;
+        self.undoState = None;
+        self.refreshSlots();
+    }
+
+    fn flushBatteryRam(&mut self) -> () {
+        if let Err(err) = self.nes.flushBatteryRam() {
+            self.gui.showError(format!("Could not write battery save: {}", err));
+        }
     }
 
     fn copyBufferToPixels(&mut self, buffer: &Vec<u8>) -> () {
@@ -151,7 +504,6 @@ impl Console {
 
     pub fn run(mut self) {
 
-        let mut audioTime: f64 = 0.0;
         let mut canPressEscape: bool = true;
 
         let mut input = WinitInputHelper::new();
@@ -159,6 +511,7 @@ impl Console {
         let imgBytes = image::load_from_memory(img).unwrap().to_rgb8().into_raw();
 
         let mut pixelBuffer: Vec<u8> = vec![0; 256 * 240 * 3];
+        let mut pacer = FramePacer::new(self.nes.getFrameRate());
 
         if self.gameState == GameState::NotLoaded {
             pixelBuffer = imgBytes.clone();
@@ -172,36 +525,91 @@ impl Console {
                 match *state {
                     GameState::NotLoaded => {
 
-                        if canPressEscape && input.key_pressed(VirtualKeyCode::Escape) {
+                        if canPressEscape && !self.gui.isCapturingKey() && input.key_pressed(VirtualKeyCode::Escape) {
                             canPressEscape = false;
                             *controlFlow = ControlFlow::Exit;
                         }
 
                         self.window.request_redraw();
+                        if *controlFlow != ControlFlow::Exit {
+                            *controlFlow = ControlFlow::Wait;
+                        }
                     }
                     GameState::Loaded => {
                         
-                        for _ in 0..29781 {
-                            for _ in 0..3 {
-                                if let Some(buffer) = self.ppu.borrow_mut().cycleAndPrepareTexture().cloned() {
-                                    pixelBuffer = buffer;
+                        // the key being bound in the rebinding dialog shouldn't also reach the game
+                        let capturing = self.gui.isCapturingKey();
+                        if !capturing {
+                            self.keyboard.update(&input);
+                        }
+                        self.updateZapper(&input);
+                        self.updatePaddle(&input);
+                        if !capturing {
+                            self.handleSlotHotkeys(&input);
+                            self.handleSpeedHotkeys(&input);
+                        }
+                        let hotkeys = self.settings.hotkeys;
+                        let rewinding = !capturing && input.key_held(hotkeys.rewind);
+
+                        let fastForward = self.fastForwardLocked || (!capturing && input.key_held(hotkeys.fastForward));
+                        let speed = if fastForward { self.options.fastForwardSpeed } else { self.speedPercent as f64 / 100.0 };
+                        pacer.setSpeed(speed);
+                        self.nes.setAudioSpeed(speed);
+                        self.nes.setAudioMuted(fastForward && self.options.muteFastForward);
+                        self.gui.setEmulationState(self.paused, self.speedPercent, fastForward);
+                        self.gui.setInputSetup(self.nes.getInputSetup());
+
+                        // while paused the pacer is kept at now so resuming doesn't try to catch up
+                        let framesDue = if self.paused {
+                            pacer.reset(Instant::now());
+                            std::mem::take(&mut self.frameAdvance) as u32
+                        }
+                        else {
+                            pacer.framesDue(Instant::now())
+                        };
+
+                        for _ in 0..framesDue {
+                            // hold on the oldest frame once the history runs out
+                            if rewinding {
+                                if let Some(frame) = self.nes.rewindFrame() {
+                                    pixelBuffer = frame;
                                 }
+                                continue;
                             }
-    
-                            self.cpu.borrow_mut().cycle();
-                            self.apu.borrow_mut().cycle();
-    
-                            audioTime += CPU_HERTZ_PER_CYCLE;
-                            if audioTime >= AUDIO_HERTZ_PER_SAMPLE {
-                                audioTime -= AUDIO_HERTZ_PER_SAMPLE;
-                                self.apu.borrow_mut().addSampleToBuffer();
+
+                            // buttons go in per frame so turbo toggles at the same rate whatever the speed
+                            self.keyboard.nextFrame();
+                            for port in 0..4 {
+                                self.nes.setButtons(port, self.keyboard.getButtons(port));
+                            }
+
+                            if let (true, Some(queued)) = (self.options.audioSync, self.nes.queuedAudioSamples()) {
+                                let format = self.options.audioFormat;
+                                let samplesPerFrame = (format.sampleRate * format.channels.count() as u32) as f64 / self.nes.getFrameRate();
+                                self.nes.setAudioRateAdjust(audioRateAdjust(queued, samplesPerFrame));
+                            }
+
+                            let (frame, _) = self.nes.runFrame();
+                            pixelBuffer = frame;
+                            if let Some(err) = self.nes.takeMovieError() {
+                                self.gui.showError(err.to_string());
+                                self.stopMovie();
+                            }
+                            if let Some(err) = self.nes.takeBatteryError() {
+                                self.gui.showError(format!("Could not write battery save: {}", err));
                             }
                         }
 
-                        self.bus.borrow_mut().setControllerEvents(input.clone());
-                        self.bus.borrow_mut().getControllerInput();
+                        let movieStatus = self.nes.getMovie().map(|movie| movie.getStatus());
+                        self.gui.setMovieStatus(movieStatus, self.movieReadOnly);
+                        self.gui.setDebugView(self.nes.getDebugView());
+                        self.gui.setTraceStatus(self.nes.getTraceLineCount());
+
+                        if *controlFlow != ControlFlow::Exit {
+                            *controlFlow = if self.paused { ControlFlow::Wait } else { ControlFlow::WaitUntil(pacer.getNextFrame()) };
+                        }
 
-                        if canPressEscape && input.key_pressed(VirtualKeyCode::Escape) {
+                        if canPressEscape && !capturing && input.key_pressed(VirtualKeyCode::Escape) {
                             canPressEscape = false;
                             pixelBuffer = imgBytes.clone();
                             self.copyBufferToPixels(&pixelBuffer);
@@ -220,7 +628,8 @@ impl Console {
             match event {
                 Event::WindowEvent { event, .. } => {
                     self.gui.handleEvent(&event);
-                    match *self.guiCommands.borrow() {
+                    let command = *self.guiCommands.borrow();
+                    match command {
                         GuiCommands::Default => {}
                         GuiCommands::LoadGame => {
 
@@ -229,41 +638,75 @@ impl Console {
                                 .set_directory(home::home_dir().unwrap())
                                 .pick_file();
 
-                            let cartridge = Rc::new(RefCell::new(Cartridge::new(rom.unwrap().as_path())));
-                            self.bus.borrow_mut().attachCartridge(cartridge.clone());
-                            self.ppu.borrow_mut().attachCartridge(cartridge.clone());
-                            self.cpu.borrow_mut().init();
-                            self.gameState = GameState::Loaded;
+                            if let Some(rom) = rom {
+                                self.loadGame(&rom);
+                                pacer.setFrameRate(self.nes.getFrameRate());
+                                pacer.reset(Instant::now());
+                            }
                         }
                         GuiCommands::SaveState => {
 
                             let path = FileDialog::new()
-                                .add_filter("json", &["json"])
+                                .add_filter("state", &["state"])
                                 .set_directory(home::home_dir().unwrap())
-                                .save_file().unwrap();
-
-                            SaveState::save(
-                                path,
-                                self.cpu.clone(),
-                                self.ppu.clone(),
-                                self.apu.clone(),
-                                self.cartridge.as_ref().unwrap().clone()
-                            );
+                                .save_file();
+
+                            if let (Some(path), true) = (path, self.nes.isCartridgeLoaded()) {
+                                if let Err(err) = self.nes.saveState(path) {
+                                    self.gui.showError(err.to_string());
+                                }
+                            }
                         }
                         GuiCommands::LoadState => {
 
                             let path = FileDialog::new()
-                                .add_filter("json", &["json"])
+                                .add_filter("state", &["state", "json"])
                                 .set_directory(home::home_dir().unwrap())
-                                .pick_file().unwrap();
-
-                            SaveState::load(
-                                path,
-                                self.cpu.clone(),
-                                self.ppu.clone(),
-                                self.apu.clone(),
-                                self.cartridge.as_ref().unwrap().clone()
-                            )
+                                .pick_file();
+
+                            if let (Some(path), true) = (path, self.nes.isCartridgeLoaded()) {
+                                if let Err(err) = self.nes.loadState(path) {
+                                    self.gui.showError(err.to_string());
+                                }
+                            }
+                        }
+                        GuiCommands::SaveSlot(slot) => { self.saveSlot(slot); }
+                        GuiCommands::LoadSlot(slot) => { self.loadSlot(slot); }
+                        GuiCommands::UndoLoad => { self.undoLoad(); }
+                        GuiCommands::RecordMovie(fromPowerOn) => {
+                            if self.nes.isCartridgeLoaded() {
+                                self.recordMovie(fromPowerOn);
+                                pacer.reset(Instant::now());
+                            }
+                        }
+                        GuiCommands::PlayMovie => {
+                            if self.nes.isCartridgeLoaded() {
+                                self.playMovie();
+                                pacer.reset(Instant::now());
+                            }
+                        }
+                        GuiCommands::StopMovie => { self.stopMovie(); }
+                        GuiCommands::SetMovieReadOnly(readOnly) => { self.setMovieReadOnly(readOnly); }
+                        GuiCommands::TogglePause => { self.paused = !self.paused; }
+                        GuiCommands::FrameAdvance => { self.advanceFrame(); }
+                        GuiCommands::SetFastForward(fastForward) => { self.fastForwardLocked = fastForward; }
+                        GuiCommands::SetSpeed(percent) => { self.speedPercent = percent; }
+                        GuiCommands::SetInputSetup(setup) => { self.nes.setInputSetup(setup); }
+                        GuiCommands::SetKey(target, key) => { self.setKey(target, key); }
+                        GuiCommands::ResetKeys => { self.resetKeys(); }
+                        GuiCommands::SetTurboRate(rate) => { self.setTurboRate(rate); }
+                        GuiCommands::ShowDebugger(visible) => {
+                            self.nes.setDebuggerEnabled(visible);
+                            self.gui.setDebugView(self.nes.getDebugView());
+                        }
+                        GuiCommands::Debug(command) => { self.nes.debug(command); }
+                        GuiCommands::StartTrace(filter) => {
+                            self.startTrace(filter);
+                            pacer.reset(Instant::now());
+                        }
+                        GuiCommands::StopTrace => { self.stopTrace(); }
+                        GuiCommands::Quit => {
+                            *controlFlow = ControlFlow::Exit;
                         }
                     }
                     *self.guiCommands.borrow_mut() = GuiCommands::Default;
@@ -288,6 +731,11 @@ impl Console {
                         return Ok(());
                     });
                 }
+                Event::LoopDestroyed => {
+                    self.stopMovie();
+                    self.stopTrace();
+                    self.flushBatteryRam();
+                }
                 _ => {}
             }
 
@@ -1,32 +1,79 @@
 #![allow(non_snake_case)]
 #![allow(warnings)]
 
-use std::rc::Rc;
-use std::cell::RefCell;
-use sdl2::EventPump;
 use crate::clock::Clocked;
-use std::process::exit;
-use sdl2::keyboard::Keycode;
-use sdl2::event::Event;
-use winit::event::VirtualKeyCode;
-use winit_input_helper::WinitInputHelper;
+use crate::save_load::ControllerData;
+
+const A_POS: u8 = 0;
+const B_POS: u8 = 1;
+const SEL_POS: u8 = 2;
+const STR_POS: u8 = 3;
+const UP_POS: u8 = 4;
+const DWN_POS: u8 = 5;
+const LFT_POS: u8 = 6;
+const RGT_POS: u8 = 7;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct ButtonState {
+    pub a: bool,
+    pub b: bool,
+    pub select: bool,
+    pub start: bool,
+    pub up: bool,
+    pub down: bool,
+    pub left: bool,
+    pub right: bool,
+}
+
+impl ButtonState {
+    // bit order matches the order the NES shifts the buttons out: A, B, Select, Start, Up, Down, Left, Right
+    pub fn fromByte(byte: u8) -> Self {
+        ButtonState {
+            a: (byte >> A_POS) & 1 == 1,
+            b: (byte >> B_POS) & 1 == 1,
+            select: (byte >> SEL_POS) & 1 == 1,
+            start: (byte >> STR_POS) & 1 == 1,
+            up: (byte >> UP_POS) & 1 == 1,
+            down: (byte >> DWN_POS) & 1 == 1,
+            left: (byte >> LFT_POS) & 1 == 1,
+            right: (byte >> RGT_POS) & 1 == 1,
+        }
+    }
+
+    pub fn toByte(&self) -> u8 {
+        let mut byte: u8 = 0;
+        byte |= (self.a as u8) << A_POS;
+        byte |= (self.b as u8) << B_POS;
+        byte |= (self.select as u8) << SEL_POS;
+        byte |= (self.start as u8) << STR_POS;
+        byte |= (self.up as u8) << UP_POS;
+        byte |= (self.down as u8) << DWN_POS;
+        byte |= (self.left as u8) << LFT_POS;
+        byte |= (self.right as u8) << RGT_POS;
+        return byte;
+    }
+}
+
+// Feeds button states into the console, one port at a time. Port 0 is $4016, port 1 is $4017,
+// and with a Four Score plugged in ports 2 and 3 are players 3 and 4.
+pub trait InputProvider {
+    fn getButtons(&mut self, port: u8) -> ButtonState;
+}
 
 pub struct Controller {
-    //eventPump: Rc<RefCell<EventPump>>,
     controllerState: u8,
     controllerIdx: u8,
     strobe: bool,
-    input: Option<WinitInputHelper>,
+    pendingState: u8,
 }
 
 impl Controller {
     pub fn new() -> Self {
         Controller {
-            //eventPump,
             controllerState: 0,
             controllerIdx: 0,
             strobe: false,
-            input: None
+            pendingState: 0,
         }
     }
 
@@ -51,115 +98,70 @@ impl Controller {
         }
     }
 
-    pub fn setEvents(&mut self, input: WinitInputHelper) -> () {
-        self.input = Some(input);
+    // buttons take effect the next time the controller is cycled
+    pub fn setButtons(&mut self, buttons: ButtonState) -> () {
+        self.pendingState = buttons.toByte();
     }
-}
-
-const A_POS: u8 = 0;
-const B_POS: u8 = 1;
-const SEL_POS: u8 = 2;
-const STR_POS: u8 = 3;
-const UP_POS: u8 = 4;
-const DWN_POS: u8 = 5;
-const LFT_POS: u8 = 6;
-const RGT_POS: u8 = 7;
-
-impl Clocked for Controller {
-    fn cycle(&mut self) {
-
-        // if-block time!
-        if let Some(key) = &self.input {
-            if key.key_pressed(VirtualKeyCode::Z) {
-                self.controllerState |= (1 << A_POS);
-            }
 
-            if key.key_pressed(VirtualKeyCode::X) {
-                self.controllerState |= (1 << B_POS);
-            }
-
-            if key.key_pressed(VirtualKeyCode::Left) {
-                self.controllerState |= (1 << LFT_POS);
-            }
-
-            if key.key_pressed(VirtualKeyCode::Right) {
-                self.controllerState |= (1 << RGT_POS);
-            }
-
-            if key.key_pressed(VirtualKeyCode::Up) {
-                self.controllerState |= (1 << UP_POS);
-            }
-
-            if key.key_pressed(VirtualKeyCode::Down) {
-                self.controllerState |= (1 << DWN_POS);
-            }
-
-            if key.key_pressed(VirtualKeyCode::Return) {
-                self.controllerState |= (1 << STR_POS);
-            }
+    // the buttons waiting to be latched
+    pub fn getButtons(&self) -> ButtonState {
+        return ButtonState::fromByte(self.pendingState);
+    }
 
-            if key.key_pressed(VirtualKeyCode::RShift) {
-                self.controllerState |= (1 << SEL_POS);
-            }
+    pub fn saveState(&self) -> ControllerData {
+        ControllerData {
+            controllerState: self.controllerState,
+            controllerIdx: self.controllerIdx,
+            strobe: self.strobe,
+        }
+    }
 
+    pub fn loadState(&mut self, data: &ControllerData) -> () {
+        self.controllerState = data.controllerState;
+        self.controllerIdx = data.controllerIdx;
+        self.strobe = data.strobe;
+    }
+}
 
-            if key.key_released(VirtualKeyCode::Z) {
-                self.controllerState &= !(1 << A_POS);
-            }
+impl Clocked for Controller {
+    fn cycle(&mut self) {
+        self.controllerState = self.pendingState;
+    }
+}
 
-            if key.key_released(VirtualKeyCode::X) {
-                self.controllerState &= !(1 << B_POS);
-            }
+#[cfg(test)]
+mod ControllerSpec {
+    use super::*;
 
-            if key.key_released(VirtualKeyCode::Left) {
-                self.controllerState &= !(1 << LFT_POS);
-            }
+    #[test]
+    fn buttonStateRoundTrip() -> () {
+        for byte in 0..=255u8 {
+            assert_eq!(ButtonState::fromByte(byte).toByte(), byte);
+        }
+    }
 
-            if key.key_released(VirtualKeyCode::Right) {
-                self.controllerState &= !(1 << RGT_POS);
-            }
+    #[test]
+    fn readsButtonsInOrder() -> () {
+        let mut controller = Controller::new();
+        controller.setButtons(ButtonState { a: true, start: true, right: true, ..ButtonState::default() });
+        controller.cycle();
 
-            if key.key_released(VirtualKeyCode::Up) {
-                self.controllerState &= !(1 << UP_POS);
-            }
+        controller.writeState(1);
+        controller.writeState(0);
 
-            if key.key_released(VirtualKeyCode::Down) {
-                self.controllerState &= !(1 << DWN_POS);
-            }
+        let bits: Vec<u8> = (0..8).map(|_| controller.getState()).collect();
+        assert_eq!(bits, vec![1, 0, 0, 1, 0, 0, 0, 1]);
+    }
 
-            if key.key_released(VirtualKeyCode::Return) {
-                self.controllerState &= !(1 << STR_POS);
-            }
+    #[test]
+    fn buttonsWaitForLatch() -> () {
+        let mut controller = Controller::new();
+        controller.setButtons(ButtonState { a: true, ..ButtonState::default() });
 
-            if key.key_released(VirtualKeyCode::RShift) {
-                self.controllerState &= !(1 << SEL_POS);
-            }
-        }
+        controller.writeState(1);
+        assert_eq!(controller.getState(), 0);
 
-        // for event in self.events.as_slice() {
-        //     match *event {
-        //         Event::Quit { .. } => { exit(0); }
-        //
-        //         Event::KeyDown { keycode: Some(Keycode::Z), .. } => { self.controllerState |= (1 << A_POS); }
-        //         Event::KeyDown { keycode: Some(Keycode::X), .. } => { self.controllerState |= (1 << B_POS); }
-        //         Event::KeyDown { keycode: Some(Keycode::Left), .. } => { self.controllerState |= (1 << LFT_POS); }
-        //         Event::KeyDown { keycode: Some(Keycode::Right), .. } => { self.controllerState |= (1 << RGT_POS); }
-        //         Event::KeyDown { keycode: Some(Keycode::Up), .. } => { self.controllerState |= (1 << UP_POS); }
-        //         Event::KeyDown { keycode: Some(Keycode::Down), .. } => { self.controllerState |= (1 << DWN_POS); }
-        //         Event::KeyDown { keycode: Some(Keycode::Return), .. } => { self.controllerState |= (1 << STR_POS); }
-        //         Event::KeyDown { keycode: Some(Keycode::RShift), .. } => { self.controllerState |= (1 << SEL_POS); }
-        //
-        //         Event::KeyUp { keycode: Some(Keycode::Z), .. } => { self.controllerState &= !(1 << A_POS); }
-        //         Event::KeyUp { keycode: Some(Keycode::X), .. } => { self.controllerState &= !(1 << B_POS); }
-        //         Event::KeyUp { keycode: Some(Keycode::Left), .. } => { self.controllerState &= !(1 << LFT_POS); }
-        //         Event::KeyUp { keycode: Some(Keycode::Right), .. } => { self.controllerState &= !(1 << RGT_POS); }
-        //         Event::KeyUp { keycode: Some(Keycode::Up), .. } => { self.controllerState &= !(1 << UP_POS); }
-        //         Event::KeyUp { keycode: Some(Keycode::Down), .. } => { self.controllerState &= !(1 << DWN_POS); }
-        //         Event::KeyUp { keycode: Some(Keycode::Return), .. } => { self.controllerState &= !(1 << STR_POS); }
-        //         Event::KeyUp { keycode: Some(Keycode::RShift), .. } => { self.controllerState &= !(1 << SEL_POS); }
-        //
-        //         _ => {}
-        //     }
-        // }
+        controller.cycle();
+        assert_eq!(controller.getState(), 1);
     }
-}
\ No newline at end of file
+}
@@ -3,6 +3,7 @@
 #![allow(exceeding_bitshifts)]
 
 use crate::data_bus::*;
+use crate::cpu_bus::CpuBus;
 use crate::opcode_info::*;
 use std::cell::RefCell;
 use std::rc::Rc;
@@ -15,6 +16,9 @@ use std::any::Any;
 use std::fs::File;
 use std::io::Write;
 use crate::save_load::{BusData, CpuData, CpuFlagData};
+use crate::trace_logger::TraceLogger;
+
+const RESET_CYCLES: u64 = 7;
 
 const CARRY_POS: u8 = 0;
 const ZERO_POS: u8 = 1;
@@ -53,7 +57,19 @@ impl Flags {
 
 const STACK_IDX: u16 = 0x0100;
 
-pub struct Cpu {
+// A snapshot of the registers, for the debugger and anything else looking in from outside
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct CpuRegisters {
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub sp: u8,
+    pub pc: u16,
+    pub p: u8,
+}
+
+// Generic over the bus so the real core calls DataBus directly, while tests can swap in a FlatBus
+pub struct Cpu<B: CpuBus = DataBus> {
     // registers
     regA: u8,
     regY: u8,
@@ -63,7 +79,7 @@ pub struct Cpu {
     pgmCounter: u16,
     stkPointer: u8,
 
-    memory: Rc<RefCell<DataBus>>,
+    memory: Rc<RefCell<B>>,
 
     // flag(s)
     flags: Flags,
@@ -89,11 +105,19 @@ pub struct Cpu {
     oamPage: u16,
     oamCycles: u16,
 
+    // cycles since power on, counting the 7 the reset sequence takes like nestest.log does
+    cycleCount: u64,
+    tracer: Option<TraceLogger>,
+
+    // where the running instruction's immediate operand is, since reading it is really a fetch
+    immediateOperand: Option<u16>,
 }
 
-impl Clocked for Cpu {
+impl<B: CpuBus> Clocked for Cpu<B> {
     #[inline]
     fn cycle(&mut self) {
+        let cycleCount = self.cycleCount;
+        self.cycleCount += 1;
         self.isEvenCycle = !self.isEvenCycle;
 
         if self.waitCycles != 0 {
@@ -126,11 +150,17 @@ impl Clocked for Cpu {
             }
         }
 
-        let opInfo = &OPCODE_INSTRUCTIONS[self.readMem8(self.pgmCounter) as usize];
+        if self.tracer.is_some() {
+            self.trace(cycleCount);
+        }
+
+        let opInfo = &OPCODE_INSTRUCTIONS[self.fetchMem8(self.pgmCounter) as usize];
         let (target, bytes, increment, boundaryCrossed) = self.getAddressInfo(opInfo.opCode, opInfo.addrMode, self.pgmCounter.wrapping_add(1));
 
         self.pgmCounter = if increment { self.pgmCounter.wrapping_add(bytes) } else { self.pgmCounter };
+        self.immediateOperand = if opInfo.addrMode == AddressMode::Immediate { target } else { None };
         self.executeInstruction(opInfo.opCode, target);
+        self.immediateOperand = None;
 
         // we add cycles so that potential interrupt cycles are not erased
         self.waitCycles += (opInfo.cycles as u16) - 1;
@@ -139,8 +169,8 @@ impl Clocked for Cpu {
     }
 }
 
-impl Cpu {
-    pub fn new(memory: Rc<RefCell<DataBus>>) -> Cpu {
+impl<B: CpuBus> Cpu<B> {
+    pub fn new(memory: Rc<RefCell<B>>) -> Cpu<B> {
 
         let mut cpu = Cpu {
             regA: 0,
@@ -159,6 +189,9 @@ impl Cpu {
             oamPage: 0,
             oamCycles: 0,
             isEvenCycle: false,
+            cycleCount: 0,
+            tracer: None,
+            immediateOperand: None,
         };
 
         cpu.setFlags(0x24);
@@ -170,12 +203,14 @@ impl Cpu {
         let lo = self.memory.borrow().readCpuMem(0xFFFC);
         let hi = self.memory.borrow().readCpuMem(0xFFFD);
         self.pgmCounter = ((hi as u16) << 8) | (lo as u16);
+        self.cycleCount = RESET_CYCLES;
     }
 
     pub fn reset(&mut self) -> () {
         self.stkPointer = self.stkPointer.wrapping_sub(3);
         self.setFlags(0x24);
         self.pgmCounter = self.readMem16(0xFFFC);
+        self.cycleCount = RESET_CYCLES;
     }
 
     pub fn saveState(&self) -> CpuData {
@@ -234,6 +269,60 @@ impl Cpu {
         self.oamCycles = data.oamCycles;
     }
 
+    pub fn getRegisters(&self) -> CpuRegisters {
+        CpuRegisters {
+            a: self.regA,
+            x: self.regX,
+            y: self.regY,
+            sp: self.stkPointer,
+            pc: self.pgmCounter,
+            p: self.getFlagValues(),
+        }
+    }
+
+    // true when the next cycle fetches a new instruction, rather than finishing the last one,
+    // taking an interrupt or running OAM DMA
+    pub fn isAtInstruction(&self) -> bool {
+        return self.waitCycles == 0 && !self.triggerIrq && !self.triggerNmi && !self.isOamTransfer;
+    }
+
+    // for tests that start the CPU from a known state
+    pub fn setRegisters(&mut self, registers: CpuRegisters) -> () {
+        self.regA = registers.a;
+        self.regX = registers.x;
+        self.regY = registers.y;
+        self.stkPointer = registers.sp;
+        self.pgmCounter = registers.pc;
+        self.setFlags(registers.p);
+    }
+
+    pub fn setProgramCounter(&mut self, addr: u16) -> () {
+        self.pgmCounter = addr;
+    }
+
+    pub fn getCycleCount(&self) -> u64 {
+        return self.cycleCount;
+    }
+
+    // Starts logging each instruction as it's about to run, or stops with None. Hands back the
+    // logger it replaces so it can be finished.
+    pub fn setTracer(&mut self, tracer: Option<TraceLogger>) -> Option<TraceLogger> {
+        return std::mem::replace(&mut self.tracer, tracer);
+    }
+
+    pub fn getTracer(&self) -> Option<&TraceLogger> {
+        return self.tracer.as_ref();
+    }
+
+    fn trace(&mut self, cycleCount: u64) -> () {
+        let registers = self.getRegisters();
+        let memory = self.memory.borrow();
+        let (scanline, dot) = memory.getPpuPosition();
+        if let Some(tracer) = self.tracer.as_mut() {
+            tracer.log(&registers, scanline, dot, cycleCount, |addr| memory.peekCpuMem(addr));
+        }
+    }
+
     pub fn saveBusState(&self) -> BusData {
         BusData {
             cpuMem: self.memory.borrow().copyCpuMem()
@@ -913,18 +1002,18 @@ impl Cpu {
                 return (None, 1, true, false);
             }
             AddressMode::Absolute => {
-                let target = self.readMem16(oper);
+                let target = self.fetchMem16(oper);
 
                 return (Some(target), 3, self.pcShouldIncrement(*opCode), false);
             }
             AddressMode::AbsoluteX => {
-                let orgTarget: u16 = self.readMem16(oper);
+                let orgTarget: u16 = self.fetchMem16(oper);
                 let newTarget: u16 = orgTarget.wrapping_add(self.regX as u16);
 
                 return (Some(newTarget), 3, self.pcShouldIncrement(*opCode), newTarget & 0xFF00 != orgTarget);
             }
             AddressMode::AbsoluteY => {
-                let orgTarget: u16 = self.readMem16(oper);
+                let orgTarget: u16 = self.fetchMem16(oper);
                 let newTarget: u16 = orgTarget.wrapping_add(self.regY as u16);
 
                 return (Some(newTarget), 3, self.pcShouldIncrement(*opCode), newTarget & 0xFF00 != orgTarget);
@@ -937,7 +1026,7 @@ impl Cpu {
             }
             AddressMode::Indirect => {
                 // only the JMP instruction uses this addressing mode
-                let orgAddr = self.readMem16(oper);
+                let orgAddr = self.fetchMem16(oper);
                 let lo = self.readMem8(orgAddr);
                 let hi = self.readMem8(
                     if (orgAddr.wrapping_add(1) & 0x00FF) == 0 { orgAddr & 0xFF00 } else { orgAddr.wrapping_add(1) }
@@ -947,7 +1036,7 @@ impl Cpu {
                 return (Some(target), 3, false, false);
             }
             AddressMode::IndirectIndexed => {
-                let zpgAddr = self.readMem8(oper);
+                let zpgAddr = self.fetchMem8(oper);
                 let mut storedAddr: u16 = 0x0000;
 
                 if zpgAddr == 0xFF {
@@ -961,7 +1050,7 @@ impl Cpu {
                 return (Some(target), 2, self.pcShouldIncrement(*opCode), (storedAddr & 0x00FF) > (target & 0x00FF));
             }
             AddressMode::IndexedIndirect => {
-                let zpgAddr = self.readMem8(oper).wrapping_add(self.regX);
+                let zpgAddr = self.fetchMem8(oper).wrapping_add(self.regX);
                 let mut storedAddr: u16 = 0x0000;
 
                 if zpgAddr == 0xFF {
@@ -975,7 +1064,7 @@ impl Cpu {
             }
             AddressMode::Relative => {
                 // this addressing mode is only for branching instructions
-                let mut jumpOffset = self.readMem8(oper);
+                let mut jumpOffset = self.fetchMem8(oper);
                 let mut target: u16;
 
                 target = self.pgmCounter.wrapping_add(jumpOffset as u16);
@@ -995,16 +1084,16 @@ impl Cpu {
                 return (Some(target), 2, !branching, target & 0xFF00 != (oper.wrapping_sub(1)) & 0xFF00);
             }
             AddressMode::ZeroPage => {
-                let target = self.readMem8(oper) as u16;
+                let target = self.fetchMem8(oper) as u16;
                 return (Some(target), 2, self.pcShouldIncrement(*opCode), false);
             }
             AddressMode::ZeroPageX => {
-                let addr = self.readMem8(oper);
+                let addr = self.fetchMem8(oper);
                 let target = addr.wrapping_add(self.regX) as u16;
                 return (Some(target), 2, self.pcShouldIncrement(*opCode), target < addr as u16);
             }
             AddressMode::ZeroPageY => {
-                let addr = self.readMem8(oper);
+                let addr = self.fetchMem8(oper);
                 let target = addr.wrapping_add(self.regY) as u16;
                 return (Some(target), 2, self.pcShouldIncrement(*opCode), target < addr as u16);
             }
@@ -1032,21 +1121,38 @@ impl Cpu {
     #[inline]
     fn readMem16(&self, ref addr: u16) -> u16 {
         let lo = self.memory.borrow().readCpuMem(*addr);
-        let hi = self.memory.borrow().readCpuMem((*addr + 1));
+        let hi = self.memory.borrow().readCpuMem(addr.wrapping_add(1));
         return (hi as u16) << 8 | lo as u16;
     }
 
     #[inline]
     fn readMem8(&self, ref addr: u16) -> u8 {
+        if self.immediateOperand == Some(*addr) {
+            return self.fetchMem8(*addr);
+        }
         return self.memory.borrow().readCpuMem(*addr);
     }
 
+    // Opcode and operand bytes. They come off the bus like any read, but the debugger doesn't
+    // count them as reads.
+    #[inline]
+    fn fetchMem8(&self, ref addr: u16) -> u8 {
+        return self.memory.borrow().fetchCpuMem(*addr);
+    }
+
+    #[inline]
+    fn fetchMem16(&self, ref addr: u16) -> u16 {
+        let lo = self.memory.borrow().fetchCpuMem(*addr);
+        let hi = self.memory.borrow().fetchCpuMem(addr.wrapping_add(1));
+        return (hi as u16) << 8 | lo as u16;
+    }
+
     #[inline]
     fn writeMem8(&mut self, ref addr: u16, value: u8) -> () {
         // have to OAM DMA transfer here to prevent violation of borrowing rules
         // TODO: FIX THIS
         match *addr {
-            0x4014 => { self.triggerOamTransfer((value as u16) << 8); }
+            0x4014 if self.memory.borrow().hasOamDma() => { self.triggerOamTransfer((value as u16) << 8); }
             _ => { self.memory.borrow_mut().writeCpuMem(*addr, value); }
         }
     }
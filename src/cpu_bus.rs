@@ -0,0 +1,98 @@
+#![allow(non_snake_case)]
+#![allow(warnings)]
+
+use std::cell::RefCell;
+
+// Everything the CPU needs from the memory it's wired to. DataBus is the real one; FlatBus lets
+// the CPU be tested on its own.
+pub trait CpuBus {
+    fn readCpuMem(&self, addr: u16) -> u8;
+    fn writeCpuMem(&mut self, addr: u16, val: u8) -> ();
+
+    // an opcode or operand byte, which the debugger doesn't treat as a read
+    fn fetchCpuMem(&self, addr: u16) -> u8 {
+        return self.readCpuMem(addr);
+    }
+
+    // reads without side effects, for trace logs
+    fn peekCpuMem(&self, addr: u16) -> u8;
+
+    // one byte of an OAM DMA transfer
+    fn cpuWriteOam(&mut self, val: u8) -> ();
+
+    // the internal RAM, for save states
+    fn copyCpuMem(&self) -> Vec<u8>;
+    fn loadCpuMem(&mut self, memory: &Vec<u8>) -> ();
+
+    // the PPU's (scanline, dot), for trace logs
+    fn getPpuPosition(&self) -> (u16, u16) {
+        return (0, 0);
+    }
+
+    // whether a write to $4014 starts an OAM DMA transfer instead of reaching memory
+    fn hasOamDma(&self) -> bool {
+        return true;
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum BusAccess {
+    Read(u16, u8),
+    Write(u16, u8),
+}
+
+// 64K of plain RAM with nothing mapped into it, that remembers every access the CPU makes.
+pub struct FlatBus {
+    memory: Vec<u8>,
+    accesses: RefCell<Vec<BusAccess>>,
+}
+
+impl FlatBus {
+    pub fn new() -> Self {
+        FlatBus {
+            memory: vec![0; 0x10000],
+            accesses: RefCell::new(Vec::new()),
+        }
+    }
+
+    // sets memory without it counting as an access
+    pub fn poke(&mut self, addr: u16, val: u8) -> () {
+        self.memory[addr as usize] = val;
+    }
+
+    pub fn takeAccesses(&mut self) -> Vec<BusAccess> {
+        return self.accesses.take();
+    }
+}
+
+impl CpuBus for FlatBus {
+    fn readCpuMem(&self, addr: u16) -> u8 {
+        let val = self.memory[addr as usize];
+        self.accesses.borrow_mut().push(BusAccess::Read(addr, val));
+        return val;
+    }
+
+    fn writeCpuMem(&mut self, addr: u16, val: u8) -> () {
+        self.memory[addr as usize] = val;
+        self.accesses.borrow_mut().push(BusAccess::Write(addr, val));
+    }
+
+    fn peekCpuMem(&self, addr: u16) -> u8 {
+        return self.memory[addr as usize];
+    }
+
+    // there's no PPU, the bytes go nowhere
+    fn cpuWriteOam(&mut self, val: u8) -> () {}
+
+    fn hasOamDma(&self) -> bool {
+        return false;
+    }
+
+    fn copyCpuMem(&self) -> Vec<u8> {
+        return self.memory.clone();
+    }
+
+    fn loadCpuMem(&mut self, memory: &Vec<u8>) -> () {
+        self.memory = memory.clone();
+    }
+}
@@ -9,11 +9,19 @@ use crate::ppu::*;
 use crate::cartridge::*;
 use crate::mappers::mapper::MirrorType::*;
 use crate::palette::*;
-use crate::controller::Controller;
+use crate::controller::{ButtonState, Controller};
+use crate::four_score::FourScore;
+use crate::zapper::Zapper;
+use crate::arkanoid::{Vaus, VausKind};
+use crate::debugger::Debugger;
 use crate::clock::Clocked;
 use crate::apu::Apu;
-use sdl2::event::Event;
-use winit_input_helper::WinitInputHelper;
+use crate::cpu_bus::CpuBus;
+use crate::save_load::InputData;
+
+// Only the low bits of $4016/$4017 are driven by the controllers. The rest float at whatever was
+// last on the bus, which for the usual LDA $4016 is the $40 high byte of the address.
+const CONTROLLER_OPEN_BUS: u8 = 0x40;
 
 // Split the buses in two. One for CPU-PPU intercommunication, one for PPU data reads and writes.
 
@@ -24,6 +32,11 @@ pub struct DataBus {
     apu: Option<Rc<RefCell<Apu>>>,
     cartridge: Option<Rc<RefCell<Cartridge>>>,
     controller1: Option<Rc<RefCell<Controller>>>,
+    controller2: Option<Rc<RefCell<Controller>>>,
+    fourScore: Option<Rc<RefCell<FourScore>>>,   // replaces both controllers while attached
+    zapper: Option<Rc<RefCell<Zapper>>>,         // replaces controller two while attached
+    vaus: Option<Rc<RefCell<Vaus>>>,             // the NES one replaces controller two, the Famicom one sits beside both
+    debugger: Option<Rc<RefCell<Debugger>>>,     // told about every CPU read and write, for watchpoints
 }
 
 impl DataBus {
@@ -35,6 +48,11 @@ impl DataBus {
             apu: None,
             cartridge: None,
             controller1: None,
+            controller2: None,
+            fourScore: None,
+            zapper: None,
+            vaus: None,
+            debugger: None,
         }
     }
 
@@ -46,6 +64,10 @@ impl DataBus {
         self.cpu = Some(cpuRef);
     }
 
+    pub fn attachDebugger(&mut self, debuggerRef: Option<Rc<RefCell<Debugger>>>) -> () {
+        self.debugger = debuggerRef;
+    }
+
     pub fn attachApu(&mut self, apuRef: Rc<RefCell<Apu>>) {
         self.apu = Some(apuRef);
     }
@@ -58,6 +80,48 @@ impl DataBus {
         self.controller1 = Some(con1Ref);
     }
 
+    pub fn attachController2(&mut self, con2Ref: Rc<RefCell<Controller>>) -> () {
+        self.controller2 = Some(con2Ref);
+    }
+
+    pub fn attachZapper(&mut self, zapperRef: Option<Rc<RefCell<Zapper>>>) -> () {
+        self.zapper = zapperRef;
+    }
+
+    pub fn attachVaus(&mut self, vausRef: Option<Rc<RefCell<Vaus>>>) -> () {
+        self.vaus = vausRef;
+    }
+
+    // None goes back to the two plain controllers
+    pub fn attachFourScore(&mut self, fourScoreRef: Option<Rc<RefCell<FourScore>>>) -> () {
+        self.fourScore = fourScoreRef;
+    }
+
+    pub fn saveInputState(&self) -> InputData {
+        InputData {
+            controller1: self.controller1.as_ref().map(|controller| controller.borrow().saveState()),
+            controller2: self.controller2.as_ref().map(|controller| controller.borrow().saveState()),
+            fourScore: self.fourScore.as_ref().map(|fourScore| fourScore.borrow().saveState()),
+            vaus: self.vaus.as_ref().map(|vaus| vaus.borrow().saveState()),
+        }
+    }
+
+    // devices that weren't attached when the state was saved are left as they are
+    pub fn loadInputState(&mut self, data: &InputData) -> () {
+        if let (Some(controller), Some(data)) = (&self.controller1, &data.controller1) {
+            controller.borrow_mut().loadState(data);
+        }
+        if let (Some(controller), Some(data)) = (&self.controller2, &data.controller2) {
+            controller.borrow_mut().loadState(data);
+        }
+        if let (Some(fourScore), Some(data)) = (&self.fourScore, &data.fourScore) {
+            fourScore.borrow_mut().loadState(data);
+        }
+        if let (Some(vaus), Some(data)) = (&self.vaus, &data.vaus) {
+            vaus.borrow_mut().loadState(data);
+        }
+    }
+
     pub fn copyCpuMem(&self) -> Vec<u8> {
         return self.cpuMem.clone();
     }
@@ -68,6 +132,10 @@ impl DataBus {
 
     #[inline]
     pub fn writeCpuMem(&mut self, ref addr: u16, val: u8) -> () {
+        if let Some(debugger) = &self.debugger {
+            debugger.borrow_mut().onWrite(*addr, val);
+        }
+
         if *addr < 0x2000 {
             self.cpuMem[(*addr & 0x07FF) as usize] = val;
         }
@@ -76,7 +144,19 @@ impl DataBus {
             self.ppu.as_ref().unwrap().borrow_mut().writeMem(*addr & 0x0007, val);
         }
         else if *addr == 0x4016 {
+            if let Some(fourScore) = &self.fourScore {
+                fourScore.borrow_mut().writeState(val);
+                return;
+            }
+
+            // one strobe line goes to both ports
+            if let Some(vaus) = &self.vaus {
+                vaus.borrow_mut().writeState(val);
+            }
             self.controller1.as_ref().unwrap().borrow_mut().writeState(val);
+            if let Some(controller2) = &self.controller2 {
+                controller2.borrow_mut().writeState(val);
+            }
         }
         else if (*addr > 0x3FFF && *addr < 0x4014) || *addr == 0x4015 || *addr == 0x4017 {
             self.apu.as_ref().unwrap().borrow_mut().write(*addr, val);
@@ -88,6 +168,15 @@ impl DataBus {
 
     #[inline]
     pub fn readCpuMem(&self, ref addr: u16) -> u8 {
+        if let Some(debugger) = &self.debugger {
+            debugger.borrow_mut().onRead(*addr);
+        }
+        return self.fetchCpuMem(*addr);
+    }
+
+    // the same as readCpuMem without read watchpoints seeing it, for the CPU fetching instructions
+    #[inline]
+    pub fn fetchCpuMem(&self, ref addr: u16) -> u8 {
         if *addr < 0x2000 {
             return self.cpuMem[(*addr & 0x07FF) as usize].clone();
         }
@@ -97,18 +186,49 @@ impl DataBus {
         else if *addr == 0x4015 {
             return self.apu.as_ref().unwrap().borrow_mut().read(*addr);
         }
+        else if (*addr == 0x4016 || *addr == 0x4017) && self.fourScore.is_some() {
+            return CONTROLLER_OPEN_BUS | self.fourScore.as_ref().unwrap().borrow_mut().getState((*addr - 0x4016) as u8);
+        }
         else if *addr == 0x4016 {
-            return self.controller1.as_ref().unwrap().borrow_mut().getState();
+            let paddle = self.vaus.as_ref().map_or(0, |vaus| vaus.borrow_mut().getState(0));
+            return CONTROLLER_OPEN_BUS | self.controller1.as_ref().unwrap().borrow_mut().getState() | paddle;
+        }
+        else if *addr == 0x4017 && self.zapper.is_some() {
+            return CONTROLLER_OPEN_BUS | self.zapper.as_ref().unwrap().borrow().getState(&self.ppu.as_ref().unwrap().borrow());
+        }
+        else if *addr == 0x4017 && self.vaus.as_ref().map_or(false, |vaus| vaus.borrow().getKind() == VausKind::Nes) {
+            return CONTROLLER_OPEN_BUS | self.vaus.as_ref().unwrap().borrow_mut().getState(1);
         }
         else if *addr == 0x4017 {
-            // controller two stuff goes here
-            return 0;
+            let paddle = self.vaus.as_ref().map_or(0, |vaus| vaus.borrow_mut().getState(1));
+            let state = self.controller2.as_ref().map_or(0, |controller2| controller2.borrow_mut().getState());
+            return CONTROLLER_OPEN_BUS | state | paddle;
         }
         else {
             return self.cartridge.as_ref().unwrap().borrow_mut().cpuRead(*addr);
         }
     }
 
+    // the PPU's (scanline, dot), for trace logs
+    pub fn getPpuPosition(&self) -> (u16, u16) {
+        return self.ppu.as_ref().map_or((0, 0), |ppu| {
+            let ppu = ppu.borrow();
+            (ppu.getScanline(), ppu.getCycle())
+        });
+    }
+
+    // Reads RAM or the cartridge without touching any registers or watchpoints, for debuggers.
+    // The PPU, APU and controller registers all read as 0.
+    pub fn peekCpuMem(&self, addr: u16) -> u8 {
+        if addr < 0x2000 {
+            return self.cpuMem[(addr & 0x07FF) as usize];
+        }
+        else if addr >= 0x6000 {
+            return self.cartridge.as_ref().map_or(0, |cartridge| cartridge.borrow_mut().cpuRead(addr));
+        }
+        return 0;
+    }
+
     #[inline]
     pub fn cpuWriteOam(&mut self, val: u8) -> () {
         self.ppu.as_ref().unwrap().borrow_mut().cpuWriteOam(val);
@@ -116,11 +236,43 @@ impl DataBus {
 
     #[inline]
     pub fn getControllerInput(&mut self) -> () {
+        if let Some(fourScore) = &self.fourScore {
+            fourScore.borrow_mut().latch();
+        }
         self.controller1.as_ref().unwrap().borrow_mut().cycle();
+        if let Some(controller2) = &self.controller2 {
+            controller2.borrow_mut().cycle();
+        }
     }
 
-    pub fn setControllerEvents(&mut self, input: WinitInputHelper) -> () {
-        self.controller1.as_ref().unwrap().borrow_mut().setEvents(input);
+    // `port` is really the player, 2 and 3 only mean anything with a Four Score attached
+    pub fn setControllerButtons(&mut self, port: u8, buttons: ButtonState) -> () {
+        if let Some(fourScore) = &self.fourScore {
+            fourScore.borrow_mut().setButtons(port, buttons);
+            return;
+        }
+
+        match port {
+            0 => { self.controller1.as_ref().unwrap().borrow_mut().setButtons(buttons); }
+            1 => {
+                if let Some(controller2) = &self.controller2 {
+                    controller2.borrow_mut().setButtons(buttons);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    pub fn getControllerButtons(&self, port: u8) -> ButtonState {
+        if let Some(fourScore) = &self.fourScore {
+            return fourScore.borrow().getButtons(port);
+        }
+
+        return match port {
+            0 => self.controller1.as_ref().unwrap().borrow().getButtons(),
+            1 => self.controller2.as_ref().map(|controller2| controller2.borrow().getButtons()).unwrap_or_default(),
+            _ => ButtonState::default(),
+        };
     }
 
     pub fn setDmcCpuStall(&mut self) -> () {
@@ -183,6 +335,40 @@ impl DataBus {
 }
 
 
+impl CpuBus for DataBus {
+    fn readCpuMem(&self, addr: u16) -> u8 {
+        return DataBus::readCpuMem(self, addr);
+    }
+
+    fn writeCpuMem(&mut self, addr: u16, val: u8) -> () {
+        DataBus::writeCpuMem(self, addr, val);
+    }
+
+    fn fetchCpuMem(&self, addr: u16) -> u8 {
+        return DataBus::fetchCpuMem(self, addr);
+    }
+
+    fn peekCpuMem(&self, addr: u16) -> u8 {
+        return DataBus::peekCpuMem(self, addr);
+    }
+
+    fn cpuWriteOam(&mut self, val: u8) -> () {
+        DataBus::cpuWriteOam(self, val);
+    }
+
+    fn copyCpuMem(&self) -> Vec<u8> {
+        return DataBus::copyCpuMem(self);
+    }
+
+    fn loadCpuMem(&mut self, memory: &Vec<u8>) -> () {
+        DataBus::loadCpuMem(self, memory);
+    }
+
+    fn getPpuPosition(&self) -> (u16, u16) {
+        return DataBus::getPpuPosition(self);
+    }
+}
+
 #[cfg(test)]
 mod MemorySpec {
     use super::*;
@@ -217,6 +403,24 @@ mod MemorySpec {
         mem.writeCpuMem(0x2001, 1);
     }
 
+    #[test]
+    fn controllersShareStrobe() -> () {
+        let mut mem = DataBus::new();
+        mem.attachController1(Rc::new(RefCell::new(Controller::new())));
+        mem.attachController2(Rc::new(RefCell::new(Controller::new())));
+        mem.setControllerButtons(0, ButtonState { a: true, ..ButtonState::default() });
+        mem.setControllerButtons(1, ButtonState { b: true, ..ButtonState::default() });
+        mem.getControllerInput();
+
+        mem.writeCpuMem(0x4016, 1);
+        mem.writeCpuMem(0x4016, 0);
+
+        assert_eq!(mem.readCpuMem(0x4016), 0x41);
+        assert_eq!(mem.readCpuMem(0x4017), 0x40);
+        assert_eq!(mem.readCpuMem(0x4016), 0x40);
+        assert_eq!(mem.readCpuMem(0x4017), 0x41);
+    }
+
     #[test]
     fn pushPopStack() -> () {
         let mut mem = DataBus::new();
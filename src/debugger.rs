@@ -0,0 +1,338 @@
+#![allow(non_snake_case)]
+#![allow(warnings)]
+
+use std::collections::{BTreeSet, VecDeque};
+use std::fmt;
+use crate::cpu::CpuRegisters;
+use crate::disasm;
+
+const OPCODE_JSR: u8 = 0x20;
+const OPCODE_RTI: u8 = 0x40;
+const OPCODE_RTS: u8 = 0x60;
+
+// how many of the most recently run instructions are kept to show above the current one
+const HISTORY_LEN: usize = 8;
+
+// instructions disassembled from the program counter onwards
+const LINES_AHEAD: usize = 16;
+
+// Stops the CPU when any byte in start..=end is accessed the chosen ways.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Watchpoint {
+    pub start: u16,
+    pub end: u16,
+    pub read: bool,
+    pub write: bool,
+    pub execute: bool,
+}
+
+impl Watchpoint {
+    fn contains(&self, addr: u16) -> bool {
+        return addr >= self.start && addr <= self.end;
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum BreakReason {
+    Paused,
+    Step,
+    Breakpoint(u16),
+    Read(u16),
+    Write(u16, u8),
+    Execute(u16),
+}
+
+impl fmt::Display for BreakReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BreakReason::Paused => write!(f, "Paused"),
+            BreakReason::Step => write!(f, "Stepped"),
+            BreakReason::Breakpoint(addr) => write!(f, "Breakpoint at ${:04X}", addr),
+            BreakReason::Read(addr) => write!(f, "Read from ${:04X}", addr),
+            BreakReason::Write(addr, val) => write!(f, "Wrote ${:02X} to ${:04X}", val, addr),
+            BreakReason::Execute(addr) => write!(f, "Executed ${:04X}", addr),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum DebugCommand {
+    Continue,
+    Pause,
+    StepInto,
+    StepOver,   // runs a JSR through to its return
+    StepOut,    // runs until the current subroutine returns
+    RunTo(u16),
+    ToggleBreakpoint(u16),
+    AddWatchpoint(Watchpoint),
+    RemoveWatchpoint(usize),
+}
+
+enum RunMode {
+    Run,
+    StepInto,
+    StepOver { returnTo: u16, sp: u8 },
+    StepOut { sp: u8 },
+    RunTo(u16),
+}
+
+// One disassembled instruction for the debugger window
+#[derive(Debug, Clone, PartialEq)]
+pub struct DisasmLine {
+    pub address: u16,
+    pub bytes: String,
+    pub text: String,
+    pub current: bool,
+    pub breakpoint: bool,
+}
+
+// Everything the debugger window shows, taken whenever the frontend asks
+#[derive(Debug, Clone, PartialEq)]
+pub struct DebugView {
+    pub registers: CpuRegisters,
+    pub stack: Vec<u8>,     // $0100-$01FF
+    pub lines: Vec<DisasmLine>,
+    pub breakpoints: Vec<u16>,
+    pub watchpoints: Vec<Watchpoint>,
+    pub breakReason: Option<BreakReason>,
+}
+
+// Breakpoints, watchpoints and stepping. The console asks `checkInstruction` before every
+// instruction and stops emulating while it says so; the data bus reports every CPU read and write.
+pub struct Debugger {
+    breakpoints: BTreeSet<u16>,
+    watchpoints: Vec<Watchpoint>,
+    mode: RunMode,
+    breakReason: Option<BreakReason>,   // Some while stopped
+    pendingBreak: Option<BreakReason>,  // a watchpoint hit partway through an instruction stops before the next
+    resuming: bool,                     // lets the instruction we stopped on run when carrying on
+    lastOpcode: u8,
+    history: VecDeque<u16>,
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Debugger {
+            breakpoints: BTreeSet::new(),
+            watchpoints: Vec::new(),
+            mode: RunMode::Run,
+            breakReason: None,
+            pendingBreak: None,
+            resuming: false,
+            lastOpcode: 0,
+            history: VecDeque::with_capacity(HISTORY_LEN),
+        }
+    }
+
+    pub fn isPaused(&self) -> bool {
+        return self.breakReason.is_some();
+    }
+
+    pub fn getBreakReason(&self) -> Option<BreakReason> {
+        return self.breakReason;
+    }
+
+    pub fn hasBreakpoint(&self, addr: u16) -> bool {
+        return self.breakpoints.contains(&addr);
+    }
+
+    pub fn toggleBreakpoint(&mut self, addr: u16) -> () {
+        if !self.breakpoints.remove(&addr) {
+            self.breakpoints.insert(addr);
+        }
+    }
+
+    pub fn addWatchpoint(&mut self, watchpoint: Watchpoint) -> () {
+        self.watchpoints.push(watchpoint);
+    }
+
+    pub fn removeWatchpoint(&mut self, idx: usize) -> () {
+        if idx < self.watchpoints.len() {
+            self.watchpoints.remove(idx);
+        }
+    }
+
+    // stops before the next instruction
+    pub fn pause(&mut self) -> () {
+        if !self.isPaused() {
+            self.pendingBreak = Some(BreakReason::Paused);
+        }
+    }
+
+    pub fn resume(&mut self) -> () {
+        self.carryOn(RunMode::Run);
+    }
+
+    pub fn stepInto(&mut self) -> () {
+        self.carryOn(RunMode::StepInto);
+    }
+
+    // `opcode` is the instruction about to run
+    pub fn stepOver(&mut self, registers: &CpuRegisters, opcode: u8) -> () {
+        if opcode == OPCODE_JSR {
+            self.carryOn(RunMode::StepOver { returnTo: registers.pc.wrapping_add(3), sp: registers.sp });
+        }
+        else {
+            self.carryOn(RunMode::StepInto);
+        }
+    }
+
+    pub fn stepOut(&mut self, registers: &CpuRegisters) -> () {
+        self.carryOn(RunMode::StepOut { sp: registers.sp });
+    }
+
+    pub fn runTo(&mut self, addr: u16) -> () {
+        self.carryOn(RunMode::RunTo(addr));
+    }
+
+    fn carryOn(&mut self, mode: RunMode) -> () {
+        self.mode = mode;
+        self.resuming = self.breakReason.take().is_some();
+        self.pendingBreak = None;
+    }
+
+    pub fn onRead(&mut self, addr: u16) -> () {
+        if self.pendingBreak.is_none() && self.watchpoints.iter().any(|watch| watch.read && watch.contains(addr)) {
+            self.pendingBreak = Some(BreakReason::Read(addr));
+        }
+    }
+
+    pub fn onWrite(&mut self, addr: u16, val: u8) -> () {
+        if self.pendingBreak.is_none() && self.watchpoints.iter().any(|watch| watch.write && watch.contains(addr)) {
+            self.pendingBreak = Some(BreakReason::Write(addr, val));
+        }
+    }
+
+    // Called with the CPU about to run the instruction at `registers.pc`. True to stop there.
+    pub fn checkInstruction(&mut self, registers: &CpuRegisters, opcode: u8) -> bool {
+        if self.isPaused() {
+            return true;
+        }
+
+        let mut reason = self.pendingBreak.take();
+        if reason.is_none() && !std::mem::take(&mut self.resuming) {
+            reason = self.breakAt(registers);
+        }
+
+        if reason.is_some() {
+            self.breakReason = reason;
+            self.mode = RunMode::Run;
+            return true;
+        }
+
+        if self.history.len() == HISTORY_LEN {
+            self.history.pop_front();
+        }
+        self.history.push_back(registers.pc);
+        self.lastOpcode = opcode;
+        return false;
+    }
+
+    fn breakAt(&self, registers: &CpuRegisters) -> Option<BreakReason> {
+        let pc = registers.pc;
+        if self.breakpoints.contains(&pc) {
+            return Some(BreakReason::Breakpoint(pc));
+        }
+        if self.watchpoints.iter().any(|watch| watch.execute && watch.contains(pc)) {
+            return Some(BreakReason::Execute(pc));
+        }
+
+        let stepped = match self.mode {
+            RunMode::Run => false,
+            RunMode::StepInto => true,
+            RunMode::StepOver { returnTo, sp } => pc == returnTo && registers.sp >= sp,
+            // popped past the frame we started in
+            RunMode::StepOut { sp } => (self.lastOpcode == OPCODE_RTS || self.lastOpcode == OPCODE_RTI) && registers.sp > sp,
+            RunMode::RunTo(addr) => pc == addr,
+        };
+        return if stepped { Some(BreakReason::Step) } else { None };
+    }
+
+    // `peek` reads CPU memory without side effects
+    pub fn buildView(&self, registers: CpuRegisters, peek: impl Fn(u16) -> u8) -> DebugView {
+        let mut lines: Vec<DisasmLine> = self.history.iter()
+            .map(|addr| self.disassembleLine(*addr, &registers, &peek).0)
+            .collect();
+
+        let mut addr = registers.pc;
+        for _ in 0..LINES_AHEAD {
+            let (line, len) = self.disassembleLine(addr, &registers, &peek);
+            lines.push(line);
+            addr = addr.wrapping_add(len);
+        }
+
+        return DebugView {
+            registers,
+            stack: (0x0100..0x0200).map(|addr| peek(addr)).collect(),
+            lines,
+            breakpoints: self.breakpoints.iter().copied().collect(),
+            watchpoints: self.watchpoints.clone(),
+            breakReason: self.breakReason,
+        };
+    }
+
+    fn disassembleLine(&self, addr: u16, registers: &CpuRegisters, peek: &impl Fn(u16) -> u8) -> (DisasmLine, u16) {
+        let instruction = disasm::decode(addr, peek);
+        let current = addr == registers.pc;
+        let mut text = instruction.format();
+        // where the next instruction will actually read or write, once indexing is applied
+        if current {
+            if let Some(effective) = instruction.effectiveAddress(registers, peek) {
+                if Some(effective) != instruction.baseAddress() {
+                    text = format!("{} @ ${:04X}", text, effective);
+                }
+            }
+        }
+        if let Some(annotation) = instruction.annotation() {
+            text = format!("{} {}", text, annotation);
+        }
+
+        let line = DisasmLine {
+            address: addr,
+            bytes: instruction.bytes.iter().map(|byte| format!("{:02X}", byte)).collect::<Vec<String>>().join(" "),
+            text,
+            current,
+            breakpoint: self.hasBreakpoint(addr),
+        };
+        return (line, instruction.len());
+    }
+}
+
+#[cfg(test)]
+mod DebuggerSpec {
+    use super::*;
+
+    fn at(pc: u16, sp: u8) -> CpuRegisters {
+        return CpuRegisters { pc, sp, ..CpuRegisters::default() };
+    }
+
+    #[test]
+    fn stepOverRunsSubroutine() -> () {
+        let mut debugger = Debugger::new();
+        debugger.toggleBreakpoint(0x8000);
+        assert!(debugger.checkInstruction(&at(0x8000, 0xFD), OPCODE_JSR));
+        assert_eq!(debugger.getBreakReason(), Some(BreakReason::Breakpoint(0x8000)));
+
+        debugger.stepOver(&at(0x8000, 0xFD), OPCODE_JSR);
+        assert!(!debugger.checkInstruction(&at(0x8000, 0xFD), OPCODE_JSR));
+        assert!(!debugger.checkInstruction(&at(0x9000, 0xFB), OPCODE_RTS));
+        assert!(debugger.checkInstruction(&at(0x8003, 0xFD), 0xEA));
+        assert_eq!(debugger.getBreakReason(), Some(BreakReason::Step));
+    }
+
+    #[test]
+    fn watchpointStopsAfterAccess() -> () {
+        let mut debugger = Debugger::new();
+        debugger.addWatchpoint(Watchpoint { start: 0x0300, end: 0x03FF, read: false, write: true, execute: false });
+
+        assert!(!debugger.checkInstruction(&at(0x8000, 0xFD), 0x8D));
+        debugger.onRead(0x0300);
+        debugger.onWrite(0x0342, 0x1F);
+        assert!(debugger.checkInstruction(&at(0x8003, 0xFD), 0xEA));
+        assert_eq!(debugger.getBreakReason(), Some(BreakReason::Write(0x0342, 0x1F)));
+
+        // carrying on runs the instruction it stopped before
+        debugger.resume();
+        assert!(!debugger.checkInstruction(&at(0x8003, 0xFD), 0xEA));
+    }
+}
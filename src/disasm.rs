@@ -0,0 +1,235 @@
+#![allow(non_snake_case)]
+#![allow(warnings)]
+
+use crate::cpu::CpuRegisters;
+use crate::opcode_info::{AddressMode, OpMnemonic, OPCODE_INSTRUCTIONS};
+
+// One decoded instruction. Formats as ca65 source, with the unofficial opcodes spelled the way
+// ca65 expects them under `.setcpu "6502X"`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Instruction {
+    pub address: u16,
+    pub bytes: Vec<u8>,     // opcode then operand, 1 to 3 bytes
+    pub opCode: OpMnemonic,
+    pub addrMode: AddressMode,
+}
+
+// Decodes the instruction at `addr`. `peek` reads CPU memory and shouldn't have side effects.
+pub fn decode(addr: u16, peek: impl Fn(u16) -> u8) -> Instruction {
+    let opcode = peek(addr);
+    let info = &OPCODE_INSTRUCTIONS[opcode as usize];
+    let len = instructionLength(info.addrMode);
+    return Instruction {
+        address: addr,
+        bytes: (0..len).map(|offset| peek(addr.wrapping_add(offset))).collect(),
+        opCode: info.opCode,
+        addrMode: info.addrMode,
+    };
+}
+
+// Goes by the addressing mode rather than OpcodeInfo::bytes, which the CPU itself never reads.
+pub fn instructionLength(addrMode: AddressMode) -> u16 {
+    return match addrMode {
+        AddressMode::Implied | AddressMode::Accumulator => 1,
+        AddressMode::Absolute | AddressMode::AbsoluteX | AddressMode::AbsoluteY | AddressMode::Indirect => 3,
+        _ => 2,
+    };
+}
+
+// ca65 names, lower case
+pub fn mnemonic(opCode: OpMnemonic) -> &'static str {
+    return match opCode {
+        OpMnemonic::ADC => "adc", OpMnemonic::AND => "and", OpMnemonic::ASL => "asl", OpMnemonic::BCC => "bcc",
+        OpMnemonic::BCS => "bcs", OpMnemonic::BEQ => "beq", OpMnemonic::BIT => "bit", OpMnemonic::BMI => "bmi",
+        OpMnemonic::BNE => "bne", OpMnemonic::BPL => "bpl", OpMnemonic::BRK => "brk", OpMnemonic::BVC => "bvc",
+        OpMnemonic::BVS => "bvs", OpMnemonic::CLC => "clc", OpMnemonic::CLD => "cld", OpMnemonic::CLI => "cli",
+        OpMnemonic::CLV => "clv", OpMnemonic::CMP => "cmp", OpMnemonic::CPX => "cpx", OpMnemonic::CPY => "cpy",
+        OpMnemonic::DEC => "dec", OpMnemonic::DEX => "dex", OpMnemonic::DEY => "dey", OpMnemonic::EOR => "eor",
+        OpMnemonic::INC => "inc", OpMnemonic::INX => "inx", OpMnemonic::INY => "iny", OpMnemonic::JMP => "jmp",
+        OpMnemonic::JSR => "jsr", OpMnemonic::LDA => "lda", OpMnemonic::LDX => "ldx", OpMnemonic::LDY => "ldy",
+        OpMnemonic::LSR => "lsr", OpMnemonic::NOP => "nop", OpMnemonic::ORA => "ora", OpMnemonic::PHA => "pha",
+        OpMnemonic::PHP => "php", OpMnemonic::PLA => "pla", OpMnemonic::PLP => "plp", OpMnemonic::ROL => "rol",
+        OpMnemonic::ROR => "ror", OpMnemonic::RTI => "rti", OpMnemonic::RTS => "rts", OpMnemonic::SBC => "sbc",
+        OpMnemonic::SEC => "sec", OpMnemonic::SED => "sed", OpMnemonic::SEI => "sei", OpMnemonic::STA => "sta",
+        OpMnemonic::STX => "stx", OpMnemonic::STY => "sty", OpMnemonic::TAX => "tax", OpMnemonic::TAY => "tay",
+        OpMnemonic::TSX => "tsx", OpMnemonic::TXA => "txa", OpMnemonic::TXS => "txs", OpMnemonic::TYA => "tya",
+
+        // unofficial
+        OpMnemonic::AHX => "sha", OpMnemonic::ALR => "alr", OpMnemonic::ANC => "anc", OpMnemonic::ARR => "arr",
+        OpMnemonic::AXS => "axs", OpMnemonic::DCP => "dcp", OpMnemonic::ISC => "isc", OpMnemonic::KIL => "jam",
+        OpMnemonic::LAS => "las", OpMnemonic::LAX => "lax", OpMnemonic::RLA => "rla", OpMnemonic::RRA => "rra",
+        OpMnemonic::SAX => "sax", OpMnemonic::SHX => "shx", OpMnemonic::SHY => "shy", OpMnemonic::SLO => "slo",
+        OpMnemonic::SRE => "sre", OpMnemonic::TAS => "tas", OpMnemonic::XAA => "ane",
+    };
+}
+
+// The official opcodes, everything else is one of the undocumented ones
+const OFFICIAL_OPCODES: [u8; 151] = [
+    0x00, 0x01, 0x05, 0x06, 0x08, 0x09, 0x0A, 0x0D, 0x0E, 0x10, 0x11, 0x15, 0x16, 0x18, 0x19, 0x1D, 0x1E,
+    0x20, 0x21, 0x24, 0x25, 0x26, 0x28, 0x29, 0x2A, 0x2C, 0x2D, 0x2E, 0x30, 0x31, 0x35, 0x36, 0x38, 0x39, 0x3D, 0x3E,
+    0x40, 0x41, 0x45, 0x46, 0x48, 0x49, 0x4A, 0x4C, 0x4D, 0x4E, 0x50, 0x51, 0x55, 0x56, 0x58, 0x59, 0x5D, 0x5E,
+    0x60, 0x61, 0x65, 0x66, 0x68, 0x69, 0x6A, 0x6C, 0x6D, 0x6E, 0x70, 0x71, 0x75, 0x76, 0x78, 0x79, 0x7D, 0x7E,
+    0x81, 0x84, 0x85, 0x86, 0x88, 0x8A, 0x8C, 0x8D, 0x8E, 0x90, 0x91, 0x94, 0x95, 0x96, 0x98, 0x99, 0x9A, 0x9D,
+    0xA0, 0xA1, 0xA2, 0xA4, 0xA5, 0xA6, 0xA8, 0xA9, 0xAA, 0xAC, 0xAD, 0xAE, 0xB0, 0xB1, 0xB4, 0xB5, 0xB6, 0xB8, 0xB9, 0xBA, 0xBC, 0xBD, 0xBE,
+    0xC0, 0xC1, 0xC4, 0xC5, 0xC6, 0xC8, 0xC9, 0xCA, 0xCC, 0xCD, 0xCE, 0xD0, 0xD1, 0xD5, 0xD6, 0xD8, 0xD9, 0xDD, 0xDE,
+    0xE0, 0xE1, 0xE4, 0xE5, 0xE6, 0xE8, 0xE9, 0xEA, 0xEC, 0xED, 0xEE, 0xF0, 0xF1, 0xF5, 0xF6, 0xF8, 0xF9, 0xFD, 0xFE,
+];
+
+pub fn isOfficial(opcode: u8) -> bool {
+    return OFFICIAL_OPCODES.contains(&opcode);
+}
+
+// Names for the PPU, APU and I/O registers, including the PPU's mirrors up to $3FFF
+pub fn registerName(addr: u16) -> Option<&'static str> {
+    const PPU: [&str; 8] = ["PPUCTRL", "PPUMASK", "PPUSTATUS", "OAMADDR", "OAMDATA", "PPUSCROLL", "PPUADDR", "PPUDATA"];
+    const APU: [&str; 24] = [
+        "SQ1_VOL", "SQ1_SWEEP", "SQ1_LO", "SQ1_HI", "SQ2_VOL", "SQ2_SWEEP", "SQ2_LO", "SQ2_HI",
+        "TRI_LINEAR", "", "TRI_LO", "TRI_HI", "NOISE_VOL", "", "NOISE_LO", "NOISE_HI",
+        "DMC_FREQ", "DMC_RAW", "DMC_START", "DMC_LEN", "OAMDMA", "SND_CHN", "JOY1", "JOY2",
+    ];
+
+    return match addr {
+        0x2000..=0x3FFF => Some(PPU[(addr & 0x07) as usize]),
+        0x4000..=0x4017 => Some(APU[(addr - 0x4000) as usize]).filter(|name| !name.is_empty()),
+        _ => None,
+    };
+}
+
+impl Instruction {
+    pub fn len(&self) -> u16 {
+        return self.bytes.len() as u16;
+    }
+
+    pub fn opcode(&self) -> u8 {
+        return self.bytes[0];
+    }
+
+    pub fn isOfficial(&self) -> bool {
+        return isOfficial(self.opcode());
+    }
+
+    // the one byte or two byte operand, 0 if there isn't one
+    pub fn operand(&self) -> u16 {
+        return match self.bytes.len() {
+            2 => self.bytes[1] as u16,
+            3 => (self.bytes[2] as u16) << 8 | self.bytes[1] as u16,
+            _ => 0,
+        };
+    }
+
+    // where a branch goes if taken
+    pub fn branchTarget(&self) -> u16 {
+        return self.address.wrapping_add(2).wrapping_add(self.bytes[1] as i8 as u16);
+    }
+
+    // The address written in the instruction itself, before any indexing or indirection: the one
+    // worth naming if it's a hardware register. None for modes that don't name one.
+    pub fn baseAddress(&self) -> Option<u16> {
+        return match self.addrMode {
+            AddressMode::Implied | AddressMode::Accumulator | AddressMode::Immediate => None,
+            AddressMode::Relative => Some(self.branchTarget()),
+            _ => Some(self.operand()),
+        };
+    }
+
+    // ca65 syntax, e.g. `lda ($10),y`, `asl a` or `sta a:$0010` for an absolute store to zero page
+    pub fn format(&self) -> String {
+        let name = mnemonic(self.opCode);
+        let byte = self.operand() as u8;
+        let word = self.operand();
+        // ca65 would assemble a zero page address as the shorter zero page form without the a: prefix
+        let abs = if word < 0x100 { "a:" } else { "" };
+
+        return match self.addrMode {
+            AddressMode::Implied => String::from(name),
+            AddressMode::Accumulator => format!("{} a", name),
+            AddressMode::Immediate => format!("{} #${:02X}", name, byte),
+            AddressMode::ZeroPage => format!("{} ${:02X}", name, byte),
+            AddressMode::ZeroPageX => format!("{} ${:02X},x", name, byte),
+            AddressMode::ZeroPageY => format!("{} ${:02X},y", name, byte),
+            AddressMode::IndexedIndirect => format!("{} (${:02X},x)", name, byte),
+            AddressMode::IndirectIndexed => format!("{} (${:02X}),y", name, byte),
+            AddressMode::Relative => format!("{} ${:04X}", name, self.branchTarget()),
+            AddressMode::Absolute => format!("{} {}${:04X}", name, abs, word),
+            AddressMode::AbsoluteX => format!("{} {}${:04X},x", name, abs, word),
+            AddressMode::AbsoluteY => format!("{} {}${:04X},y", name, abs, word),
+            AddressMode::Indirect => format!("{} (${:04X})", name, word),
+        };
+    }
+
+    // The address the instruction will actually touch with the CPU in `registers`, following
+    // indexing and pointers (and the JMP ($xxFF) page wrap). None for modes without one.
+    pub fn effectiveAddress(&self, registers: &CpuRegisters, peek: impl Fn(u16) -> u8) -> Option<u16> {
+        let byte = self.operand() as u8;
+        let word = self.operand();
+        let pointer = |zpg: u8| (peek(zpg.wrapping_add(1) as u16) as u16) << 8 | peek(zpg as u16) as u16;
+
+        return match self.addrMode {
+            AddressMode::Implied | AddressMode::Accumulator | AddressMode::Immediate => None,
+            AddressMode::Relative => Some(self.branchTarget()),
+            AddressMode::ZeroPage => Some(byte as u16),
+            AddressMode::ZeroPageX => Some(byte.wrapping_add(registers.x) as u16),
+            AddressMode::ZeroPageY => Some(byte.wrapping_add(registers.y) as u16),
+            AddressMode::Absolute => Some(word),
+            AddressMode::AbsoluteX => Some(word.wrapping_add(registers.x as u16)),
+            AddressMode::AbsoluteY => Some(word.wrapping_add(registers.y as u16)),
+            AddressMode::IndexedIndirect => Some(pointer(byte.wrapping_add(registers.x))),
+            AddressMode::IndirectIndexed => Some(pointer(byte).wrapping_add(registers.y as u16)),
+            AddressMode::Indirect => {
+                let hi = (word & 0xFF00) | (word.wrapping_add(1) & 0x00FF);
+                Some((peek(hi) as u16) << 8 | peek(word) as u16)
+            }
+        };
+    }
+
+    // ca65 comment naming the hardware register the instruction uses, if any
+    pub fn annotation(&self) -> Option<String> {
+        return self.baseAddress().and_then(registerName).map(|name| format!("; {}", name));
+    }
+}
+
+#[cfg(test)]
+mod DisasmSpec {
+    use super::*;
+
+    fn decodeBytes(bytes: &[u8]) -> Instruction {
+        let mut memory = vec![0u8; 0x10000];
+        memory[0x8000..0x8000 + bytes.len()].copy_from_slice(bytes);
+        return decode(0x8000, |addr| memory[addr as usize]);
+    }
+
+    #[test]
+    fn formatsCa65() -> () {
+        assert_eq!(decodeBytes(&[0xB1, 0x10]).format(), "lda ($10),y");
+        assert_eq!(decodeBytes(&[0x0A]).format(), "asl a");
+        assert_eq!(decodeBytes(&[0x8D, 0x10, 0x00]).format(), "sta a:$0010");
+        assert_eq!(decodeBytes(&[0xD0, 0xFE]).format(), "bne $8000");
+        assert_eq!(decodeBytes(&[0xA7, 0x44]).format(), "lax $44");
+        assert_eq!(decodeBytes(&[0x1C, 0x00, 0x20]).len(), 3);
+        assert!(!decodeBytes(&[0xA7, 0x44]).isOfficial());
+    }
+
+    #[test]
+    fn namesRegisters() -> () {
+        assert_eq!(decodeBytes(&[0x8D, 0x00, 0x20]).annotation(), Some(String::from("; PPUCTRL")));
+        assert_eq!(decodeBytes(&[0xAD, 0x0D, 0x30]).annotation(), Some(String::from("; PPUSCROLL")));
+        assert_eq!(decodeBytes(&[0x8D, 0x14, 0x40]).annotation(), Some(String::from("; OAMDMA")));
+        assert_eq!(decodeBytes(&[0x8D, 0x00, 0x03]).annotation(), None);
+    }
+
+    #[test]
+    fn followsPointers() -> () {
+        let mut memory = vec![0u8; 0x10000];
+        memory[0x8000..0x8002].copy_from_slice(&[0xB1, 0xFF]);
+        memory[0x00FF] = 0x34;
+        memory[0x0000] = 0x12;
+        memory[0x9000..0x9003].copy_from_slice(&[0x6C, 0xFF, 0x02]);
+        memory[0x02FF] = 0x00;
+        memory[0x0200] = 0xC0;
+
+        let registers = CpuRegisters { y: 0x10, ..CpuRegisters::default() };
+        let peek = |addr: u16| memory[addr as usize];
+        assert_eq!(decode(0x8000, peek).effectiveAddress(&registers, peek), Some(0x1244));
+        assert_eq!(decode(0x9000, peek).effectiveAddress(&registers, peek), Some(0xC000));
+    }
+}
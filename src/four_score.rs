@@ -0,0 +1,146 @@
+#![allow(non_snake_case)]
+#![allow(warnings)]
+
+use crate::controller::ButtonState;
+use crate::save_load::FourScoreData;
+
+// after the 8 bits of each pad, the NES Four Score sends a signature on each port so games can
+// tell it's there. Shifted out least significant bit first, $4016 reads 0,0,0,1,0,0,0,0 and $4017
+// reads 0,0,1,0,0,0,0,0.
+const SIGNATURES: [u32; 2] = [0x08, 0x04];
+const REPORT_BITS: u8 = 24;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum FourScoreKind {
+    // NES Four Score: $4016 sends pad 1, pad 3 then a signature on D0, $4017 does the same for 2 and 4
+    Nes,
+    // Famicom four-player adapter: pads 1 and 2 on D0 as usual, pads 3 and 4 beside them on D1
+    Famicom,
+}
+
+// Four controllers behind $4016/$4017, standing in for the usual pair.
+pub struct FourScore {
+    kind: FourScoreKind,
+    pendingState: [u8; 4],
+    state: [u8; 4],
+    readIdx: [u8; 2],
+    strobe: bool,
+}
+
+impl FourScore {
+    pub fn new(kind: FourScoreKind) -> Self {
+        FourScore {
+            kind,
+            pendingState: [0; 4],
+            state: [0; 4],
+            readIdx: [0; 2],
+            strobe: false,
+        }
+    }
+
+    pub fn getKind(&self) -> FourScoreKind {
+        return self.kind;
+    }
+
+    // buttons take effect at the next latch, like Controller::setButtons
+    pub fn setButtons(&mut self, player: u8, buttons: ButtonState) -> () {
+        if let Some(state) = self.pendingState.get_mut(player as usize) {
+            *state = buttons.toByte();
+        }
+    }
+
+    pub fn getButtons(&self, player: u8) -> ButtonState {
+        return ButtonState::fromByte(*self.pendingState.get(player as usize).unwrap_or(&0));
+    }
+
+    pub fn latch(&mut self) -> () {
+        self.state = self.pendingState;
+    }
+
+    pub fn saveState(&self) -> FourScoreData {
+        FourScoreData {
+            state: self.state,
+            readIdx: self.readIdx,
+            strobe: self.strobe,
+        }
+    }
+
+    pub fn loadState(&mut self, data: &FourScoreData) -> () {
+        self.state = data.state;
+        self.readIdx = data.readIdx;
+        self.strobe = data.strobe;
+    }
+
+    pub fn writeState(&mut self, val: u8) -> () {
+        self.strobe = val & 1 == 1;
+        if self.strobe {
+            self.readIdx = [0; 2];
+        }
+    }
+
+    // `port` is 0 for $4016 and 1 for $4017. Only the low bits are returned.
+    pub fn getState(&mut self, port: u8) -> u8 {
+        let port = port as usize & 1;
+        if self.strobe {
+            self.readIdx[port] = 0;
+        }
+
+        let idx = self.readIdx[port];
+        let value = match self.kind {
+            FourScoreKind::Nes => {
+                let report = self.state[port] as u32 | (self.state[port + 2] as u32) << 8 | SIGNATURES[port] << 16;
+                if idx < REPORT_BITS { ((report >> idx) & 1) as u8 } else { 1 }
+            }
+            FourScoreKind::Famicom => {
+                if idx < 8 {
+                    let low = (self.state[port] >> idx) & 1;
+                    let high = (self.state[port + 2] >> idx) & 1;
+                    low | high << 1
+                }
+                else {
+                    0x03
+                }
+            }
+        };
+
+        if !self.strobe && idx < REPORT_BITS {
+            self.readIdx[port] += 1;
+        }
+        return value;
+    }
+}
+
+#[cfg(test)]
+mod FourScoreSpec {
+    use super::*;
+
+    fn pads(fourScore: &mut FourScore) -> () {
+        for player in 0..4 {
+            fourScore.setButtons(player, ButtonState::fromByte(1 << player));
+        }
+        fourScore.latch();
+        fourScore.writeState(1);
+        fourScore.writeState(0);
+    }
+
+    #[test]
+    fn nesSendsSignature() -> () {
+        let mut fourScore = FourScore::new(FourScoreKind::Nes);
+        pads(&mut fourScore);
+
+        let first: u32 = (0..24).map(|idx| (fourScore.getState(0) as u32) << idx).sum();
+        let second: u32 = (0..24).map(|idx| (fourScore.getState(1) as u32) << idx).sum();
+        assert_eq!(first, 0x08_04_01);
+        assert_eq!(second, 0x04_08_02);
+        assert_eq!(fourScore.getState(0), 1);
+    }
+
+    #[test]
+    fn famicomUsesSecondBit() -> () {
+        let mut fourScore = FourScore::new(FourScoreKind::Famicom);
+        pads(&mut fourScore);
+
+        let first: Vec<u8> = (0..3).map(|_| fourScore.getState(0)).collect();
+        assert_eq!(first, vec![0x01, 0x00, 0x02]);
+    }
+}
@@ -0,0 +1,137 @@
+#![allow(non_snake_case)]
+#![allow(warnings)]
+
+use std::time::{Duration, Instant};
+
+// if we fall further behind than this, give up on catching up and start fresh
+const MAX_CATCH_UP_FRAMES: u32 = 4;
+
+// keep roughly this many frames of audio queued up for the sound card
+const TARGET_AUDIO_FRAMES: f64 = 3.0;
+
+// never stretch or squash audio by more than half a percent, nobody can hear that
+const MAX_RATE_ADJUST: f64 = 0.005;
+
+// Schedules emulated frames off a monotonic clock so speed doesn't depend on how often the window gets events.
+pub struct FramePacer {
+    frameRate: f64,
+    speed: f64,
+    frameDuration: Duration,
+    nextFrame: Instant,
+}
+
+impl FramePacer {
+    pub fn new(frameRate: f64) -> Self {
+        FramePacer {
+            frameRate,
+            speed: 1.0,
+            frameDuration: Duration::from_secs_f64(1.0 / frameRate),
+            nextFrame: Instant::now(),
+        }
+    }
+
+    pub fn setFrameRate(&mut self, frameRate: f64) -> () {
+        self.frameRate = frameRate;
+        self.updateFrameDuration();
+    }
+
+    // multiplier on the frame rate, e.g. 4.0 for fast-forward or 0.25 for slow motion
+    pub fn setSpeed(&mut self, speed: f64) -> () {
+        self.speed = speed;
+        self.updateFrameDuration();
+    }
+
+    pub fn getSpeed(&self) -> f64 {
+        return self.speed;
+    }
+
+    fn updateFrameDuration(&mut self) -> () {
+        self.frameDuration = Duration::from_secs_f64(1.0 / (self.frameRate * self.speed));
+    }
+
+    pub fn reset(&mut self, now: Instant) -> () {
+        self.nextFrame = now;
+    }
+
+    // How many frames should be emulated at `now`. Usually 0 or 1, more if we're running late.
+    pub fn framesDue(&mut self, now: Instant) -> u32 {
+        if now < self.nextFrame {
+            return 0;
+        }
+
+        let behind = (now - self.nextFrame).as_secs_f64();
+        let mut frames = 1 + (behind / self.frameDuration.as_secs_f64()) as u32;
+
+        // e.g. the window was dragged or a file dialog was open
+        if frames > MAX_CATCH_UP_FRAMES {
+            frames = 1;
+            self.nextFrame = now;
+        }
+
+        self.nextFrame += self.frameDuration * frames;
+        return frames;
+    }
+
+    pub fn getNextFrame(&self) -> Instant {
+        return self.nextFrame;
+    }
+}
+
+// Dynamic rate control: returns a factor for the audio sample rate that nudges the output queue
+// back towards its target depth. Above 1 means make more samples, below 1 means make fewer.
+pub fn audioRateAdjust(queuedSamples: usize, samplesPerFrame: f64) -> f64 {
+    let target = samplesPerFrame * TARGET_AUDIO_FRAMES;
+    let error = ((target - queuedSamples as f64) / target).max(-1.0).min(1.0);
+    return 1.0 + error * MAX_RATE_ADJUST;
+}
+
+#[cfg(test)]
+mod FramePacerSpec {
+    use super::*;
+
+    #[test]
+    fn schedulesFromClock() -> () {
+        let start = Instant::now();
+        let mut pacer = FramePacer::new(50.0);
+        pacer.reset(start);
+
+        assert_eq!(pacer.framesDue(start), 1);
+        assert_eq!(pacer.framesDue(start + Duration::from_millis(10)), 0);
+        assert_eq!(pacer.framesDue(start + Duration::from_millis(20)), 1);
+        assert_eq!(pacer.framesDue(start + Duration::from_millis(65)), 2);
+        assert_eq!(pacer.getNextFrame(), start + Duration::from_millis(80));
+    }
+
+    #[test]
+    fn resyncsWhenFarBehind() -> () {
+        let start = Instant::now();
+        let mut pacer = FramePacer::new(50.0);
+        pacer.reset(start);
+
+        let late = start + Duration::from_secs(2);
+        assert_eq!(pacer.framesDue(late), 1);
+        assert_eq!(pacer.getNextFrame(), late + Duration::from_millis(20));
+    }
+
+    #[test]
+    fn speedScalesFrameRate() -> () {
+        let start = Instant::now();
+        let mut pacer = FramePacer::new(50.0);
+        pacer.setSpeed(4.0);
+        pacer.reset(start);
+
+        assert_eq!(pacer.framesDue(start + Duration::from_millis(17)), 4);
+
+        pacer.setSpeed(0.5);
+        pacer.reset(start);
+        assert_eq!(pacer.framesDue(start), 1);
+        assert_eq!(pacer.getNextFrame(), start + Duration::from_millis(40));
+    }
+
+    #[test]
+    fn audioRateFollowsQueue() -> () {
+        assert!(audioRateAdjust(0, 735.0) > 1.0);
+        assert!(audioRateAdjust(100000, 735.0) < 1.0);
+        assert_eq!(audioRateAdjust(2205, 735.0), 1.0);
+    }
+}
@@ -1,13 +1,22 @@
 #![allow(non_snake_case)]
 
 use std::cell::RefCell;
+use std::num::NonZeroU32;
 use std::rc::Rc;
-use egui::{ClippedMesh, CtxRef};
+use std::time::SystemTime;
+use egui::{ClippedMesh, CtxRef, TextureId};
 use egui_wgpu_backend::{BackendError, ScreenDescriptor};
 use egui_wgpu_backend::RenderPass;
 use pixels::PixelsContext;
+use winit::event::{ElementState, VirtualKeyCode};
 use winit::window::Window;
 use crate::gui_commands::GuiCommands;
+use crate::input_setup::InputSetup;
+use crate::keyboard_input::BUTTON_NAMES;
+use crate::settings::{KeyTarget, Settings, HOTKEY_NAMES};
+use crate::debugger::{DebugCommand, DebugView, Watchpoint};
+use crate::trace_logger::TraceFilter;
+use crate::state_slots::{formatAge, SlotInfo, NUM_SLOTS, THUMBNAIL_HEIGHT, THUMBNAIL_WIDTH};
 
 pub struct Gui {
     context: CtxRef,
@@ -15,7 +24,11 @@ pub struct Gui {
     descriptor: ScreenDescriptor,
     renderPass: RenderPass,
     meshes: Vec<ClippedMesh>,
-    components: GuiComponents
+    components: GuiComponents,
+
+    // slot thumbnails waiting to be uploaded, and the textures already on the GPU
+    pendingThumbnails: Vec<(usize, Option<Vec<u8>>)>,
+    thumbnails: Vec<Option<(wgpu::Texture, TextureId)>>
 }
 
 impl Gui {
@@ -30,7 +43,9 @@ impl Gui {
             },
             renderPass: RenderPass::new(pixels.device(), pixels.render_texture_format(), 1),
             meshes: Vec::new(),
-            components: GuiComponents::new(commands)
+            components: GuiComponents::new(commands),
+            pendingThumbnails: Vec::new(),
+            thumbnails: (0..NUM_SLOTS).map(|_| None).collect()
         }
     }
 
@@ -46,27 +61,241 @@ impl Gui {
 
     pub fn render(&mut self, encoder: &mut wgpu::CommandEncoder, target: &wgpu::TextureView, context: &PixelsContext) -> Result<(), BackendError> {
         self.renderPass.update_texture(&context.device, &context.queue, &self.context.font_image());
+        self.uploadThumbnails(context);
         self.renderPass.update_user_textures(&context.device, &context.queue);
         self.renderPass.update_buffers(&context.device, &context.queue, &self.meshes, &self.descriptor);
         return self.renderPass.execute(encoder, target, &self.meshes,&self.descriptor, None);
     }
 
     pub fn handleEvent(&mut self, event: &winit::event::WindowEvent) -> () {
+        // while waiting for a key to bind, key presses go to the dialog rather than egui. Capturing only
+        // ends once the key is released, so the press doesn't reach the game or the hotkeys either.
+        if let (Some(target), winit::event::WindowEvent::KeyboardInput { input, .. }) = (self.components.capturing, event) {
+            let key = match input.virtual_keycode {
+                Some(key) => key,
+                None => { return; }
+            };
+
+            match (input.state, self.components.capturedKey) {
+                (ElementState::Pressed, None) => {
+                    // Escape backs out without changing anything
+                    if key != VirtualKeyCode::Escape {
+                        *self.components.commands.borrow_mut() = GuiCommands::SetKey(target, key);
+                    }
+                    self.components.capturedKey = Some(key);
+                }
+                (ElementState::Released, Some(captured)) if captured == key => {
+                    self.components.capturing = None;
+                    self.components.capturedKey = None;
+                }
+                _ => {}
+            }
+            return;
+        }
+
         self.state.on_event(&self.context, event);
     }
 
+    pub fn isCapturingKey(&self) -> bool {
+        return self.components.capturing.is_some();
+    }
+
+    // None while the debugger is off
+    pub fn setDebugView(&mut self, view: Option<DebugView>) -> () {
+        self.components.debugView = view;
+    }
+
+    // closes the debugger window without asking for the debugger to be turned off
+    pub fn hideDebugger(&mut self) -> () {
+        self.components.debuggerVisible = false;
+        self.components.debugView = None;
+    }
+
+    // lines logged so far, None while not tracing
+    pub fn setTraceStatus(&mut self, lines: Option<u64>) -> () {
+        self.components.traceLines = lines;
+    }
+
+    // the bindings shown in the rebinding dialog
+    pub fn setSettings(&mut self, settings: &Settings) -> () {
+        self.components.settings = settings.clone();
+    }
+
+    // true while the mouse is over a menu or window, so clicks there aren't game input
+    pub fn wantsPointer(&self) -> bool {
+        return self.context.is_pointer_over_area() || self.context.wants_pointer_input();
+    }
+
+    pub fn showError(&mut self, message: String) -> () {
+        self.components.errorMessage = message;
+        self.components.errorVisible = true;
+    }
+
+    // `status` is the frame counter text, None hides it
+    pub fn setMovieStatus(&mut self, status: Option<String>, readOnly: bool) -> () {
+        self.components.movieStatus = status;
+        self.components.movieReadOnly = readOnly;
+    }
+
+    pub fn setEmulationState(&mut self, paused: bool, speedPercent: u32, fastForward: bool) -> () {
+        self.components.paused = paused;
+        self.components.speedPercent = speedPercent;
+        self.components.fastForward = fastForward;
+    }
+
+    pub fn setInputSetup(&mut self, setup: InputSetup) -> () {
+        self.components.inputSetup = setup;
+    }
+
+    // `slots` holds whatever is in each slot for the running game, or all None on the splash screen
+    pub fn setSlots(&mut self, slots: Vec<Option<SlotInfo>>, currentSlot: usize, canUndo: bool) -> () {
+        for (idx, info) in slots.into_iter().enumerate() {
+            self.components.slotTimes[idx] = info.as_ref().map(|info| info.modified);
+            self.pendingThumbnails.push((idx, info.and_then(|info| info.thumbnail)));
+        }
+        self.components.currentSlot = currentSlot;
+        self.components.canUndo = canUndo;
+    }
+
+    fn uploadThumbnails(&mut self, context: &PixelsContext) -> () {
+        for (idx, thumbnail) in std::mem::take(&mut self.pendingThumbnails) {
+            let pixels = match thumbnail {
+                Some(pixels) => pixels,
+                None => {
+                    self.components.slotThumbnails[idx] = None;
+                    continue;
+                }
+            };
+
+            let size = wgpu::Extent3d {
+                width: THUMBNAIL_WIDTH as u32,
+                height: THUMBNAIL_HEIGHT as u32,
+                depth_or_array_layers: 1,
+            };
+
+            let texture = context.device.create_texture(&wgpu::TextureDescriptor {
+                label: Some(format!("slot{}_thumbnail", idx).as_str()),
+                size,
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: wgpu::TextureFormat::Rgba8UnormSrgb,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            });
+
+            context.queue.write_texture(
+                wgpu::ImageCopyTexture {
+                    texture: &texture,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d::ZERO,
+                    aspect: wgpu::TextureAspect::All,
+                },
+                pixels.as_slice(),
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: NonZeroU32::new(THUMBNAIL_WIDTH as u32 * 4),
+                    rows_per_image: NonZeroU32::new(THUMBNAIL_HEIGHT as u32),
+                },
+                size,
+            );
+
+            // reuse the slot's texture id so egui doesn't leak one per save
+            let id = match self.thumbnails[idx].take() {
+                Some((_, id)) => {
+                    self.renderPass.update_egui_texture_from_wgpu_texture(&context.device, &texture, wgpu::FilterMode::Nearest, id);
+                    id
+                }
+                None => self.renderPass.egui_texture_from_wgpu_texture(&context.device, &texture, wgpu::FilterMode::Nearest)
+            };
+
+            self.thumbnails[idx] = Some((texture, id));
+            self.components.slotThumbnails[idx] = Some(id);
+        }
+    }
+
 
 }
 
 struct GuiComponents {
     aboutVisible: bool,
+    errorVisible: bool,
+    errorMessage: String,
+    slotsVisible: bool,
+    slotTimes: Vec<Option<SystemTime>>,
+    slotThumbnails: Vec<Option<TextureId>>,
+    currentSlot: usize,
+    canUndo: bool,
+    movieStatus: Option<String>,
+    movieReadOnly: bool,
+    paused: bool,
+    speedPercent: u32,
+    fastForward: bool,
+    inputSetup: InputSetup,
+    bindingsVisible: bool,
+    bindingsPage: usize,    // players 1-4, then the hotkeys
+    settings: Settings,
+    capturing: Option<KeyTarget>,
+    capturedKey: Option<VirtualKeyCode>,
+    debuggerVisible: bool,
+    debugView: Option<DebugView>,
+    debugInput: DebugInput,
+    traceVisible: bool,
+    traceLines: Option<u64>,
+    traceInput: TraceInput,
     commands: Rc<RefCell<GuiCommands>>
 }
 
+// what's been typed into the debugger window's breakpoint and watchpoint fields
+#[derive(Default)]
+struct DebugInput {
+    breakpoint: String,
+    watchStart: String,
+    watchEnd: String,
+    watchRead: bool,
+    watchWrite: bool,
+    watchExecute: bool,
+}
+
+// the trace logger window's filter fields, blank for no limit
+#[derive(Default)]
+struct TraceInput {
+    start: String,
+    end: String,
+    maxLines: String,
+}
+
+fn parseAddress(text: &str) -> Option<u16> {
+    return u16::from_str_radix(text.trim().trim_start_matches('$'), 16).ok();
+}
+
 impl GuiComponents {
     pub fn new(commands: Rc<RefCell<GuiCommands>>) -> Self {
         GuiComponents {
             aboutVisible: false,
+            errorVisible: false,
+            errorMessage: String::new(),
+            slotsVisible: false,
+            slotTimes: vec![None; NUM_SLOTS],
+            slotThumbnails: vec![None; NUM_SLOTS],
+            currentSlot: 0,
+            canUndo: false,
+            movieStatus: None,
+            movieReadOnly: true,
+            paused: false,
+            speedPercent: 100,
+            fastForward: false,
+            inputSetup: InputSetup::Standard,
+            bindingsVisible: false,
+            bindingsPage: 0,
+            settings: Settings::default(),
+            capturing: None,
+            capturedKey: None,
+            debuggerVisible: false,
+            debugView: None,
+            debugInput: DebugInput { watchWrite: true, ..DebugInput::default() },
+            traceVisible: false,
+            traceLines: None,
+            traceInput: TraceInput::default(),
             commands
         }
     }
@@ -98,8 +327,119 @@ impl GuiComponents {
 
                     ui.separator();
 
+                    if ui.button("Quick Slots...").clicked() {
+                        self.slotsVisible = true;
+                        ui.close_menu();
+                    }
+
+                    if ui.add_enabled(self.canUndo, egui::Button::new("Undo Load")).clicked() {
+                        *self.commands.borrow_mut() = GuiCommands::UndoLoad;
+                        ui.close_menu();
+                    }
+
+                    ui.separator();
+
                     if ui.button("Quit").clicked() {
-                        std::process::exit(0);
+                        *self.commands.borrow_mut() = GuiCommands::Quit;
+                        ui.close_menu();
+                    }
+                });
+
+                ui.menu_button("Emulation", |ui| {
+                    let mut paused = self.paused;
+                    if ui.checkbox(&mut paused, "Pause").changed() {
+                        *self.commands.borrow_mut() = GuiCommands::TogglePause;
+                    }
+
+                    if ui.button("Frame Advance").clicked() {
+                        *self.commands.borrow_mut() = GuiCommands::FrameAdvance;
+                    }
+
+                    ui.separator();
+
+                    let mut fastForward = self.fastForward;
+                    if ui.checkbox(&mut fastForward, "Fast Forward").changed() {
+                        *self.commands.borrow_mut() = GuiCommands::SetFastForward(fastForward);
+                    }
+
+                    for percent in [100, 50, 25] {
+                        if ui.radio(self.speedPercent == percent, format!("{}% Speed", percent)).clicked() {
+                            *self.commands.borrow_mut() = GuiCommands::SetSpeed(percent);
+                            ui.close_menu();
+                        }
+                    }
+                });
+
+                ui.menu_button("Input", |ui| {
+                    let setups = [
+                        (InputSetup::Standard, "Two Controllers"),
+                        (InputSetup::FourScore, "NES Four Score"),
+                        (InputSetup::FamicomFourPlayer, "Famicom Four-Player Adapter"),
+                        (InputSetup::Zapper, "Zapper"),
+                        (InputSetup::Arkanoid, "Arkanoid Paddle"),
+                        (InputSetup::FamicomArkanoid, "Famicom Arkanoid Paddle"),
+                    ];
+                    for (setup, name) in setups {
+                        if ui.radio(self.inputSetup == setup, name).clicked() {
+                            *self.commands.borrow_mut() = GuiCommands::SetInputSetup(setup);
+                            ui.close_menu();
+                        }
+                    }
+
+                    ui.separator();
+
+                    if ui.button("Key Bindings...").clicked() {
+                        self.bindingsVisible = true;
+                        ui.close_menu();
+                    }
+
+                    ui.separator();
+
+                    for rate in [30, 20, 15] {
+                        if ui.radio(self.settings.turboRate == rate, format!("Turbo {} Hz", rate)).clicked() {
+                            *self.commands.borrow_mut() = GuiCommands::SetTurboRate(rate);
+                            ui.close_menu();
+                        }
+                    }
+                });
+
+                ui.menu_button("Debug", |ui| {
+                    if ui.checkbox(&mut self.debuggerVisible, "Debugger").changed() {
+                        *self.commands.borrow_mut() = GuiCommands::ShowDebugger(self.debuggerVisible);
+                        ui.close_menu();
+                    }
+
+                    if ui.checkbox(&mut self.traceVisible, "Trace Logger").changed() {
+                        ui.close_menu();
+                    }
+                });
+
+                ui.menu_button("Movie", |ui| {
+                    if ui.button("Record from Power On...").clicked() {
+                        *self.commands.borrow_mut() = GuiCommands::RecordMovie(true);
+                        ui.close_menu();
+                    }
+
+                    if ui.button("Record from Here...").clicked() {
+                        *self.commands.borrow_mut() = GuiCommands::RecordMovie(false);
+                        ui.close_menu();
+                    }
+
+                    if ui.button("Play...").clicked() {
+                        *self.commands.borrow_mut() = GuiCommands::PlayMovie;
+                        ui.close_menu();
+                    }
+
+                    if ui.add_enabled(self.movieStatus.is_some(), egui::Button::new("Stop")).clicked() {
+                        *self.commands.borrow_mut() = GuiCommands::StopMovie;
+                        ui.close_menu();
+                    }
+
+                    ui.separator();
+
+                    let mut readOnly = self.movieReadOnly;
+                    if ui.checkbox(&mut readOnly, "Read-Only").changed() {
+                        *self.commands.borrow_mut() = GuiCommands::SetMovieReadOnly(readOnly);
                     }
                 });
             })
@@ -107,11 +447,304 @@ impl GuiComponents {
 
 
 
+        let mut status: Vec<String> = Vec::new();
+        if self.paused {
+            status.push(String::from("PAUSED"));
+        }
+        else if self.fastForward {
+            status.push(String::from(">>"));
+        }
+        else if self.speedPercent != 100 {
+            status.push(format!("{}%", self.speedPercent));
+        }
+        status.extend(self.movieStatus.clone());
+        if self.traceLines.is_some() {
+            status.push(String::from("TRACING"));
+        }
+
+        if !status.is_empty() {
+            egui::Area::new("status")
+                .anchor(egui::Align2::RIGHT_BOTTOM, [-8.0, -8.0])
+                .interactable(false)
+                .show(context, |ui| {
+                    for line in status {
+                        ui.label(egui::RichText::new(line).monospace().color(egui::Color32::WHITE).background_color(egui::Color32::from_black_alpha(160)));
+                    }
+                });
+        }
+
         egui::Window::new("Welcome to RustyNES!")
             .open(&mut self.aboutVisible)
             .show(context, |ui| {
                 ui.label("This is a hobby NES emulator written in Rust.");
                 ui.label("Thanks, and have fun!")
             });
+
+        let now = SystemTime::now();
+        let commands = self.commands.clone();
+        let slotTimes = &self.slotTimes;
+        let slotThumbnails = &self.slotThumbnails;
+        let currentSlot = self.currentSlot;
+        egui::Window::new("Quick Slots")
+            .open(&mut self.slotsVisible)
+            .vscroll(true)
+            .show(context, |ui| {
+                egui::Grid::new("slots").striped(true).show(ui, |ui| {
+                    for slot in 0..NUM_SLOTS {
+                        let name = format!("{}Slot {}", if slot == currentSlot { "> " } else { "" }, slot);
+                        ui.label(name);
+
+                        match slotThumbnails[slot] {
+                            Some(id) => { ui.image(id, [THUMBNAIL_WIDTH as f32 / 2.0, THUMBNAIL_HEIGHT as f32 / 2.0]); }
+                            None => { ui.label(""); }
+                        }
+
+                        match slotTimes[slot] {
+                            Some(modified) => { ui.label(formatAge(modified, now)); }
+                            None => { ui.label("empty"); }
+                        }
+
+                        if ui.button("Save").clicked() {
+                            *commands.borrow_mut() = GuiCommands::SaveSlot(slot);
+                        }
+                        if ui.add_enabled(slotTimes[slot].is_some(), egui::Button::new("Load")).clicked() {
+                            *commands.borrow_mut() = GuiCommands::LoadSlot(slot);
+                        }
+                        ui.end_row();
+                    }
+                });
+            });
+
+        let commands = self.commands.clone();
+        let settings = &self.settings;
+        let page = &mut self.bindingsPage;
+        let capturing = &mut self.capturing;
+        egui::Window::new("Key Bindings")
+            .open(&mut self.bindingsVisible)
+            .show(context, |ui| {
+                ui.horizontal(|ui| {
+                    for player in 0..4 {
+                        ui.selectable_value(page, player, format!("Player {}", player + 1));
+                    }
+                    ui.selectable_value(page, 4, "Hotkeys");
+                });
+                ui.separator();
+
+                let targets: Vec<(&str, KeyTarget)> = if *page < 4 {
+                    BUTTON_NAMES.iter().enumerate().map(|(button, name)| (*name, KeyTarget::Button(*page as u8, button))).collect()
+                }
+                else {
+                    HOTKEY_NAMES.iter().enumerate().map(|(hotkey, name)| (*name, KeyTarget::Hotkey(hotkey))).collect()
+                };
+
+                egui::Grid::new("bindings").striped(true).show(ui, |ui| {
+                    for (name, target) in targets {
+                        ui.label(name);
+                        let text = match (*capturing == Some(target), settings.getKey(target)) {
+                            (true, _) => String::from("Press a key..."),
+                            (false, Some(key)) => format!("{:?}", key),
+                            (false, None) => String::new(),
+                        };
+                        if ui.button(text).clicked() {
+                            *capturing = Some(target);
+                        }
+                        ui.end_row();
+                    }
+                });
+
+                ui.separator();
+                if ui.button("Reset to Defaults").clicked() {
+                    *commands.borrow_mut() = GuiCommands::ResetKeys;
+                }
+            });
+        if !self.bindingsVisible {
+            self.capturing = None;
+            self.capturedKey = None;
+        }
+
+        self.buildDebugger(context);
+        self.buildTraceLogger(context);
+
+        egui::Window::new("Error")
+            .open(&mut self.errorVisible)
+            .show(context, |ui| {
+                ui.label(&self.errorMessage);
+            });
+    }
+
+    fn buildDebugger(&mut self, context: &CtxRef) -> () {
+        let wasVisible = self.debuggerVisible;
+        let commands = self.commands.clone();
+        let send = |command: DebugCommand| *commands.borrow_mut() = GuiCommands::Debug(command);
+        let view = &self.debugView;
+        let input = &mut self.debugInput;
+
+        egui::Window::new("Debugger")
+            .open(&mut self.debuggerVisible)
+            .default_width(420.0)
+            .show(context, |ui| {
+                let view = match view {
+                    Some(view) => view,
+                    None => { return; }
+                };
+                let paused = view.breakReason.is_some();
+
+                ui.horizontal(|ui| {
+                    if ui.add_enabled(paused, egui::Button::new("Continue")).clicked() {
+                        send(DebugCommand::Continue);
+                    }
+                    if ui.add_enabled(!paused, egui::Button::new("Pause")).clicked() {
+                        send(DebugCommand::Pause);
+                    }
+                    if ui.add_enabled(paused, egui::Button::new("Step Into")).clicked() {
+                        send(DebugCommand::StepInto);
+                    }
+                    if ui.add_enabled(paused, egui::Button::new("Step Over")).clicked() {
+                        send(DebugCommand::StepOver);
+                    }
+                    if ui.add_enabled(paused, egui::Button::new("Step Out")).clicked() {
+                        send(DebugCommand::StepOut);
+                    }
+                });
+                ui.label(view.breakReason.map_or(String::from("Running"), |reason| reason.to_string()));
+                ui.separator();
+
+                let registers = view.registers;
+                let flags: String = "NV-BDIZC".chars().enumerate()
+                    .map(|(bit, flag)| if registers.p & (0x80 >> bit) != 0 { flag } else { flag.to_ascii_lowercase() })
+                    .collect();
+                ui.monospace(format!("A:{:02X} X:{:02X} Y:{:02X} SP:{:02X} PC:{:04X}  P:{:02X} {}",
+                    registers.a, registers.x, registers.y, registers.sp, registers.pc, registers.p, flags));
+                ui.separator();
+
+                // click the gutter to toggle a breakpoint, or the arrow to run to that line
+                egui::Grid::new("disassembly").striped(true).show(ui, |ui| {
+                    for line in view.lines.iter() {
+                        if ui.small_button(if line.breakpoint { "●" } else { "○" }).clicked() {
+                            send(DebugCommand::ToggleBreakpoint(line.address));
+                        }
+                        if ui.add_enabled(paused && !line.current, egui::Button::new("▶").small()).clicked() {
+                            send(DebugCommand::RunTo(line.address));
+                        }
+                        let text = format!("{} {:04X}  {:<8}  {}", if line.current { ">" } else { " " }, line.address, line.bytes, line.text);
+                        ui.monospace(text);
+                        ui.end_row();
+                    }
+                });
+
+                egui::CollapsingHeader::new("Stack").show(ui, |ui| {
+                    for (row, bytes) in view.stack.chunks(16).enumerate() {
+                        let cells: Vec<String> = bytes.iter().enumerate().map(|(col, byte)| {
+                            let marker = if (row * 16 + col) as u8 == registers.sp { "*" } else { " " };
+                            format!("{}{:02X}", marker, byte)
+                        }).collect();
+                        ui.monospace(format!("{:04X}:{}", 0x0100 + row * 16, cells.concat()));
+                    }
+                });
+
+                egui::CollapsingHeader::new("Breakpoints").default_open(true).show(ui, |ui| {
+                    for addr in view.breakpoints.iter() {
+                        ui.horizontal(|ui| {
+                            ui.monospace(format!("${:04X}", addr));
+                            if ui.small_button("Remove").clicked() {
+                                send(DebugCommand::ToggleBreakpoint(*addr));
+                            }
+                        });
+                    }
+                    ui.horizontal(|ui| {
+                        ui.add(egui::TextEdit::singleline(&mut input.breakpoint).desired_width(60.0).hint_text("C000"));
+                        if ui.button("Add").clicked() {
+                            if let Some(addr) = parseAddress(&input.breakpoint).filter(|addr| !view.breakpoints.contains(addr)) {
+                                send(DebugCommand::ToggleBreakpoint(addr));
+                                input.breakpoint.clear();
+                            }
+                        }
+                    });
+                });
+
+                egui::CollapsingHeader::new("Watchpoints").default_open(true).show(ui, |ui| {
+                    for (idx, watch) in view.watchpoints.iter().enumerate() {
+                        ui.horizontal(|ui| {
+                            let kinds: String = [(watch.read, 'R'), (watch.write, 'W'), (watch.execute, 'X')].iter()
+                                .map(|(on, kind)| if *on { *kind } else { '-' })
+                                .collect();
+                            ui.monospace(format!("${:04X}-${:04X} {}", watch.start, watch.end, kinds));
+                            if ui.small_button("Remove").clicked() {
+                                send(DebugCommand::RemoveWatchpoint(idx));
+                            }
+                        });
+                    }
+                    ui.horizontal(|ui| {
+                        ui.add(egui::TextEdit::singleline(&mut input.watchStart).desired_width(60.0).hint_text("0300"));
+                        ui.label("to");
+                        ui.add(egui::TextEdit::singleline(&mut input.watchEnd).desired_width(60.0).hint_text("same"));
+                        ui.checkbox(&mut input.watchRead, "R");
+                        ui.checkbox(&mut input.watchWrite, "W");
+                        ui.checkbox(&mut input.watchExecute, "X");
+                        if ui.button("Add").clicked() {
+                            if let Some(start) = parseAddress(&input.watchStart) {
+                                let end = parseAddress(&input.watchEnd).unwrap_or(start).max(start);
+                                send(DebugCommand::AddWatchpoint(Watchpoint {
+                                    start,
+                                    end,
+                                    read: input.watchRead,
+                                    write: input.watchWrite,
+                                    execute: input.watchExecute,
+                                }));
+                                input.watchStart.clear();
+                                input.watchEnd.clear();
+                            }
+                        }
+                    });
+                });
+            });
+
+        // closing the window turns the debugger off
+        if wasVisible && !self.debuggerVisible {
+            *self.commands.borrow_mut() = GuiCommands::ShowDebugger(false);
+        }
+    }
+
+    fn buildTraceLogger(&mut self, context: &CtxRef) -> () {
+        let commands = &self.commands;
+        let traceLines = self.traceLines;
+        let input = &mut self.traceInput;
+
+        egui::Window::new("Trace Logger")
+            .open(&mut self.traceVisible)
+            .resizable(false)
+            .show(context, |ui| {
+                ui.label("Logs every instruction in the format of nestest.log");
+                egui::Grid::new("trace filter").show(ui, |ui| {
+                    ui.label("From");
+                    ui.add_enabled(traceLines.is_none(), egui::TextEdit::singleline(&mut input.start).desired_width(60.0).hint_text("0000"));
+                    ui.label("to");
+                    ui.add_enabled(traceLines.is_none(), egui::TextEdit::singleline(&mut input.end).desired_width(60.0).hint_text("FFFF"));
+                    ui.end_row();
+
+                    ui.label("Max lines");
+                    ui.add_enabled(traceLines.is_none(), egui::TextEdit::singleline(&mut input.maxLines).desired_width(60.0).hint_text("no limit"));
+                    ui.end_row();
+                });
+
+                match traceLines {
+                    Some(lines) => {
+                        ui.label(format!("{} lines written", lines));
+                        if ui.button("Stop").clicked() {
+                            *commands.borrow_mut() = GuiCommands::StopTrace;
+                        }
+                    }
+                    None => {
+                        if ui.button("Start...").clicked() {
+                            let defaults = TraceFilter::default();
+                            *commands.borrow_mut() = GuiCommands::StartTrace(TraceFilter {
+                                start: parseAddress(&input.start).unwrap_or(defaults.start),
+                                end: parseAddress(&input.end).unwrap_or(defaults.end),
+                                maxLines: input.maxLines.trim().parse().ok(),
+                            });
+                        }
+                    }
+                }
+            });
     }
 }
@@ -1,9 +1,36 @@
 #![allow(non_snake_case)]
 #![allow(warnings)]
 
+use winit::event::VirtualKeyCode;
+use crate::input_setup::InputSetup;
+use crate::settings::KeyTarget;
+use crate::debugger::DebugCommand;
+use crate::trace_logger::TraceFilter;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum GuiCommands {
     Default,
     LoadGame,
     SaveState,
-    LoadState
+    LoadState,
+    SaveSlot(usize),
+    LoadSlot(usize),
+    UndoLoad,
+    RecordMovie(bool),    // true to record from power-on
+    PlayMovie,
+    StopMovie,
+    SetMovieReadOnly(bool),
+    TogglePause,
+    FrameAdvance,
+    SetFastForward(bool),
+    SetSpeed(u32),    // percent, for slow motion
+    SetInputSetup(InputSetup),
+    SetKey(KeyTarget, VirtualKeyCode),
+    ResetKeys,    // back to the default bindings
+    SetTurboRate(u32),    // presses per second
+    ShowDebugger(bool),
+    Debug(DebugCommand),
+    StartTrace(TraceFilter),
+    StopTrace,
+    Quit
 }
\ No newline at end of file
@@ -0,0 +1,103 @@
+#![allow(non_snake_case)]
+#![allow(warnings)]
+
+use std::fmt;
+use std::str::FromStr;
+use serde::{Serialize, Deserialize};
+use crate::rom_header::RomHeader;
+
+// NES 2.0 default expansion device values we know how to emulate
+const EXPANSION_FOUR_SCORE: u8 = 0x02;
+const EXPANSION_FAMICOM_FOUR_PLAYER: u8 = 0x03;
+const EXPANSION_ZAPPER: u8 = 0x08;
+const EXPANSION_ARKANOID: u8 = 0x0F;
+const EXPANSION_FAMICOM_ARKANOID: u8 = 0x10;
+
+// What's plugged into the controller ports.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum InputSetup {
+    Auto,   // whatever the NES 2.0 header asks for, two controllers if it doesn't say
+    Standard,
+    FourScore,
+    FamicomFourPlayer,
+    Zapper,     // controller in port 1, Zapper in port 2
+    Arkanoid,   // controller in port 1, Vaus paddle in port 2
+    FamicomArkanoid,    // both controllers, Vaus paddle on the expansion port
+}
+
+impl InputSetup {
+    pub fn resolve(&self, header: &RomHeader) -> InputSetup {
+        return match self {
+            InputSetup::Auto => {
+                match header.defaultExpansionDevice {
+                    EXPANSION_FOUR_SCORE => InputSetup::FourScore,
+                    EXPANSION_FAMICOM_FOUR_PLAYER => InputSetup::FamicomFourPlayer,
+                    EXPANSION_ZAPPER => InputSetup::Zapper,
+                    EXPANSION_ARKANOID => InputSetup::Arkanoid,
+                    EXPANSION_FAMICOM_ARKANOID => InputSetup::FamicomArkanoid,
+                    _ => InputSetup::Standard,
+                }
+            }
+            setup => *setup,
+        };
+    }
+}
+
+impl Default for InputSetup {
+    fn default() -> Self {
+        InputSetup::Auto
+    }
+}
+
+impl fmt::Display for InputSetup {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            InputSetup::Auto => "auto",
+            InputSetup::Standard => "standard",
+            InputSetup::FourScore => "fourscore",
+            InputSetup::FamicomFourPlayer => "famicom4p",
+            InputSetup::Zapper => "zapper",
+            InputSetup::Arkanoid => "arkanoid",
+            InputSetup::FamicomArkanoid => "famicomarkanoid",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for InputSetup {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        return match s.to_lowercase().as_str() {
+            "auto" => Ok(InputSetup::Auto),
+            "standard" => Ok(InputSetup::Standard),
+            "fourscore" => Ok(InputSetup::FourScore),
+            "famicom4p" => Ok(InputSetup::FamicomFourPlayer),
+            "zapper" => Ok(InputSetup::Zapper),
+            "arkanoid" => Ok(InputSetup::Arkanoid),
+            "famicomarkanoid" => Ok(InputSetup::FamicomArkanoid),
+            _ => Err(format!("Unknown input setup '{}', expected auto, standard, fourscore, famicom4p, zapper, arkanoid or famicomarkanoid", s)),
+        };
+    }
+}
+
+#[cfg(test)]
+mod InputSetupSpec {
+    use super::*;
+
+    #[test]
+    fn autoFollowsHeader() -> () {
+        let mut bytes = [0x4E, 0x45, 0x53, 0x1A, 0x01, 0x01, 0x00, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02];
+        let header = RomHeader::fromBytes(&bytes).unwrap();
+        assert_eq!(InputSetup::Auto.resolve(&header), InputSetup::FourScore);
+        assert_eq!(InputSetup::Standard.resolve(&header), InputSetup::Standard);
+
+        bytes[15] = 0x10;
+        let header = RomHeader::fromBytes(&bytes).unwrap();
+        assert_eq!(InputSetup::Auto.resolve(&header), InputSetup::FamicomArkanoid);
+
+        bytes[15] = 0x01;
+        let header = RomHeader::fromBytes(&bytes).unwrap();
+        assert_eq!(InputSetup::Auto.resolve(&header), InputSetup::Standard);
+    }
+}
@@ -0,0 +1,231 @@
+#![allow(non_snake_case)]
+#![allow(warnings)]
+
+use serde::{Serialize, Deserialize};
+use winit::event::VirtualKeyCode;
+use winit_input_helper::WinitInputHelper;
+use crate::controller::{ButtonState, InputProvider};
+
+// in the order KeyBindings::getKey and setKey index them
+pub const BUTTON_NAMES: [&str; 10] = ["A", "B", "Select", "Start", "Up", "Down", "Left", "Right", "Turbo A", "Turbo B"];
+
+pub const DEFAULT_TURBO_RATE: u32 = 15;
+
+// Which key drives each button of one controller.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct KeyBindings {
+    pub a: VirtualKeyCode,
+    pub b: VirtualKeyCode,
+    pub select: VirtualKeyCode,
+    pub start: VirtualKeyCode,
+    pub up: VirtualKeyCode,
+    pub down: VirtualKeyCode,
+    pub left: VirtualKeyCode,
+    pub right: VirtualKeyCode,
+    // autofire, pressing and releasing the button at the turbo rate while held. Unbound by default
+    // past player 1, and missing from settings files written before turbo existed.
+    #[serde(default)]
+    pub turboA: Option<VirtualKeyCode>,
+    #[serde(default)]
+    pub turboB: Option<VirtualKeyCode>,
+}
+
+impl KeyBindings {
+    pub fn player1() -> Self {
+        KeyBindings {
+            a: VirtualKeyCode::Z,
+            b: VirtualKeyCode::X,
+            select: VirtualKeyCode::RShift,
+            start: VirtualKeyCode::Return,
+            up: VirtualKeyCode::Up,
+            down: VirtualKeyCode::Down,
+            left: VirtualKeyCode::Left,
+            right: VirtualKeyCode::Right,
+            turboA: Some(VirtualKeyCode::C),
+            turboB: Some(VirtualKeyCode::V),
+        }
+    }
+
+    pub fn player2() -> Self {
+        KeyBindings {
+            a: VirtualKeyCode::H,
+            b: VirtualKeyCode::G,
+            select: VirtualKeyCode::T,
+            start: VirtualKeyCode::Y,
+            up: VirtualKeyCode::W,
+            down: VirtualKeyCode::S,
+            left: VirtualKeyCode::A,
+            right: VirtualKeyCode::D,
+            turboA: None,
+            turboB: None,
+        }
+    }
+
+    pub fn player3() -> Self {
+        KeyBindings {
+            a: VirtualKeyCode::O,
+            b: VirtualKeyCode::U,
+            select: VirtualKeyCode::Key7,
+            start: VirtualKeyCode::Key8,
+            up: VirtualKeyCode::I,
+            down: VirtualKeyCode::K,
+            left: VirtualKeyCode::J,
+            right: VirtualKeyCode::L,
+            turboA: None,
+            turboB: None,
+        }
+    }
+
+    pub fn player4() -> Self {
+        KeyBindings {
+            a: VirtualKeyCode::Numpad3,
+            b: VirtualKeyCode::Numpad1,
+            select: VirtualKeyCode::Numpad7,
+            start: VirtualKeyCode::Numpad9,
+            up: VirtualKeyCode::Numpad8,
+            down: VirtualKeyCode::Numpad5,
+            left: VirtualKeyCode::Numpad4,
+            right: VirtualKeyCode::Numpad6,
+            turboA: None,
+            turboB: None,
+        }
+    }
+
+    pub fn getKey(&self, button: usize) -> Option<VirtualKeyCode> {
+        return match button {
+            0 => Some(self.a),
+            1 => Some(self.b),
+            2 => Some(self.select),
+            3 => Some(self.start),
+            4 => Some(self.up),
+            5 => Some(self.down),
+            6 => Some(self.left),
+            7 => Some(self.right),
+            8 => self.turboA,
+            9 => self.turboB,
+            _ => None,
+        };
+    }
+
+    pub fn setKey(&mut self, button: usize, key: VirtualKeyCode) -> () {
+        let slot = match button {
+            0 => &mut self.a,
+            1 => &mut self.b,
+            2 => &mut self.select,
+            3 => &mut self.start,
+            4 => &mut self.up,
+            5 => &mut self.down,
+            6 => &mut self.left,
+            7 => &mut self.right,
+            8 => { self.turboA = Some(key); return; }
+            9 => { self.turboB = Some(key); return; }
+            _ => { return; }
+        };
+        *slot = key;
+    }
+
+    fn read(&self, input: &WinitInputHelper) -> ButtonState {
+        return ButtonState {
+            a: input.key_held(self.a),
+            b: input.key_held(self.b),
+            select: input.key_held(self.select),
+            start: input.key_held(self.start),
+            up: input.key_held(self.up),
+            down: input.key_held(self.down),
+            left: input.key_held(self.left),
+            right: input.key_held(self.right),
+        };
+    }
+
+    // (turbo A, turbo B)
+    fn readTurbo(&self, input: &WinitInputHelper) -> (bool, bool) {
+        let held = |key: Option<VirtualKeyCode>| key.map_or(false, |key| input.key_held(key));
+        return (held(self.turboA), held(self.turboB));
+    }
+}
+
+// Turns winit key presses into the buttons of all four controllers.
+pub struct KeyboardInput {
+    bindings: [KeyBindings; 4],
+    buttons: [ButtonState; 4],
+    turboHeld: [(bool, bool); 4],
+    turboPeriod: u32,   // frames per press and release of a turbo button
+    turboFrame: u32,
+}
+
+impl KeyboardInput {
+    pub fn new() -> Self {
+        KeyboardInput {
+            bindings: [KeyBindings::player1(), KeyBindings::player2(), KeyBindings::player3(), KeyBindings::player4()],
+            buttons: [ButtonState::default(); 4],
+            turboHeld: [(false, false); 4],
+            turboPeriod: 4,
+            turboFrame: 0,
+        }
+    }
+
+    // `rate` is presses per second, rounded to a whole number of frames. A button has to be seen
+    // released for at least a frame to count as pressed again, so it can't go past half the frame rate.
+    pub fn setTurboRate(&mut self, rate: u32, frameRate: f64) -> () {
+        self.turboPeriod = (frameRate / rate.max(1) as f64).round().max(2.0) as u32;
+        self.turboFrame %= self.turboPeriod;
+    }
+
+    // Advances the turbo buttons by a frame. Call once before each frame's buttons are read.
+    pub fn nextFrame(&mut self) -> () {
+        self.turboFrame = (self.turboFrame + 1) % self.turboPeriod;
+    }
+
+    pub fn setBindings(&mut self, port: u8, bindings: KeyBindings) -> () {
+        if let Some(slot) = self.bindings.get_mut(port as usize) {
+            *slot = bindings;
+        }
+    }
+
+    pub fn getBindings(&self, port: u8) -> Option<KeyBindings> {
+        return self.bindings.get(port as usize).copied();
+    }
+
+    pub fn update(&mut self, input: &WinitInputHelper) -> () {
+        for port in 0..4 {
+            self.buttons[port] = self.bindings[port].read(input);
+            self.turboHeld[port] = self.bindings[port].readTurbo(input);
+        }
+    }
+}
+
+impl InputProvider for KeyboardInput {
+    fn getButtons(&mut self, port: u8) -> ButtonState {
+        let mut buttons = match self.buttons.get(port as usize) {
+            Some(buttons) => *buttons,
+            None => { return ButtonState::default(); }
+        };
+
+        // pressed for the first half of each period
+        let turboOn = self.turboFrame < self.turboPeriod / 2;
+        let (turboA, turboB) = self.turboHeld[port as usize];
+        buttons.a |= turboA && turboOn;
+        buttons.b |= turboB && turboOn;
+        return buttons;
+    }
+}
+
+#[cfg(test)]
+mod KeyboardInputSpec {
+    use super::*;
+
+    #[test]
+    fn turboTogglesAtRate() -> () {
+        let mut keyboard = KeyboardInput::new();
+        keyboard.setTurboRate(20, 60.0);
+        keyboard.turboHeld[1] = (true, false);
+
+        let presses: Vec<bool> = (0..6).map(|_| {
+            let buttons = keyboard.getButtons(1);
+            keyboard.nextFrame();
+            assert!(!buttons.b);
+            buttons.a
+        }).collect();
+        assert_eq!(presses, vec![true, false, false, true, false, false]);
+    }
+}
@@ -1,15 +1,32 @@
 pub mod clock;
 pub mod palette;
 pub mod controller;
+pub mod four_score;
+pub mod input_setup;
+pub mod zapper;
+pub mod arkanoid;
+pub mod keyboard_input;
+pub mod settings;
+pub mod cpu_bus;
 pub mod data_bus;
 pub mod ppu_bus;
 pub mod save_load;
+pub mod state_slots;
+pub mod rewind;
+pub mod movie;
 pub mod opcode_info;
+pub mod disasm;
 pub mod cpu;
+pub mod debugger;
+pub mod trace_logger;
 pub mod ppu;
 pub mod apu;
 pub mod cartridge;
+pub mod rom_header;
+pub mod region;
 pub mod mappers;
+pub mod nes;
+pub mod frame_pacer;
 pub mod console;
 pub mod gui;
 pub mod gui_commands;
\ No newline at end of file
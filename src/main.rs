@@ -1,9 +1,13 @@
 #[macro_use]
 extern crate clap;
 
-use clap::App;
-use rustynes::console::Console;
-use std::path::Path;
+use clap::{App, ArgMatches};
+use rustynes::apu::audio_sink::{AudioChannels, AudioFormat, NullAudioSink};
+use rustynes::console::{Console, ConsoleOptions};
+use rustynes::disasm;
+use rustynes::nes::Nes;
+use rustynes::region::Region;
+use std::path::{Path, PathBuf};
 use rustynes::clock::Clocked;
 use std::fs::File;
 
@@ -11,9 +15,56 @@ use std::fs::File;
 fn main() {
     let yaml = load_yaml!("./config/clap_args.yml");
     let matches = App::from_yaml(yaml).get_matches();
+    if let Some(matches) = matches.subcommand_matches("disasm") {
+        disassemble(matches);
+        return;
+    }
 
     let rom = matches.value_of("ROM");
     //let rom = Some("./loz.nes");
-    let console = Console::new(rom);
+    let options = ConsoleOptions {
+        rom: rom.map(PathBuf::from),
+        saveDirectory: matches.value_of("SAVE_DIR").map(PathBuf::from),
+        region: matches.value_of("REGION").unwrap().parse().unwrap(),
+        inputSetup: matches.value_of("INPUT").unwrap().parse().unwrap(),
+        audioSync: !matches.is_present("NO_AUDIO_SYNC"),
+        audioFormat: AudioFormat {
+            sampleRate: matches.value_of("SAMPLE_RATE").unwrap().parse().unwrap(),
+            channels: if matches.is_present("STEREO") { AudioChannels::Stereo } else { AudioChannels::Mono },
+        },
+        rewindSeconds: value_t!(matches, "REWIND_SECONDS", f64).unwrap_or_else(|err| err.exit()),
+        rewindInterval: value_t!(matches, "REWIND_INTERVAL", u32).unwrap_or_else(|err| err.exit()).max(1),
+        fastForwardSpeed: value_t!(matches, "FAST_FORWARD_SPEED", f64).unwrap_or_else(|err| err.exit()).max(1.0),
+        muteFastForward: matches.value_of("FAST_FORWARD_AUDIO") == Some("mute"),
+    };
+    let console = Console::new(options);
     console.run();
 }
+
+// Prints the code at an address in the rom's initial bank layout, one instruction per line.
+fn disassemble(matches: &ArgMatches) -> () {
+    let mut nes = Nes::new(Box::new(NullAudioSink::new()));
+    if let Err(err) = nes.loadCartridge(Path::new(matches.value_of("ROM").unwrap())) {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    }
+
+    let reset = (nes.peekCpuMem(0xFFFD) as u16) << 8 | nes.peekCpuMem(0xFFFC) as u16;
+    let mut addr = match matches.value_of("START") {
+        Some(start) => u16::from_str_radix(start.trim_start_matches('$').trim_start_matches("0x"), 16)
+            .unwrap_or_else(|_| clap::Error::value_validation_auto(format!("'{}' isn't a hex address", start)).exit()),
+        None => reset,
+    };
+    let count = value_t!(matches, "COUNT", usize).unwrap_or_else(|err| err.exit());
+
+    for _ in 0..count {
+        let instruction = disasm::decode(addr, |addr| nes.peekCpuMem(addr));
+        let bytes = instruction.bytes.iter().map(|byte| format!("{:02X}", byte)).collect::<Vec<String>>().join(" ");
+        let text = match instruction.annotation() {
+            Some(annotation) => format!("{:<16}{}", instruction.format(), annotation),
+            None => instruction.format(),
+        };
+        println!("{:04X}  {:<8}  {}", addr, bytes, text);
+        addr = addr.wrapping_add(instruction.len());
+    }
+}
@@ -30,4 +30,11 @@ pub trait Mapper {
     // save states
     fn saveState(&self) -> MapperData;
     fn loadState(&mut self, data: &MapperData) -> ();
+
+    // PRG RAM that a battery keeps alive, for mappers that have any
+    fn getPrgRam(&self) -> Option<&Vec<u8>> {
+        return None;
+    }
+
+    fn loadPrgRam(&mut self, data: &[u8]) -> () {}
 }
\ No newline at end of file
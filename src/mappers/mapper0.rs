@@ -3,6 +3,7 @@
 
 use std::convert::TryFrom;
 use crate::mappers::mapper::{MirrorType, Mapper};
+use crate::rom_header::RomHeader;
 use crate::save_load::{Mapper0Data, MapperData};
 
 pub struct Mapper0 {
@@ -12,11 +13,12 @@ pub struct Mapper0 {
 }
 
 impl Mapper0 {
-    pub fn new(numPrgBanks: u8, numChrBanks: u8, mirrorType: MirrorType) -> Self {
+    pub fn new(header: &RomHeader) -> Self {
         Mapper0 {
-            numPrgBanks,
-            numChrBanks,
-            mirrorType,
+            // NROM tops out at 32K of PRG and 8K of CHR
+            numPrgBanks: header.numPrgBanks().min(2) as u8,
+            numChrBanks: header.numChrBanks().min(1) as u8,
+            mirrorType: header.mirrorType,
         }
     }
 }
@@ -4,6 +4,7 @@
 
 use std::convert::TryFrom;
 use crate::mappers::mapper::{MirrorType, Mapper};
+use crate::rom_header::RomHeader;
 use crate::save_load::{Mapper2Data, MapperData};
 
 pub struct Mapper2 {
@@ -14,12 +15,13 @@ pub struct Mapper2 {
 }
 
 impl Mapper2 {
-    pub fn new(numPrgBanks: u8, numChrBanks: u8, mirrorType: MirrorType) -> Self {
+    pub fn new(header: &RomHeader) -> Self {
         Mapper2 {
             switchBank: 0,
-            lastBank: numPrgBanks - 1,
-            hasChrRam: numChrBanks == 0,
-            mirrorType: mirrorType
+            // the bank register is 8 bits, so nothing past 4M can be reached
+            lastBank: (header.numPrgBanks() - 1).min(u8::MAX as usize) as u8,
+            hasChrRam: header.numChrBanks() == 0,
+            mirrorType: header.mirrorType
         }
     }
 }
@@ -4,6 +4,7 @@
 
 use std::convert::TryFrom;
 use crate::mappers::mapper::{Mapper, MirrorType};
+use crate::rom_header::RomHeader;
 use crate::save_load::{Mapper3Data, MapperData};
 
 pub struct Mapper3 {
@@ -12,10 +13,10 @@ pub struct Mapper3 {
 }
 
 impl Mapper3 {
-    pub fn new(mirrorType: MirrorType) -> Self {
+    pub fn new(header: &RomHeader) -> Self {
         Mapper3 {
             chrBank: 0,
-            mirrorType
+            mirrorType: header.mirrorType
         }
     }
 }
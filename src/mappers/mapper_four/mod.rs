@@ -5,6 +5,7 @@ use std::convert::TryFrom;
 use crate::mappers::mapper_four::chr_register::ChrRegister;
 use crate::mappers::mapper_four::prg_register::PrgRegister;
 use crate::mappers::mapper::{MirrorType, Mapper};
+use crate::rom_header::RomHeader;
 use crate::save_load::{Mapper4Data, MapperData};
 
 mod chr_register;
@@ -32,7 +33,8 @@ pub struct Mapper4 {
 }
 
 impl Mapper4 {
-    pub fn new(numPrgBanks: u8, numChrBanks: u8, mirrorType: MirrorType) -> Self {
+    pub fn new(header: &RomHeader) -> Self {
+        let numPrgBanks = header.numPrgBanks();
 
         let mut vPrgBanks: Vec<u32> = vec![0; 4];
         vPrgBanks[1] = 0x2000;
@@ -46,7 +48,7 @@ impl Mapper4 {
             vPrgRam: vec![0; 0x2000],
             secLastPrgBank: (numPrgBanks * 2 - 2) as u16,
             lastPrgBank: (numPrgBanks * 2 - 1) as u16,
-            mirrorType,
+            mirrorType: header.mirrorType,
             target: 0,
             prgBankMode: 0,
             chrInversion: 0,
@@ -209,6 +211,15 @@ impl Mapper for Mapper4 {
         return self.prgRamEnabled;
     }
 
+    fn getPrgRam(&self) -> Option<&Vec<u8>> {
+        return Some(&self.vPrgRam);
+    }
+
+    fn loadPrgRam(&mut self, data: &[u8]) -> () {
+        let len = data.len().min(self.vPrgRam.len());
+        self.vPrgRam[..len].copy_from_slice(&data[..len]);
+    }
+
     fn checkIrq(&self) -> bool {
         return self.irqReady;
     }
@@ -10,6 +10,7 @@ use crate::mappers::mapper::{Mapper, MirrorType};
 use crate::mappers::mapper_one::control_register::{ControlRegister, PrgMode, ChrMode};
 use crate::mappers::mapper_one::chr_register::ChrRegister;
 use crate::mappers::mapper_one::prg_register::PrgRegister;
+use crate::rom_header::RomHeader;
 use crate::save_load::{Mapper1ChrRegData, Mapper1CtrlRegData, Mapper1Data, Mapper1PrgRegData, MapperData};
 
 pub struct Mapper1 {
@@ -23,10 +24,14 @@ pub struct Mapper1 {
 }
 
 impl Mapper1 {
-    pub fn new(numPrgBanks: u8, numChrBanks: u8, mirrorType: MirrorType) -> Self {
+    pub fn new(header: &RomHeader) -> Self {
+        // MMC1 can reach at most 512K of PRG and 128K of CHR
+        let numPrgBanks = header.numPrgBanks().min(32) as u8;
+        let numChrBanks = header.numChrBanks().min(16) as u8;
+
         Mapper1 {
             shiftReg: 0x10,
-            ctrlReg: ControlRegister::new(mirrorType),
+            ctrlReg: ControlRegister::new(header.mirrorType),
             chrReg: ChrRegister::new(),
             prgReg: PrgRegister::new(numPrgBanks),
             numPrgBanks,
@@ -168,6 +173,15 @@ impl Mapper for Mapper1 {
         return self.prgReg.isPrgRamEnabled();
     }
 
+    fn getPrgRam(&self) -> Option<&Vec<u8>> {
+        return Some(&self.vPrgRam);
+    }
+
+    fn loadPrgRam(&mut self, data: &[u8]) -> () {
+        let len = data.len().min(self.vPrgRam.len());
+        self.vPrgRam[..len].copy_from_slice(&data[..len]);
+    }
+
     fn checkIrq(&self) -> bool {
         return false;
     }
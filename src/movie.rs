@@ -0,0 +1,463 @@
+#![allow(non_snake_case)]
+#![allow(warnings)]
+
+use std::error::Error;
+use std::{fmt, fs, io};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use crate::controller::ButtonState;
+use crate::region::Region;
+use crate::save_load::SaveStateError;
+
+// bits of the command column at the start of each input line
+pub const COMMAND_SOFT_RESET: u8 = 0x01;
+pub const COMMAND_HARD_RESET: u8 = 0x02;
+
+// what we claim to be in the emuVersion line, FCEUX only uses it for display
+const EMU_VERSION: u32 = 20600;
+
+// FM2 button columns, left to right
+const BUTTON_CHARS: [char; 8] = ['R', 'L', 'D', 'U', 'T', 'S', 'B', 'A'];
+
+// FM2's own `savestate` key holds an FCEUX save state, which we can't read or write. Movies started
+// from one of our states carry it under this key instead, which FCEUX ignores.
+const NATIVE_STATE_KEY: &str = "rustynesSavestate";
+
+// values of the portN header lines
+const PORT_NONE: u32 = 0;
+const PORT_GAMEPAD: u32 = 1;
+
+#[derive(Debug)]
+pub enum MovieError {
+    Io(io::Error),
+    Parse { line: usize, reason: String },
+    UnsupportedInput(String),
+    WrongRom,
+    WrongRegion { moviePal: bool, running: Region },
+    NoCartridge,
+    State(SaveStateError),
+    BatteryRam(io::Error),
+}
+
+impl fmt::Display for MovieError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MovieError::Io(err) => write!(f, "Could not access movie: {}", err),
+            MovieError::Parse { line, reason } => write!(f, "Movie is malformed at line {}: {}", line, reason),
+            MovieError::UnsupportedInput(device) => write!(f, "Movies can't be made with this input device: {}", device),
+            MovieError::WrongRom => write!(f, "Movie was recorded with a different ROM"),
+            MovieError::WrongRegion { moviePal, running } => {
+                let recorded = if *moviePal { "PAL" } else { "NTSC" };
+                write!(f, "Movie was recorded on a {} console but the game is running as {}, start it with --region to match", recorded, running)
+            }
+            MovieError::NoCartridge => write!(f, "No game is loaded"),
+            MovieError::State(err) => write!(f, "Movie's starting state could not be loaded: {}", err),
+            MovieError::BatteryRam(err) => write!(f, "Could not write battery save before powering on for the movie: {}", err),
+        }
+    }
+}
+
+impl Error for MovieError {}
+
+impl From<io::Error> for MovieError {
+    fn from(err: io::Error) -> Self {
+        MovieError::Io(err)
+    }
+}
+
+impl From<SaveStateError> for MovieError {
+    fn from(err: SaveStateError) -> Self {
+        match err {
+            SaveStateError::NoCartridge => MovieError::NoCartridge,
+            err => MovieError::State(err),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum MovieMode {
+    Recording,
+    Playing,
+    Finished,   // read-only playback ran off the end of the input
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct MovieFrame {
+    pub commands: u8,
+    pub ports: [ButtonState; 4],    // players 3 and 4 only with a Four Score
+}
+
+// A recording of the buttons held on every frame, in FCEUX's text .fm2 format. Starting from the
+// same power-on (or the same save state) and feeding the same input back reproduces the run exactly.
+pub struct Movie {
+    romFilename: String,
+    romChecksum: Option<[u8; 16]>,
+    guid: String,
+    palFlag: bool,
+    fourScore: bool,
+    rerecordCount: u32,
+    comments: Vec<String>,
+    saveState: Option<Vec<u8>>,
+    frames: Vec<MovieFrame>,
+    mode: MovieMode,
+    readOnly: bool,
+    frame: usize,
+    modified: bool,
+}
+
+impl Movie {
+    // starts recording; `saveState` is the state the movie begins from, None for power-on
+    pub fn new(romFilename: String, romChecksum: [u8; 16], palFlag: bool, fourScore: bool, saveState: Option<Vec<u8>>) -> Self {
+        Movie {
+            romFilename,
+            romChecksum: Some(romChecksum),
+            guid: Movie::makeGuid(),
+            palFlag,
+            fourScore,
+            rerecordCount: 0,
+            comments: Vec::new(),
+            saveState,
+            frames: Vec::new(),
+            mode: MovieMode::Recording,
+            readOnly: false,
+            frame: 0,
+            modified: true,
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Movie, MovieError> {
+        return Movie::fromFm2(&fs::read_to_string(path)?);
+    }
+
+    pub fn save(&mut self, path: &Path) -> Result<(), MovieError> {
+        fs::write(path, self.toFm2())?;
+        self.modified = false;
+        return Ok(());
+    }
+
+    // parses a movie ready for read-only playback from the first frame
+    pub fn fromFm2(text: &str) -> Result<Movie, MovieError> {
+        let mut movie = Movie {
+            romFilename: String::new(),
+            romChecksum: None,
+            guid: String::new(),
+            palFlag: false,
+            fourScore: false,
+            rerecordCount: 0,
+            comments: Vec::new(),
+            saveState: None,
+            frames: Vec::new(),
+            mode: MovieMode::Playing,
+            readOnly: true,
+            frame: 0,
+            modified: false,
+        };
+        let mut ports = [PORT_GAMEPAD, PORT_GAMEPAD];
+
+        for (idx, line) in text.lines().enumerate() {
+            let lineNum = idx + 1;
+            let parseError = |reason: &str| MovieError::Parse { line: lineNum, reason: String::from(reason) };
+
+            let line = line.trim_end_matches('\r');
+            if line.is_empty() {
+                continue;
+            }
+
+            if line.starts_with('|') {
+                movie.frames.push(Movie::parseFrame(line, ports, movie.fourScore).ok_or_else(|| parseError("bad input line"))?);
+                continue;
+            }
+
+            let (key, value) = match line.find(' ') {
+                Some(split) => (&line[..split], line[split + 1..].trim()),
+                None => (line, ""),
+            };
+
+            let number = || value.parse::<u32>().map_err(|_| parseError(&format!("{} should be a number", key)));
+            match key {
+                "version" if number()? != 3 => { return Err(parseError("only version 3 movies are supported")); }
+                "binary" if number()? != 0 => { return Err(MovieError::UnsupportedInput(String::from("binary input log"))); }
+                "fourscore" => { movie.fourScore = number()? != 0; }
+                "FDS" if number()? != 0 => { return Err(MovieError::UnsupportedInput(String::from("Famicom Disk System"))); }
+                "port0" | "port1" => {
+                    let port = number()?;
+                    if port != PORT_NONE && port != PORT_GAMEPAD {
+                        return Err(MovieError::UnsupportedInput(format!("{} device {}", key, port)));
+                    }
+                    ports[if key == "port0" { 0 } else { 1 }] = port;
+                }
+                "port2" if number()? != 0 => { return Err(MovieError::UnsupportedInput(String::from("Famicom expansion port device"))); }
+                "palFlag" => { movie.palFlag = number()? != 0; }
+                "rerecordCount" => { movie.rerecordCount = number()?; }
+                "romFilename" => { movie.romFilename = String::from(value); }
+                "guid" => { movie.guid = String::from(value); }
+                "comment" => { movie.comments.push(String::from(value)); }
+                "romChecksum" => {
+                    let checksum = Movie::decodeBase64Value(value).ok_or_else(|| parseError("bad romChecksum"))?;
+                    movie.romChecksum = Some(checksum.try_into().map_err(|_| parseError("romChecksum should be 16 bytes"))?);
+                }
+                "savestate" => { return Err(MovieError::UnsupportedInput(String::from("FCEUX save state"))); }
+                NATIVE_STATE_KEY => {
+                    movie.saveState = Some(Movie::decodeBase64Value(value).ok_or_else(|| parseError("bad save state"))?);
+                }
+                // emuVersion, NewPPU, subtitles and anything newer don't affect playback
+                _ => {}
+            }
+        }
+
+        return Ok(movie);
+    }
+
+    pub fn toFm2(&self) -> String {
+        let mut out = String::new();
+        out.push_str("version 3\n");
+        out.push_str(&format!("emuVersion {}\n", EMU_VERSION));
+        out.push_str(&format!("rerecordCount {}\n", self.rerecordCount));
+        out.push_str(&format!("palFlag {}\n", self.palFlag as u8));
+        out.push_str(&format!("romFilename {}\n", self.romFilename));
+        if let Some(checksum) = self.romChecksum {
+            out.push_str(&format!("romChecksum base64:{}\n", BASE64.encode(&checksum)));
+        }
+        out.push_str(&format!("guid {}\n", self.guid));
+        out.push_str(&format!("fourscore {}\n", self.fourScore as u8));
+        out.push_str("port0 1\n");
+        out.push_str("port1 1\n");
+        out.push_str("port2 0\n");
+        for comment in self.comments.iter() {
+            out.push_str(&format!("comment {}\n", comment));
+        }
+        if let Some(state) = &self.saveState {
+            out.push_str(&format!("{} base64:{}\n", NATIVE_STATE_KEY, BASE64.encode(state)));
+        }
+
+        let numPads = if self.fourScore { 4 } else { 2 };
+        for frame in self.frames.iter() {
+            out.push_str(&format!("|{}|", frame.commands));
+            for pad in 0..numPads {
+                out.push_str(&Movie::formatButtons(frame.ports[pad]));
+                out.push('|');
+            }
+            out.push_str("|\n");
+        }
+        return out;
+    }
+
+    // Called as the buttons are latched for a frame. Records `live` or replaces it with what the movie says.
+    pub fn nextFrame(&mut self, live: [ButtonState; 4]) -> MovieFrame {
+        let liveFrame = MovieFrame { commands: 0, ports: live };
+
+        if self.mode == MovieMode::Playing {
+            // in read-write mode the player can take over at any point, which throws away the rest
+            let takeOver = !self.readOnly && live.iter().any(|buttons| buttons.toByte() != 0);
+            if takeOver && self.frame < self.frames.len() {
+                self.frames.truncate(self.frame);
+                self.rerecordCount += 1;
+            }
+
+            if self.frame < self.frames.len() {
+                let frame = self.frames[self.frame];
+                self.frame += 1;
+                return frame;
+            }
+
+            self.mode = if self.readOnly { MovieMode::Finished } else { MovieMode::Recording };
+        }
+
+        if self.mode == MovieMode::Recording {
+            self.frames.push(liveFrame);
+            self.frame += 1;
+            self.modified = true;
+        }
+
+        return liveFrame;
+    }
+
+    // rewinds playback to the first frame
+    pub fn restart(&mut self) -> () {
+        self.frame = 0;
+        self.mode = MovieMode::Playing;
+    }
+
+    pub fn setReadOnly(&mut self, readOnly: bool) -> () {
+        self.readOnly = readOnly;
+        if !readOnly && self.mode == MovieMode::Finished {
+            self.mode = MovieMode::Recording;
+        }
+    }
+
+    pub fn isReadOnly(&self) -> bool {
+        return self.readOnly;
+    }
+
+    pub fn getMode(&self) -> MovieMode {
+        return self.mode;
+    }
+
+    pub fn getFrame(&self) -> usize {
+        return self.frame;
+    }
+
+    pub fn len(&self) -> usize {
+        return self.frames.len();
+    }
+
+    pub fn isModified(&self) -> bool {
+        return self.modified;
+    }
+
+    pub fn isPal(&self) -> bool {
+        return self.palFlag;
+    }
+
+    pub fn isFourScore(&self) -> bool {
+        return self.fourScore;
+    }
+
+    pub fn getRomChecksum(&self) -> Option<[u8; 16]> {
+        return self.romChecksum;
+    }
+
+    pub fn getSaveState(&self) -> Option<&Vec<u8>> {
+        return self.saveState.as_ref();
+    }
+
+    // what the frame counter overlay shows
+    pub fn getStatus(&self) -> String {
+        return match self.mode {
+            MovieMode::Recording => format!("REC {}", self.frame),
+            MovieMode::Playing => format!("PLAY {} / {}", self.frame, self.frames.len()),
+            MovieMode::Finished => format!("END {} / {}", self.frame, self.frames.len()),
+        };
+    }
+
+    // the port headers are ignored with a Four Score, there are always four pads
+    fn parseFrame(line: &str, ports: [u32; 2], fourScore: bool) -> Option<MovieFrame> {
+        let fields: Vec<&str> = line.split('|').collect();
+        let numPads = if fourScore { 4 } else { 2 };
+        if fields.len() < numPads + 2 {
+            return None;
+        }
+
+        let mut frame = MovieFrame::default();
+        frame.commands = fields[1].trim().parse().ok()?;
+        for pad in 0..numPads {
+            if fourScore || ports[pad] == PORT_GAMEPAD {
+                frame.ports[pad] = Movie::parseButtons(fields[pad + 2])?;
+            }
+        }
+        return Some(frame);
+    }
+
+    // anything but a space or a dot counts as held
+    fn parseButtons(field: &str) -> Option<ButtonState> {
+        let chars: Vec<char> = field.chars().collect();
+        if chars.len() != BUTTON_CHARS.len() {
+            return None;
+        }
+
+        let held: Vec<bool> = chars.iter().map(|c| *c != '.' && *c != ' ').collect();
+        return Some(ButtonState {
+            right: held[0],
+            left: held[1],
+            down: held[2],
+            up: held[3],
+            start: held[4],
+            select: held[5],
+            b: held[6],
+            a: held[7],
+        });
+    }
+
+    fn formatButtons(buttons: ButtonState) -> String {
+        let held = [buttons.right, buttons.left, buttons.down, buttons.up, buttons.start, buttons.select, buttons.b, buttons.a];
+        return held.iter().zip(BUTTON_CHARS.iter()).map(|(held, c)| if *held { *c } else { '.' }).collect();
+    }
+
+    // FM2 binary values are either "base64:..." or "0x..." hex
+    fn decodeBase64Value(value: &str) -> Option<Vec<u8>> {
+        if let Some(hex) = value.strip_prefix("0x") {
+            if hex.len() % 2 != 0 {
+                return None;
+            }
+            return (0..hex.len()).step_by(2).map(|idx| u8::from_str_radix(&hex[idx..idx + 2], 16).ok()).collect();
+        }
+        return BASE64.decode(value.strip_prefix("base64:")?).ok();
+    }
+
+    // FCEUX only needs this to be unique, it's never checked against anything
+    fn makeGuid() -> String {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_nanos()).unwrap_or(0);
+        let hex = format!("{:032X}", nanos.wrapping_mul(0x9E3779B97F4A7C15F39CC0605CEDC835));
+        return format!("{}-{}-{}-{}-{}", &hex[0..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..32]);
+    }
+}
+
+#[cfg(test)]
+mod MovieSpec {
+    use super::*;
+
+    const FM2: &str = "version 3\nemuVersion 20604\nrerecordCount 7\npalFlag 0\nromFilename game\n\
+romChecksum base64:AAECAwQFBgcICQoLDA0ODw==\nguid 452DE2C3-EF43-2FA9-77AC-0677FC51543B\n\
+fourscore 0\nport0 1\nport1 0\nport2 0\n|0|........|||\n|2|R......A|||\n|0|...UT...|||\n";
+
+    #[test]
+    fn parsesFceuxMovie() -> () {
+        let movie = Movie::fromFm2(FM2).unwrap();
+        assert_eq!(movie.len(), 3);
+        assert_eq!(movie.getRomChecksum(), Some([0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15]));
+
+        let mut movie = movie;
+        let live = [ButtonState { b: true, ..ButtonState::default() }; 4];
+        assert_eq!(movie.nextFrame(live), MovieFrame::default());
+
+        let frame = movie.nextFrame(live);
+        assert_eq!(frame.commands, COMMAND_HARD_RESET);
+        assert_eq!(frame.ports[0], ButtonState { right: true, a: true, ..ButtonState::default() });
+
+        assert_eq!(movie.nextFrame(live).ports[0], ButtonState { up: true, start: true, ..ButtonState::default() });
+        assert_eq!(movie.nextFrame(live).ports, live);
+        assert_eq!(movie.getMode(), MovieMode::Finished);
+    }
+
+    #[test]
+    fn writeThenRead() -> () {
+        let mut movie = Movie::new(String::from("game"), [0xAB; 16], true, false, Some(vec![1, 2, 3, 4, 5]));
+        let held = ButtonState { left: true, select: true, ..ButtonState::default() };
+        let none = ButtonState::default();
+        movie.nextFrame([held, none, none, none]);
+        movie.nextFrame([none, held, none, none]);
+
+        let text = movie.toFm2();
+        assert!(text.contains("|0|.L...S..|........||"));
+        assert!(!text.contains("savestate "));
+
+        let mut copy = Movie::fromFm2(&text).unwrap();
+        assert!(copy.isPal());
+        assert_eq!(copy.getRomChecksum(), Some([0xAB; 16]));
+        assert_eq!(copy.getSaveState(), Some(&vec![1, 2, 3, 4, 5]));
+        assert_eq!(copy.nextFrame([none; 4]).ports, [held, none, none, none]);
+        assert_eq!(copy.nextFrame([none; 4]).ports, [none, held, none, none]);
+    }
+
+    #[test]
+    fn rejectsFceuxSaveState() -> () {
+        let text = FM2.replace("port2 0\n", "port2 0\nsavestate base64:AAECAw==\n");
+        assert!(matches!(Movie::fromFm2(&text), Err(MovieError::UnsupportedInput(_))));
+    }
+
+    #[test]
+    fn readWriteTakesOver() -> () {
+        let mut movie = Movie::fromFm2(FM2).unwrap();
+        movie.setReadOnly(false);
+        movie.nextFrame([ButtonState::default(); 4]);
+
+        let mut held = [ButtonState::default(); 4];
+        held[0].a = true;
+        assert_eq!(movie.nextFrame(held).ports, held);
+        assert_eq!(movie.getMode(), MovieMode::Recording);
+        assert_eq!(movie.len(), 2);
+        assert!(movie.toFm2().contains("rerecordCount 8"));
+    }
+}
@@ -0,0 +1,940 @@
+#![allow(non_snake_case)]
+#![allow(warnings)]
+
+use std::cell::RefCell;
+use std::{fs, io};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use crate::apu::Apu;
+use crate::apu::audio_sink::AudioSink;
+use crate::cartridge::{Cartridge, CartridgeError};
+use crate::clock::Clocked;
+use crate::controller::{ButtonState, Controller, InputProvider};
+use crate::cpu::Cpu;
+use crate::data_bus::DataBus;
+use crate::ppu::Ppu;
+use crate::ppu_bus::PpuBus;
+use crate::movie::{Movie, MovieError, MovieMode, COMMAND_HARD_RESET, COMMAND_SOFT_RESET};
+use crate::region::Region;
+use crate::four_score::{FourScore, FourScoreKind};
+use crate::input_setup::InputSetup;
+use crate::zapper::Zapper;
+use crate::arkanoid::{Vaus, VausKind};
+use crate::rewind::RewindBuffer;
+use crate::debugger::{DebugCommand, DebugView, Debugger};
+use crate::trace_logger::TraceLogger;
+use crate::save_load::{NesData, SaveState, SaveStateError};
+
+
+const PIXEL_WIDTH: usize = 256;
+const PIXEL_HEIGHT: usize = 240;
+
+// battery RAM is written out roughly every five seconds if it changed
+const BATTERY_FLUSH_FRAMES: u32 = 300;
+
+// frames between rewind snapshots, unless told otherwise
+const DEFAULT_REWIND_INTERVAL: u32 = 1;
+
+// The emulated console without any window, renderer or audio device attached.
+// Frontends drive it one frame at a time and present whatever it hands back.
+pub struct Nes {
+    cpu: Rc<RefCell<Cpu>>,
+    ppu: Rc<RefCell<Ppu>>,
+    apu: Rc<RefCell<Apu>>,
+    bus: Rc<RefCell<DataBus>>,
+    cartridge: Option<Rc<RefCell<Cartridge>>>,
+    inputProvider: Option<Box<dyn InputProvider>>,
+    frameBuffer: Vec<u8>,
+    regionSetting: Region,
+    region: Region,
+    inputSetupSetting: InputSetup,
+    inputSetup: InputSetup,
+    zapper: Option<Rc<RefCell<Zapper>>>,
+    vaus: Option<Rc<RefCell<Vaus>>>,
+    ppuFifths: u32,
+    saveDirectory: Option<PathBuf>,
+    batterySavePath: Option<PathBuf>,
+    flushedBatteryRam: Vec<u8>,
+    framesSinceFlush: u32,
+    rewind: Option<RewindBuffer>,
+    rewindSeconds: f64,
+    rewindInterval: u32,
+    movie: Option<Movie>,
+    movieError: Option<MovieError>,
+    batteryError: Option<io::Error>,
+    batteryFlushFailing: bool,  // so a lasting failure is only reported once
+    powerOnState: Option<Vec<u8>>,
+    debugger: Option<Rc<RefCell<Debugger>>>,
+    frameInProgress: bool,  // the debugger stopped partway through a frame
+}
+
+impl Nes {
+    pub fn new(audioSink: Box<dyn AudioSink>) -> Self {
+        let bus = Rc::new(RefCell::new(DataBus::new()));
+        bus.borrow_mut().attachController1(Rc::new(RefCell::new(Controller::new())));
+        bus.borrow_mut().attachController2(Rc::new(RefCell::new(Controller::new())));
+        let cpu = Rc::new(RefCell::new(Cpu::new(bus.clone())));
+        bus.borrow_mut().attachCpu(cpu.clone());
+        let apu = Rc::new(RefCell::new(Apu::new(bus.clone(), audioSink)));
+        bus.borrow_mut().attachApu(apu.clone());
+        let ppu = Rc::new(RefCell::new(Ppu::new(bus.clone(), PpuBus::new())));
+        bus.borrow_mut().attachPpu(ppu.clone());
+
+        Nes {
+            cpu,
+            ppu,
+            apu,
+            bus,
+            cartridge: None,
+            inputProvider: None,
+            frameBuffer: vec![0; PIXEL_WIDTH * PIXEL_HEIGHT * 3],
+            regionSetting: Region::Auto,
+            region: Region::Ntsc,
+            inputSetupSetting: InputSetup::Auto,
+            inputSetup: InputSetup::Standard,
+            zapper: None,
+            vaus: None,
+            ppuFifths: 0,
+            saveDirectory: None,
+            batterySavePath: None,
+            flushedBatteryRam: Vec::new(),
+            framesSinceFlush: 0,
+            rewind: None,
+            rewindSeconds: 0.0,
+            rewindInterval: DEFAULT_REWIND_INTERVAL,
+            movie: None,
+            movieError: None,
+            batteryError: None,
+            batteryFlushFailing: false,
+            powerOnState: None,
+            debugger: None,
+            frameInProgress: false,
+        }
+    }
+
+    // takes effect when the next cartridge is inserted; Auto picks the region from the ROM header
+    pub fn setRegion(&mut self, region: Region) -> () {
+        self.regionSetting = region;
+    }
+
+    // the region actually in use, never Auto
+    pub fn getRegion(&self) -> Region {
+        return self.region;
+    }
+
+    // Takes effect straight away, and again whenever a cartridge goes in in case it's Auto.
+    pub fn setInputSetup(&mut self, setup: InputSetup) -> () {
+        self.inputSetupSetting = setup;
+        let resolved = match &self.cartridge {
+            Some(cartridge) => setup.resolve(cartridge.borrow().getRomHeader()),
+            None => setup,
+        };
+        self.attachInputDevices(resolved);
+    }
+
+    // what's actually plugged in, never Auto
+    pub fn getInputSetup(&self) -> InputSetup {
+        return self.inputSetup;
+    }
+
+    fn attachInputDevices(&mut self, setup: InputSetup) -> () {
+        let fourScore = match setup {
+            InputSetup::FourScore => Some(FourScore::new(FourScoreKind::Nes)),
+            InputSetup::FamicomFourPlayer => Some(FourScore::new(FourScoreKind::Famicom)),
+            _ => None,
+        };
+        self.bus.borrow_mut().attachFourScore(fourScore.map(|fourScore| Rc::new(RefCell::new(fourScore))));
+
+        self.zapper = if setup == InputSetup::Zapper { Some(Rc::new(RefCell::new(Zapper::new()))) } else { None };
+        self.bus.borrow_mut().attachZapper(self.zapper.clone());
+
+        let vaus = match setup {
+            InputSetup::Arkanoid => Some(Vaus::new(VausKind::Nes)),
+            InputSetup::FamicomArkanoid => Some(Vaus::new(VausKind::Famicom)),
+            _ => None,
+        };
+        self.vaus = vaus.map(|vaus| Rc::new(RefCell::new(vaus)));
+        self.bus.borrow_mut().attachVaus(self.vaus.clone());
+        self.inputSetup = if setup == InputSetup::Auto { InputSetup::Standard } else { setup };
+    }
+
+    // `aim` is an NES pixel, None when pointing away from the screen. Ignored unless a Zapper is plugged in.
+    pub fn setZapper(&mut self, aim: Option<(u16, u16)>, trigger: bool) -> () {
+        if let Some(zapper) = &self.zapper {
+            let mut zapper = zapper.borrow_mut();
+            zapper.setAim(aim);
+            zapper.setTrigger(trigger);
+        }
+    }
+
+    // Turns the Arkanoid paddle's knob by `turn`, positive to the right. Ignored unless a paddle is plugged in.
+    pub fn setPaddle(&mut self, turn: i32, fire: bool) -> () {
+        if let Some(vaus) = &self.vaus {
+            let mut vaus = vaus.borrow_mut();
+            vaus.turn(turn);
+            vaus.setFire(fire);
+        }
+    }
+
+    pub fn getFrameRate(&self) -> f64 {
+        return self.region.frameRate();
+    }
+
+    // Stretches or squashes the audio output rate by a small factor so a frontend can keep
+    // the device's queue from draining or piling up. 1.0 is the sink's nominal rate.
+    pub fn setAudioRateAdjust(&mut self, adjust: f64) -> () {
+        self.apu.borrow_mut().setRateAdjust(adjust);
+    }
+
+    // Nothing is sent to the audio sink while muted. runFrame still returns the samples.
+    pub fn setAudioMuted(&mut self, muted: bool) -> () {
+        self.apu.borrow_mut().setMuted(muted);
+    }
+
+    // Tells the APU how fast the frontend is running frames compared to real time, so the audio
+    // it sends to the sink still lasts as long as the frames do. 1.0 is normal speed.
+    pub fn setAudioSpeed(&mut self, speed: f64) -> () {
+        self.apu.borrow_mut().setSpeed(speed);
+    }
+
+    pub fn queuedAudioSamples(&self) -> Option<usize> {
+        return self.apu.borrow().queuedSamples();
+    }
+
+    // How far back rewindFrame can go. 0 turns rewinding off and frees the history.
+    pub fn setRewindSeconds(&mut self, seconds: f64) -> () {
+        self.rewindSeconds = seconds.max(0.0);
+        if self.rewindSeconds == 0.0 {
+            self.rewind = None;
+            return;
+        }
+
+        let capacity = self.rewindCapacity();
+        match self.rewind.as_mut() {
+            Some(rewind) => rewind.setCapacity(capacity),
+            None => self.rewind = Some(RewindBuffer::new(capacity, self.rewindInterval)),
+        }
+    }
+
+    // Frames between rewind snapshots. Longer intervals keep more history in the same memory but
+    // step back in bigger jumps. Changing it drops the history taken so far.
+    pub fn setRewindInterval(&mut self, frames: u32) -> () {
+        self.rewindInterval = frames.max(1);
+        self.rewind = None;
+        self.setRewindSeconds(self.rewindSeconds);
+    }
+
+    fn rewindCapacity(&self) -> usize {
+        return (self.rewindSeconds * self.region.frameRate() / self.rewindInterval as f64).ceil() as usize;
+    }
+
+    // .sav files go here instead of beside the ROM
+    pub fn setSaveDirectory(&mut self, directory: Option<PathBuf>) -> () {
+        self.saveDirectory = directory;
+    }
+
+    pub fn loadCartridge(&mut self, romPath: &Path) -> Result<(), CartridgeError> {
+        let mut cartridge = Cartridge::fromPath(romPath)?;
+
+        let savePath = self.getBatterySavePath(romPath);
+        if cartridge.hasBattery() {
+            match fs::read(&savePath) {
+                Ok(data) => { cartridge.loadBatteryRam(&data); }
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+                Err(err) => { return Err(CartridgeError::Io(err)); }
+            }
+        }
+
+        self.insertCartridge(cartridge)?;
+        self.batterySavePath = Some(savePath);
+        return Ok(());
+    }
+
+    pub fn loadCartridgeFromBytes(&mut self, rom: &[u8]) -> Result<(), CartridgeError> {
+        let cartridge = Cartridge::fromBytes(rom)?;
+        return self.insertCartridge(cartridge);
+    }
+
+    fn getBatterySavePath(&self, romPath: &Path) -> PathBuf {
+        return match (&self.saveDirectory, romPath.file_name()) {
+            (Some(directory), Some(fileName)) => directory.join(fileName).with_extension("sav"),
+            _ => romPath.with_extension("sav"),
+        };
+    }
+
+    fn insertCartridge(&mut self, cartridge: Cartridge) -> Result<(), CartridgeError> {
+        // don't lose the outgoing game's save; if it can't be written the old game stays in
+        self.flushBatteryRam().map_err(CartridgeError::BatterySave)?;
+        self.batterySavePath = None;
+
+        self.flushedBatteryRam = cartridge.getBatteryRam().cloned().unwrap_or_default();
+        self.framesSinceFlush = 0;
+
+        self.region = self.regionSetting.resolve(cartridge.getRomHeader());
+        self.ppu.borrow_mut().setRegion(self.region);
+        self.apu.borrow_mut().setRegion(self.region);
+        self.ppuFifths = 0;
+        self.frameInProgress = false;
+        self.attachInputDevices(self.inputSetupSetting.resolve(cartridge.getRomHeader()));
+
+        // history from another game is no use, and the frame rate may have changed
+        let capacity = self.rewindCapacity();
+        if let Some(rewind) = self.rewind.as_mut() {
+            rewind.clear();
+            rewind.setCapacity(capacity);
+        }
+
+        let cartridge = Rc::new(RefCell::new(cartridge));
+        self.bus.borrow_mut().attachCartridge(cartridge.clone());
+        self.ppu.borrow_mut().attachCartridge(cartridge.clone());
+        self.cpu.borrow_mut().init();
+        self.cartridge = Some(cartridge);
+
+        // movies recorded from power-on start from exactly here
+        self.movie = None;
+        self.powerOnState = self.saveStateToBytes().ok();
+        return Ok(());
+    }
+
+    // Puts the console back how it was when the cartridge went in, but with battery RAM cleared, since
+    // that's what movies from power-on expect. The real save is written out first and the .sav isn't
+    // touched again until the game is reloaded, so the movie's battery RAM never replaces it.
+    fn powerOn(&mut self) -> Result<(), MovieError> {
+        let state = self.powerOnState.clone().ok_or(MovieError::NoCartridge)?;
+        self.flushBatteryRam().map_err(MovieError::BatteryRam)?;
+        self.batterySavePath = None;
+
+        self.loadStateFromBytes(&state)?;
+        if let Some(cartridge) = self.cartridge.as_ref() {
+            let size = cartridge.borrow().getBatteryRam().map_or(0, |ram| ram.len());
+            cartridge.borrow_mut().loadBatteryRam(&vec![0; size]);
+        }
+        self.ppuFifths = 0;
+        if let Some(rewind) = self.rewind.as_mut() {
+            rewind.clear();
+        }
+        return Ok(());
+    }
+
+    // Starts recording a movie, from a fresh power-on or from wherever the game is right now.
+    // Replaces any movie already running.
+    pub fn recordMovie(&mut self, romFilename: String, fromPowerOn: bool) -> Result<(), MovieError> {
+        let checksum = self.cartridge.as_ref().ok_or(MovieError::NoCartridge)?.borrow().getRomMd5();
+        if self.inputSetup != InputSetup::Standard && self.inputSetup != InputSetup::FourScore {
+            return Err(MovieError::UnsupportedInput(self.inputSetup.to_string()));
+        }
+
+        let saveState = if fromPowerOn {
+            self.powerOn()?;
+            None
+        } else {
+            Some(self.saveStateToBytes()?)
+        };
+
+        let fourScore = self.inputSetup == InputSetup::FourScore;
+        self.movie = Some(Movie::new(romFilename, checksum, self.region == Region::Pal, fourScore, saveState));
+        return Ok(());
+    }
+
+    // Plays a movie from its first frame, restoring the state it was recorded from.
+    pub fn playMovie(&mut self, mut movie: Movie) -> Result<(), MovieError> {
+        let checksum = self.cartridge.as_ref().ok_or(MovieError::NoCartridge)?.borrow().getRomMd5();
+        if movie.getRomChecksum().map_or(false, |expected| expected != checksum) {
+            return Err(MovieError::WrongRom);
+        }
+        // FM2 only says PAL or not, anything else counts as NTSC timing
+        if movie.isPal() != (self.region == Region::Pal) {
+            return Err(MovieError::WrongRegion { moviePal: movie.isPal(), running: self.region });
+        }
+
+        match movie.getSaveState() {
+            Some(state) => self.loadStateFromBytes(state)?,
+            None => self.powerOn()?,
+        }
+
+        // FM2 only knows plain controllers and the NES Four Score
+        let setup = if movie.isFourScore() { InputSetup::FourScore } else { InputSetup::Standard };
+        if self.inputSetup != setup {
+            self.attachInputDevices(setup);
+        }
+
+        movie.restart();
+        self.movie = Some(movie);
+        return Ok(());
+    }
+
+    pub fn stopMovie(&mut self) -> Option<Movie> {
+        return self.movie.take();
+    }
+
+    pub fn getMovie(&self) -> Option<&Movie> {
+        return self.movie.as_ref();
+    }
+
+    pub fn getMovieMut(&mut self) -> Option<&mut Movie> {
+        return self.movie.as_mut();
+    }
+
+    // set when the periodic battery RAM flush starts failing
+    pub fn takeBatteryError(&mut self) -> Option<io::Error> {
+        return self.batteryError.take();
+    }
+
+    // set when a reset in a playing movie couldn't be carried out, after which the movie can't be trusted
+    pub fn takeMovieError(&mut self) -> Option<MovieError> {
+        return self.movieError.take();
+    }
+
+    // true while a movie is feeding or taking input, when jumping around in time would desync it
+    fn isMovieActive(&self) -> bool {
+        return self.movie.as_ref().map_or(false, |movie| movie.getMode() != MovieMode::Finished);
+    }
+
+    pub fn isCartridgeLoaded(&self) -> bool {
+        return self.cartridge.is_some();
+    }
+
+    pub fn getRomHash(&self) -> Option<u32> {
+        return self.cartridge.as_ref().map(|cartridge| cartridge.borrow().getRomHash());
+    }
+
+    // the last finished frame, RGB
+    pub fn getFrameBuffer(&self) -> &Vec<u8> {
+        return &self.frameBuffer;
+    }
+
+    // Runs the console until the PPU finishes a frame. Returns the RGB frame
+    // (256 * 240 * 3 bytes) and the audio samples generated along the way
+    // (interleaved if the sink is stereo), which have also been pushed into the sink.
+    pub fn runFrame(&mut self) -> (Vec<u8>, Vec<f32>) {
+        if self.cartridge.is_none() || self.isDebuggerPaused() {
+            return (self.frameBuffer.clone(), Vec::new());
+        }
+
+        if !self.frameInProgress && !self.isMovieActive() && self.rewind.as_mut().map_or(false, |rewind| rewind.tick()) {
+            if let Ok(state) = self.saveStateToBytes() {
+                self.rewind.as_mut().unwrap().push(state);
+            }
+        }
+
+        return self.emulateFrame();
+    }
+
+    // Steps back to the most recent rewind snapshot and shows the frame that followed it, without
+    // sending any audio to the sink. Each call goes further back; None once the history runs out.
+    pub fn rewindFrame(&mut self) -> Option<Vec<u8>> {
+        if self.isMovieActive() {
+            return None;
+        }
+
+        let state = self.rewind.as_mut()?.pop()?;
+        self.loadStateFromBytes(&state).ok()?;
+
+        let muted = self.apu.borrow().isMuted();
+        self.apu.borrow_mut().setMuted(true);
+        let (frame, _) = self.emulateFrame();
+        self.apu.borrow_mut().setMuted(muted);
+        return Some(frame);
+    }
+
+    fn emulateFrame(&mut self) -> (Vec<u8>, Vec<f32>) {
+        // a frame the debugger stopped partway through carries on from there, with the input it started with
+        if !self.frameInProgress {
+            if let Some(provider) = self.inputProvider.as_mut() {
+                for port in 0..4 {
+                    let buttons = provider.getButtons(port);
+                    self.bus.borrow_mut().setControllerButtons(port, buttons);
+                }
+            }
+            self.applyMovieFrame();
+            self.bus.borrow_mut().getControllerInput();
+            self.frameInProgress = true;
+        }
+
+        let ppuFifthsPerCycle = self.region.ppuFifthsPerCpuCycle();
+
+        let mut frameComplete = false;
+        while !frameComplete {
+            if self.debuggerBreaks() {
+                return (self.frameBuffer.clone(), Vec::new());
+            }
+
+            // 3 dots per CPU cycle, or 3.2 on PAL
+            self.ppuFifths += ppuFifthsPerCycle;
+            while self.ppuFifths >= 5 {
+                self.ppuFifths -= 5;
+                let mut ppu = self.ppu.borrow_mut();
+                if let Some(buffer) = ppu.cycleAndPrepareTexture() {
+                    self.frameBuffer.copy_from_slice(buffer);
+                }
+                frameComplete |= ppu.isFrameComplete();
+            }
+
+            self.cpu.borrow_mut().cycle();
+            self.apu.borrow_mut().cycle();
+        }
+        self.apu.borrow_mut().endFrame();
+        self.frameInProgress = false;
+
+        self.framesSinceFlush += 1;
+        if self.framesSinceFlush >= BATTERY_FLUSH_FRAMES {
+            // a failure gets another go on the next flush, but is only reported the first time
+            match self.flushBatteryRam() {
+                Ok(()) => { self.batteryFlushFailing = false; }
+                Err(err) => {
+                    if !self.batteryFlushFailing {
+                        self.batteryError = Some(err);
+                    }
+                    self.batteryFlushFailing = true;
+                }
+            }
+        }
+
+        let samples = self.apu.borrow_mut().takeSamples();
+        return (self.frameBuffer.clone(), samples);
+    }
+
+    // asks the debugger, if there is one, whether to stop before the instruction the CPU is about to run
+    fn debuggerBreaks(&self) -> bool {
+        let debugger = match &self.debugger {
+            Some(debugger) => debugger,
+            None => { return false; }
+        };
+
+        let cpu = self.cpu.borrow();
+        if !cpu.isAtInstruction() {
+            return false;
+        }
+        let registers = cpu.getRegisters();
+        let opcode = self.bus.borrow().peekCpuMem(registers.pc);
+        return debugger.borrow_mut().checkInstruction(&registers, opcode);
+    }
+
+    // Turning the debugger off forgets its breakpoints and lets the game carry on.
+    pub fn setDebuggerEnabled(&mut self, enabled: bool) -> () {
+        self.debugger = if enabled { Some(Rc::new(RefCell::new(Debugger::new()))) } else { None };
+        self.bus.borrow_mut().attachDebugger(self.debugger.clone());
+    }
+
+    pub fn isDebuggerPaused(&self) -> bool {
+        return self.debugger.as_ref().map_or(false, |debugger| debugger.borrow().isPaused());
+    }
+
+    pub fn debug(&mut self, command: DebugCommand) -> () {
+        let debugger = match &self.debugger {
+            Some(debugger) => debugger,
+            None => { return; }
+        };
+
+        let registers = self.cpu.borrow().getRegisters();
+        let mut debugger = debugger.borrow_mut();
+        match command {
+            DebugCommand::Continue => debugger.resume(),
+            DebugCommand::Pause => debugger.pause(),
+            DebugCommand::StepInto => debugger.stepInto(),
+            DebugCommand::StepOver => debugger.stepOver(&registers, self.bus.borrow().peekCpuMem(registers.pc)),
+            DebugCommand::StepOut => debugger.stepOut(&registers),
+            DebugCommand::RunTo(addr) => debugger.runTo(addr),
+            DebugCommand::ToggleBreakpoint(addr) => debugger.toggleBreakpoint(addr),
+            DebugCommand::AddWatchpoint(watchpoint) => debugger.addWatchpoint(watchpoint),
+            DebugCommand::RemoveWatchpoint(idx) => debugger.removeWatchpoint(idx),
+        }
+    }
+
+    // Jumps the CPU somewhere other than the reset vector, for test ROMs like nestest that have
+    // an automated entry point.
+    pub fn setProgramCounter(&mut self, addr: u16) -> () {
+        self.cpu.borrow_mut().setProgramCounter(addr);
+    }
+
+    // logs every instruction from here on, finishing off any log already running
+    pub fn startTrace(&mut self, tracer: TraceLogger) -> io::Result<()> {
+        let previous = self.cpu.borrow_mut().setTracer(Some(tracer));
+        return previous.map_or(Ok(()), |previous| previous.finish());
+    }
+
+    pub fn stopTrace(&mut self) -> io::Result<()> {
+        let tracer = self.cpu.borrow_mut().setTracer(None);
+        return tracer.map_or(Ok(()), |tracer| tracer.finish());
+    }
+
+    // lines written so far, None when not tracing
+    pub fn getTraceLineCount(&self) -> Option<u64> {
+        return self.cpu.borrow().getTracer().map(|tracer| tracer.getLineCount());
+    }
+
+    // reads CPU memory without side effects, see DataBus::peekCpuMem
+    pub fn peekCpuMem(&self, addr: u16) -> u8 {
+        return self.bus.borrow().peekCpuMem(addr);
+    }
+
+    // None unless the debugger is on
+    pub fn getDebugView(&self) -> Option<DebugView> {
+        let debugger = self.debugger.as_ref()?.borrow();
+        let bus = self.bus.borrow();
+        return Some(debugger.buildView(self.cpu.borrow().getRegisters(), |addr| bus.peekCpuMem(addr)));
+    }
+
+    // Lets a running movie record or override the buttons that are about to be latched for this frame.
+    fn applyMovieFrame(&mut self) -> () {
+        let live = [0, 1, 2, 3].map(|port| self.bus.borrow().getControllerButtons(port));
+        let frame = match self.movie.as_mut() {
+            Some(movie) => movie.nextFrame(live),
+            None => { return; }
+        };
+
+        if frame.commands & COMMAND_HARD_RESET != 0 {
+            if let Err(err) = self.powerOn() {
+                self.movieError = Some(err);
+                return;
+            }
+        }
+        else if frame.commands & COMMAND_SOFT_RESET != 0 {
+            self.cpu.borrow_mut().reset();
+        }
+
+        for port in 0..4 {
+            self.bus.borrow_mut().setControllerButtons(port as u8, frame.ports[port]);
+        }
+    }
+
+    // Writes battery RAM to the .sav file if it changed since the last flush.
+    // Does nothing for carts without a battery or ROMs that weren't loaded from a file.
+    pub fn flushBatteryRam(&mut self) -> io::Result<()> {
+        self.framesSinceFlush = 0;
+
+        let (cartridge, savePath) = match (&self.cartridge, &self.batterySavePath) {
+            (Some(cartridge), Some(savePath)) => (cartridge, savePath),
+            _ => { return Ok(()); }
+        };
+
+        let cartridge = cartridge.borrow();
+        let ram = match cartridge.getBatteryRam() {
+            Some(ram) if *ram != self.flushedBatteryRam => ram,
+            _ => { return Ok(()); }
+        };
+
+        // write then rename so a crash mid-write can't eat the old save
+        let tempPath = savePath.with_extension("sav.tmp");
+        fs::write(&tempPath, ram)?;
+        fs::rename(&tempPath, savePath)?;
+
+        self.flushedBatteryRam = ram.clone();
+        return Ok(());
+    }
+
+    // polled once per frame, before the buttons are latched
+    pub fn setInputProvider(&mut self, provider: Box<dyn InputProvider>) -> () {
+        self.inputProvider = Some(provider);
+    }
+
+    // sets the buttons for a port directly; they are latched at the start of the next frame
+    pub fn setButtons(&mut self, port: u8, buttons: ButtonState) -> () {
+        self.bus.borrow_mut().setControllerButtons(port, buttons);
+    }
+
+    pub fn saveState(&self, path: PathBuf) -> Result<(), SaveStateError> {
+        return SaveState::save(path, self.cpu.clone(), self.ppu.clone(), self.apu.clone(), self.getCartridge()?, self.saveNesData());
+    }
+
+    pub fn loadState(&mut self, path: PathBuf) -> Result<(), SaveStateError> {
+        let data = SaveState::load(path, self.cpu.clone(), self.ppu.clone(), self.apu.clone(), self.getCartridge()?)?;
+        self.loadNesData(&data);
+        return Ok(());
+    }
+
+    pub fn saveStateToBytes(&self) -> Result<Vec<u8>, SaveStateError> {
+        return SaveState::saveToBytes(self.cpu.clone(), self.ppu.clone(), self.apu.clone(), self.getCartridge()?, self.saveNesData());
+    }
+
+    pub fn loadStateFromBytes(&mut self, bytes: &[u8]) -> Result<(), SaveStateError> {
+        let data = SaveState::loadFromBytes(bytes, self.cpu.clone(), self.ppu.clone(), self.apu.clone(), self.getCartridge()?)?;
+        self.loadNesData(&data);
+        return Ok(());
+    }
+
+    fn saveNesData(&self) -> NesData {
+        NesData {
+            ppuFifths: self.ppuFifths,
+            input: self.bus.borrow().saveInputState(),
+        }
+    }
+
+    fn loadNesData(&mut self, data: &NesData) -> () {
+        self.ppuFifths = data.ppuFifths;
+        self.bus.borrow_mut().loadInputState(&data.input);
+    }
+
+    fn getCartridge(&self) -> Result<Rc<RefCell<Cartridge>>, SaveStateError> {
+        return self.cartridge.clone().ok_or(SaveStateError::NoCartridge);
+    }
+}
+
+impl Drop for Nes {
+    fn drop(&mut self) {
+        self.flushBatteryRam();
+    }
+}
+
+#[cfg(test)]
+mod NesSpec {
+    use super::*;
+    use crate::apu::audio_sink::{AudioChannels, AudioFormat, BufferAudioSink, NullAudioSink};
+    use crate::debugger::{BreakReason, Watchpoint};
+
+    // NROM with a single JMP $8000 loop
+    fn makeRom() -> Vec<u8> {
+        let mut rom = vec![0x4E, 0x45, 0x53, 0x1A, 0x01, 0x01, 0x00, 0x00];
+        rom.resize(16, 0);
+
+        let mut prg = vec![0; 0x4000];
+        prg[0..3].copy_from_slice(&[0x4C, 0x00, 0x80]);
+        prg[0x3FFC] = 0x00;
+        prg[0x3FFD] = 0x80;
+        rom.extend(prg);
+        rom.extend(vec![0; 0x2000]);
+        return rom;
+    }
+
+    #[test]
+    fn frameAudioMatchesFormat() -> () {
+        let format = AudioFormat { sampleRate: 48000, channels: AudioChannels::Stereo };
+        let sink = BufferAudioSink::withFormat(format);
+        let buffer = sink.getBuffer();
+
+        let mut nes = Nes::new(Box::new(sink));
+        nes.loadCartridgeFromBytes(&makeRom()).unwrap();
+
+        let mut total = 0;
+        for _ in 0..10 {
+            let (frame, samples) = nes.runFrame();
+            assert_eq!(frame.len(), PIXEL_WIDTH * PIXEL_HEIGHT * 3);
+            assert_eq!(samples.len() % 2, 0);
+            total += samples.len();
+        }
+
+        // 10 NTSC frames is about 1/6 of a second, two channels
+        assert!((total as i32 - 16000).abs() < 40);
+        assert_eq!(buffer.borrow().len(), total);
+    }
+
+    #[test]
+    fn audioFollowsSpeed() -> () {
+        let sink = BufferAudioSink::new();
+        let buffer = sink.getBuffer();
+        let mut nes = Nes::new(Box::new(sink));
+        nes.loadCartridgeFromBytes(&makeRom()).unwrap();
+
+        // At double speed every other frame of audio is dropped, at half speed each one plays twice.
+        // Each frame is held back until the one after it has been generated.
+        nes.setAudioSpeed(2.0);
+        let lengths: Vec<usize> = (0..11).map(|_| nes.runFrame().1.len()).collect();
+        let generated: usize = lengths[..10].iter().sum();
+        assert!((buffer.borrow().len() as i32 - generated as i32 / 2).abs() < 10);
+
+        buffer.borrow_mut().clear();
+        nes.setAudioSpeed(0.5);
+        let lengths: Vec<usize> = (0..11).map(|_| nes.runFrame().1.len()).collect();
+        let generated: usize = lengths[..10].iter().sum();
+        assert_eq!(buffer.borrow().len(), generated * 2);
+    }
+
+    #[test]
+    fn stateRoundTrip() -> () {
+        let mut nes = Nes::new(Box::new(NullAudioSink::new()));
+        nes.loadCartridgeFromBytes(&makeRom()).unwrap();
+        nes.runFrame();
+
+        let state = nes.saveStateToBytes().unwrap();
+        nes.runFrame();
+        assert_ne!(nes.saveStateToBytes().unwrap(), state);
+
+        nes.loadStateFromBytes(&state).unwrap();
+        assert_eq!(nes.saveStateToBytes().unwrap(), state);
+    }
+
+    #[test]
+    fn rewindStepsBack() -> () {
+        let mut nes = Nes::new(Box::new(NullAudioSink::new()));
+        nes.setRewindSeconds(1.0);
+        nes.loadCartridgeFromBytes(&makeRom()).unwrap();
+
+        nes.runFrame();
+        nes.runFrame();
+        let afterTwo = nes.saveStateToBytes().unwrap();
+        nes.runFrame();
+
+        // snapshots are taken as each frame starts, and rewinding replays the frame after one
+        assert!(nes.rewindFrame().is_some());
+        assert!(nes.rewindFrame().is_some());
+        assert_eq!(nes.saveStateToBytes().unwrap(), afterTwo);
+
+        assert!(nes.rewindFrame().is_some());
+        assert!(nes.rewindFrame().is_none());
+    }
+
+    #[test]
+    fn rewindIntervalSkipsFrames() -> () {
+        let mut nes = Nes::new(Box::new(NullAudioSink::new()));
+        nes.setRewindSeconds(1.0);
+        nes.setRewindInterval(2);
+        nes.loadCartridgeFromBytes(&makeRom()).unwrap();
+
+        for _ in 0..4 {
+            nes.runFrame();
+        }
+        assert!(nes.rewindFrame().is_some());
+        assert!(nes.rewindFrame().is_some());
+        assert!(nes.rewindFrame().is_none());
+    }
+
+    #[test]
+    fn moviePowerOnClearsBatteryRam() -> () {
+        // MMC1 with a battery
+        let mut rom = makeRom();
+        rom[6] = 0x12;
+        let mut nes = Nes::new(Box::new(NullAudioSink::new()));
+        nes.loadCartridgeFromBytes(&rom).unwrap();
+
+        // as if a .sav had been loaded along with the ROM
+        let cartridge = nes.getCartridge().unwrap();
+        cartridge.borrow_mut().loadBatteryRam(&[0x55; 0x2000]);
+        nes.powerOnState = nes.saveStateToBytes().ok();
+
+        nes.recordMovie(String::from("test"), true).unwrap();
+        assert!(cartridge.borrow().getBatteryRam().unwrap().iter().all(|byte| *byte == 0));
+        assert!(nes.batterySavePath.is_none());
+    }
+
+    #[test]
+    fn keepsGameWhenSaveFails() -> () {
+        let mut rom = makeRom();
+        rom[6] = 0x12;
+        let mut nes = Nes::new(Box::new(NullAudioSink::new()));
+        nes.loadCartridgeFromBytes(&rom).unwrap();
+        let cartridge = nes.getCartridge().unwrap();
+        cartridge.borrow_mut().loadBatteryRam(&[0x55; 0x2000]);
+        nes.batterySavePath = Some(std::env::temp_dir().join("rustynes-no-such-dir").join("game.sav"));
+
+        assert!(matches!(nes.loadCartridgeFromBytes(&makeRom()), Err(CartridgeError::BatterySave(_))));
+        assert!(Rc::ptr_eq(&nes.getCartridge().unwrap(), &cartridge));
+        assert!(nes.batterySavePath.is_some());
+    }
+
+    #[test]
+    fn movieRegionMustMatch() -> () {
+        let mut nes = Nes::new(Box::new(NullAudioSink::new()));
+        nes.loadCartridgeFromBytes(&makeRom()).unwrap();
+        let movie = Movie::new(String::from("test"), nes.getCartridge().unwrap().borrow().getRomMd5(), true, false, None);
+        let movie = Movie::fromFm2(&movie.toFm2()).unwrap();
+        assert!(matches!(nes.playMovie(movie), Err(MovieError::WrongRegion { moviePal: true, running: Region::Ntsc })));
+
+        nes.setRegion(Region::Pal);
+        nes.loadCartridgeFromBytes(&makeRom()).unwrap();
+        let movie = Movie::new(String::from("test"), nes.getCartridge().unwrap().borrow().getRomMd5(), true, false, None);
+        assert!(nes.playMovie(Movie::fromFm2(&movie.toFm2()).unwrap()).is_ok());
+    }
+
+    #[test]
+    fn movieReplaysRun() -> () {
+        let mut nes = Nes::new(Box::new(NullAudioSink::new()));
+        nes.loadCartridgeFromBytes(&makeRom()).unwrap();
+        nes.runFrame();
+
+        nes.recordMovie(String::from("test"), true).unwrap();
+        for idx in 0..5u8 {
+            nes.setButtons(0, ButtonState::fromByte(idx));
+            nes.runFrame();
+        }
+        let recorded = nes.saveStateToBytes().unwrap();
+        let movie = Movie::fromFm2(&nes.stopMovie().unwrap().toFm2()).unwrap();
+
+        nes.setButtons(0, ButtonState::default());
+        nes.runFrame();
+        nes.playMovie(movie).unwrap();
+        for _ in 0..5 {
+            nes.runFrame();
+        }
+
+        assert_eq!(nes.saveStateToBytes().unwrap(), recorded);
+        assert_eq!(nes.getMovie().unwrap().getMode(), MovieMode::Playing);
+        nes.runFrame();
+        assert_eq!(nes.getMovie().unwrap().getMode(), MovieMode::Finished);
+    }
+
+    #[test]
+    fn stateFromOtherRomIsRejected() -> () {
+        let mut nes = Nes::new(Box::new(NullAudioSink::new()));
+        nes.loadCartridgeFromBytes(&makeRom()).unwrap();
+        let state = nes.saveStateToBytes().unwrap();
+
+        let mut otherRom = makeRom();
+        otherRom[16 + 3] = 0xEA;
+        nes.loadCartridgeFromBytes(&otherRom).unwrap();
+        assert!(matches!(nes.loadStateFromBytes(&state), Err(SaveStateError::WrongRom { .. })));
+    }
+
+    #[test]
+    fn stateKeepsControllerMidRead() -> () {
+        let mut nes = Nes::new(Box::new(NullAudioSink::new()));
+        nes.loadCartridgeFromBytes(&makeRom()).unwrap();
+        nes.setButtons(0, ButtonState::fromByte(0b0000_1001));
+        nes.runFrame();
+
+        // A and B shifted out, Select and Start to come
+        let read = |nes: &Nes| nes.bus.borrow().readCpuMem(0x4016) & 1;
+        assert_eq!((read(&nes), read(&nes)), (1, 0));
+        let state = nes.saveStateToBytes().unwrap();
+        assert_eq!((read(&nes), read(&nes)), (0, 1));
+
+        nes.loadStateFromBytes(&state).unwrap();
+        assert_eq!((read(&nes), read(&nes)), (0, 1));
+    }
+
+    #[test]
+    fn readWatchpointIgnoresFetches() -> () {
+        let watchCode = Watchpoint { start: 0x8000, end: 0xFFFF, read: true, write: false, execute: false };
+
+        // LDA #$00, JMP $8000
+        let mut rom = makeRom();
+        rom[16..21].copy_from_slice(&[0xA9, 0x00, 0x4C, 0x00, 0x80]);
+        let mut nes = Nes::new(Box::new(NullAudioSink::new()));
+        nes.loadCartridgeFromBytes(&rom).unwrap();
+        nes.setDebuggerEnabled(true);
+        nes.debug(DebugCommand::AddWatchpoint(watchCode));
+        nes.runFrame();
+        assert!(!nes.isDebuggerPaused());
+
+        // LDA $8010, JMP $8000 reads the code as data
+        rom[16..22].copy_from_slice(&[0xAD, 0x10, 0x80, 0x4C, 0x00, 0x80]);
+        nes.loadCartridgeFromBytes(&rom).unwrap();
+        nes.setDebuggerEnabled(true);
+        nes.debug(DebugCommand::AddWatchpoint(watchCode));
+        nes.runFrame();
+        assert_eq!(nes.getDebugView().unwrap().breakReason, Some(BreakReason::Read(0x8010)));
+    }
+
+    #[test]
+    fn debuggerStopsMidFrame() -> () {
+        let mut nes = Nes::new(Box::new(NullAudioSink::new()));
+        nes.loadCartridgeFromBytes(&makeRom()).unwrap();
+        nes.setDebuggerEnabled(true);
+        nes.debug(DebugCommand::ToggleBreakpoint(0x8000));
+
+        nes.runFrame();
+        let view = nes.getDebugView().unwrap();
+        assert_eq!(view.breakReason, Some(BreakReason::Breakpoint(0x8000)));
+        assert_eq!(view.lines.iter().find(|line| line.current).unwrap().text, "jmp $8000");
+
+        // the JMP loops straight back onto the breakpoint
+        nes.debug(DebugCommand::StepInto);
+        nes.runFrame();
+        assert_eq!(nes.getDebugView().unwrap().breakReason, Some(BreakReason::Breakpoint(0x8000)));
+
+        nes.debug(DebugCommand::ToggleBreakpoint(0x8000));
+        nes.debug(DebugCommand::Continue);
+        nes.runFrame();
+        assert!(!nes.isDebuggerPaused());
+        assert!(!nes.frameInProgress);
+    }
+}
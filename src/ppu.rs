@@ -13,6 +13,7 @@ use std::fs::File;
 use std::io::Write;
 use std::borrow::Borrow;
 use crate::cartridge::Cartridge;
+use crate::region::Region;
 use crate::save_load::{PpuBusData, PpuData};
 
 const SCANLINE_VISIBLE_MAX: u16 = 239;
@@ -118,15 +119,20 @@ pub struct Ppu {
 
     vPixelColours: Vec<u8>,
     vPixelPalette: Vec<u8>,
-    frame: Frame
+    frame: Frame,
+    frameComplete: bool,
+
+    // region timing
+    vblankScanline: u16,
+    lastScanline: u16,
 }
 
 impl Clocked for Ppu {
     #[inline]
     fn cycle(&mut self) {
         let renderEnabled = self.fSprEnabled == 1 || self.fBckEnabled == 1;
-        let renderLine = self.scanLine < SCANLINE_VBLANK_MIN - 1;
-        let preLine = self.scanLine == SCANLINE_MAX;
+        let renderLine = self.scanLine <= SCANLINE_VISIBLE_MAX;
+        let preLine = self.scanLine == self.lastScanline;
         let renderCycle = self.cycle > 1 && self.cycle < 258;
         let fetchCycle = self.cycle > 320 && self.cycle < 338;
         let fireIrq = self.cycle == 260 && self.scanLine < 240;
@@ -138,7 +144,7 @@ impl Clocked for Ppu {
             }
         }
 
-        if self.scanLine == SCANLINE_VBLANK_MIN && self.cycle == 1 {
+        if self.scanLine == self.vblankScanline && self.cycle == 1 {
             self.nmiOccured = true;
 
             if self.fNmi == 1 {
@@ -151,7 +157,7 @@ impl Clocked for Ppu {
             // }
         }
 
-        if self.scanLine == SCANLINE_MAX && self.cycle == 1 {
+        if self.scanLine == self.lastScanline && self.cycle == 1 {
             self.fSprZero = 0;
             self.nmiOccured = false;
             //self.canTrigNmi = true;
@@ -422,9 +428,10 @@ impl Clocked for Ppu {
             self.cycle = 0;
 
             self.scanLine += 1;
-            if self.scanLine > SCANLINE_MAX {
+            if self.scanLine > self.lastScanline {
                 self.scanLine = 0;
                 self.isOddFrame = !self.isOddFrame;
+                self.frameComplete = true;
 
                 if renderEnabled {
                     self.drawFrame();
@@ -487,7 +494,10 @@ impl Ppu {
             ppuBus: ppuBus,
             vPixelColours: vec![0; (PIXEL_WIDTH * PIXEL_HEIGHT * 3) as usize],
             vPixelPalette: vec![0; (PIXEL_WIDTH * PIXEL_HEIGHT) as usize],
-            frame: Frame::NotReady
+            frame: Frame::NotReady,
+            frameComplete: false,
+            vblankScanline: SCANLINE_VBLANK_MIN,
+            lastScanline: SCANLINE_MAX,
         }
     }
 
@@ -831,6 +841,37 @@ impl Ppu {
         }
     }
 
+    // true once per frame, whether or not rendering is enabled
+    pub fn isFrameComplete(&mut self) -> bool {
+        let complete = self.frameComplete;
+        self.frameComplete = false;
+        return complete;
+    }
+
+    pub fn getFrameBuffer(&self) -> &Vec<u8> {
+        return &self.vPixelColours;
+    }
+
+    // where the beam is right now; scanlines past 239 are off screen
+    pub fn getScanline(&self) -> u16 {
+        return self.scanLine;
+    }
+
+    pub fn getCycle(&self) -> u16 {
+        return self.cycle;
+    }
+
+    // colour of an on-screen pixel as it stands mid-frame, before the frame buffer is filled in
+    pub fn getPixelColour(&self, x: u16, y: u16) -> PaletteColour {
+        let idx = (x as u32 + y as u32 * PIXEL_WIDTH) as usize;
+        return PALETTE_ARRAY[(self.vPixelPalette[idx] & 0x003F) as usize];
+    }
+
+    pub fn setRegion(&mut self, region: Region) -> () {
+        self.vblankScanline = region.vblankScanline();
+        self.lastScanline = region.lastScanline();
+    }
+
     fn horizontalFlipper(&self, mut byte: u8) -> u8 {
         byte = (byte & 0xF0) >> 4 | (byte & 0x0F) << 4;
         byte = (byte & 0xCC) >> 2 | (byte & 0x33) << 2;
@@ -0,0 +1,141 @@
+#![allow(non_snake_case)]
+#![allow(warnings)]
+
+use std::fmt;
+use std::str::FromStr;
+use ndarray::Array1;
+use serde::{Serialize, Deserialize};
+use crate::apu::utils::*;
+use crate::rom_header::{RomHeader, Timing};
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Region {
+    Auto,   // whatever the ROM header asks for, NTSC if it doesn't say
+    Ntsc,
+    Pal,
+    Dendy,
+}
+
+impl Region {
+    pub fn resolve(&self, header: &RomHeader) -> Region {
+        return match self {
+            Region::Auto => {
+                match header.timing {
+                    Timing::Pal => Region::Pal,
+                    Timing::Dendy => Region::Dendy,
+                    Timing::Ntsc | Timing::MultiRegion => Region::Ntsc,
+                }
+            }
+            region => *region,
+        };
+    }
+
+    pub fn cpuHertz(&self) -> f64 {
+        return match self {
+            Region::Pal => 1662607.0,
+            Region::Dendy => 1773448.0,
+            _ => 1789773.0,
+        };
+    }
+
+    pub fn frameRate(&self) -> f64 {
+        return match self {
+            Region::Pal | Region::Dendy => 50.0070,
+            _ => 60.0988,
+        };
+    }
+
+    // PPU dots per CPU cycle, in fifths so PAL's 3.2 stays an integer
+    pub fn ppuFifthsPerCpuCycle(&self) -> u32 {
+        return match self {
+            Region::Pal => 16,
+            _ => 15,
+        };
+    }
+
+    // last scanline of the frame, i.e. the pre-render line
+    pub fn lastScanline(&self) -> u16 {
+        return match self {
+            Region::Pal | Region::Dendy => 311,
+            _ => 261,
+        };
+    }
+
+    // Dendy keeps PAL's frame length but puts its 20 lines of vblank at the end, like NTSC
+    pub fn vblankScanline(&self) -> u16 {
+        return match self {
+            Region::Dendy => 291,
+            _ => 241,
+        };
+    }
+
+    pub fn frameCounterSteps(&self) -> [u16; 5] {
+        return match self {
+            Region::Pal => [PAL_STEP_ONE_CYCLE, PAL_STEP_TWO_CYCLE, PAL_STEP_THREE_CYCLE, PAL_STEP_FOUR_CYCLE, PAL_STEP_FIVE_CYCLE],
+            _ => [STEP_ONE_CYCLE, STEP_TWO_CYCLE, STEP_THREE_CYCLE, STEP_FOUR_CYCLE, STEP_FIVE_CYCLE],
+        };
+    }
+
+    pub fn noiseTimerTable(&self) -> &'static Array1<u16> {
+        return match self {
+            Region::Pal => &PAL_NOISE_TIMER_TABLE,
+            _ => &NOISE_TIMER_TABLE,
+        };
+    }
+
+    pub fn dmcRateTable(&self) -> &'static Array1<u16> {
+        return match self {
+            Region::Pal => &PAL_DMC_RATE_TABLE,
+            _ => &DMC_RATE_TABLE,
+        };
+    }
+}
+
+impl Default for Region {
+    fn default() -> Self {
+        Region::Auto
+    }
+}
+
+impl fmt::Display for Region {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Region::Auto => "auto",
+            Region::Ntsc => "ntsc",
+            Region::Pal => "pal",
+            Region::Dendy => "dendy",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for Region {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        return match s.to_lowercase().as_str() {
+            "auto" => Ok(Region::Auto),
+            "ntsc" => Ok(Region::Ntsc),
+            "pal" => Ok(Region::Pal),
+            "dendy" => Ok(Region::Dendy),
+            _ => Err(format!("Unknown region '{}', expected auto, ntsc, pal or dendy", s)),
+        };
+    }
+}
+
+#[cfg(test)]
+mod RegionSpec {
+    use super::*;
+
+    #[test]
+    fn autoFollowsHeader() -> () {
+        let mut bytes = [0x4E, 0x45, 0x53, 0x1A, 0x01, 0x01, 0x00, 0x08, 0x00, 0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00];
+        let header = RomHeader::fromBytes(&bytes).unwrap();
+        assert_eq!(Region::Auto.resolve(&header), Region::Dendy);
+        assert_eq!(Region::Ntsc.resolve(&header), Region::Ntsc);
+
+        bytes[12] = 0x02;
+        let header = RomHeader::fromBytes(&bytes).unwrap();
+        assert_eq!(Region::Auto.resolve(&header), Region::Ntsc);
+    }
+}
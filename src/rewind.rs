@@ -0,0 +1,192 @@
+#![allow(non_snake_case)]
+#![allow(warnings)]
+
+use std::collections::VecDeque;
+
+// Bounded history of save states for rewinding. Only the newest snapshot is kept whole; every older
+// one is stored as the XOR against the snapshot after it, run-length encoded, since consecutive
+// frames rarely differ by more than a few hundred bytes. Stepping back pops the newest snapshot
+// and rebuilds the one before it.
+pub struct RewindBuffer {
+    latest: Option<Vec<u8>>,
+    deltas: VecDeque<Vec<u8>>,  // back turns `latest` into the snapshot before it
+    capacity: usize,
+    interval: u32,
+    framesSinceSnapshot: u32,
+}
+
+impl RewindBuffer {
+    // `capacity` snapshots, one taken every `interval` frames
+    pub fn new(capacity: usize, interval: u32) -> Self {
+        RewindBuffer {
+            latest: None,
+            deltas: VecDeque::new(),
+            capacity: capacity.max(1),
+            interval: interval.max(1),
+            framesSinceSnapshot: 0,
+        }
+    }
+
+    pub fn setCapacity(&mut self, capacity: usize) -> () {
+        self.capacity = capacity.max(1);
+        while self.len() > self.capacity {
+            self.deltas.pop_front();
+        }
+    }
+
+    pub fn clear(&mut self) -> () {
+        self.latest = None;
+        self.deltas.clear();
+        self.framesSinceSnapshot = 0;
+    }
+
+    pub fn len(&self) -> usize {
+        return if self.latest.is_some() { self.deltas.len() + 1 } else { 0 };
+    }
+
+    // true when a snapshot is due this frame
+    pub fn tick(&mut self) -> bool {
+        self.framesSinceSnapshot += 1;
+        if self.framesSinceSnapshot >= self.interval {
+            self.framesSinceSnapshot = 0;
+            return true;
+        }
+        return false;
+    }
+
+    pub fn push(&mut self, state: Vec<u8>) -> () {
+        if let Some(latest) = self.latest.take() {
+            self.deltas.push_back(RewindBuffer::encodeDelta(&latest, &state));
+            while self.deltas.len() + 1 > self.capacity {
+                self.deltas.pop_front();
+            }
+        }
+        self.latest = Some(state);
+    }
+
+    // takes the newest snapshot out, leaving the one before it as the newest
+    pub fn pop(&mut self) -> Option<Vec<u8>> {
+        let latest = self.latest.take()?;
+        self.latest = self.deltas.pop_back().map(|delta| RewindBuffer::applyDelta(&latest, &delta));
+        self.framesSinceSnapshot = 0;
+        return Some(latest);
+    }
+
+    // Delta layout: target length, then runs of (unchanged count, changed count, changed bytes XORed).
+    // Counts are LEB128 varints.
+    fn encodeDelta(target: &[u8], base: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        RewindBuffer::writeVarint(&mut out, target.len());
+
+        let byteAt = |data: &[u8], idx: usize| *data.get(idx).unwrap_or(&0);
+        let mut idx = 0;
+        while idx < target.len() {
+            let start = idx;
+            while idx < target.len() && target[idx] == byteAt(base, idx) {
+                idx += 1;
+            }
+            let same = idx - start;
+
+            let changedStart = idx;
+            while idx < target.len() && target[idx] != byteAt(base, idx) {
+                idx += 1;
+            }
+
+            RewindBuffer::writeVarint(&mut out, same);
+            RewindBuffer::writeVarint(&mut out, idx - changedStart);
+            for i in changedStart..idx {
+                out.push(target[i] ^ byteAt(base, i));
+            }
+        }
+
+        return out;
+    }
+
+    fn applyDelta(base: &[u8], delta: &[u8]) -> Vec<u8> {
+        let mut pos = 0;
+        let len = RewindBuffer::readVarint(delta, &mut pos);
+        let mut target: Vec<u8> = (0..len).map(|idx| *base.get(idx).unwrap_or(&0)).collect();
+
+        let mut idx = 0;
+        while pos < delta.len() {
+            idx += RewindBuffer::readVarint(delta, &mut pos);
+            let changed = RewindBuffer::readVarint(delta, &mut pos);
+            for _ in 0..changed {
+                target[idx] ^= delta[pos];
+                idx += 1;
+                pos += 1;
+            }
+        }
+
+        return target;
+    }
+
+    fn writeVarint(out: &mut Vec<u8>, mut val: usize) -> () {
+        while val >= 0x80 {
+            out.push((val as u8 & 0x7F) | 0x80);
+            val >>= 7;
+        }
+        out.push(val as u8);
+    }
+
+    fn readVarint(data: &[u8], pos: &mut usize) -> usize {
+        let mut val: usize = 0;
+        let mut shift = 0;
+        loop {
+            let byte = data[*pos];
+            *pos += 1;
+            val |= ((byte & 0x7F) as usize) << shift;
+            if byte & 0x80 == 0 {
+                return val;
+            }
+            shift += 7;
+        }
+    }
+}
+
+#[cfg(test)]
+mod RewindBufferSpec {
+    use super::*;
+
+    #[test]
+    fn popsInReverse() -> () {
+        let mut rewind = RewindBuffer::new(10, 1);
+        let states: Vec<Vec<u8>> = (0..5u8).map(|i| {
+            let mut state = vec![0; 300];
+            state[i as usize * 50] = i + 1;
+            state.resize(300 + i as usize, 0xAA);
+            state
+        }).collect();
+
+        for state in states.iter() {
+            rewind.push(state.clone());
+        }
+
+        for state in states.iter().rev() {
+            assert_eq!(rewind.pop().as_ref(), Some(state));
+        }
+        assert!(rewind.pop().is_none());
+    }
+
+    #[test]
+    fn dropsOldest() -> () {
+        let mut rewind = RewindBuffer::new(3, 1);
+        for i in 0..10u8 {
+            rewind.push(vec![i; 16]);
+        }
+
+        assert_eq!(rewind.len(), 3);
+        assert_eq!(rewind.pop(), Some(vec![9; 16]));
+        assert_eq!(rewind.pop(), Some(vec![8; 16]));
+        assert_eq!(rewind.pop(), Some(vec![7; 16]));
+        assert!(rewind.pop().is_none());
+    }
+
+    #[test]
+    fn ticksEveryInterval() -> () {
+        let mut rewind = RewindBuffer::new(3, 2);
+        assert!(!rewind.tick());
+        assert!(rewind.tick());
+        assert!(!rewind.tick());
+    }
+}
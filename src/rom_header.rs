@@ -0,0 +1,234 @@
+#![allow(non_snake_case)]
+#![allow(warnings)]
+
+use crate::cartridge::CartridgeError;
+use crate::mappers::mapper::MirrorType;
+
+pub const INES_MAGIC: [u8; 4] = [0x4E, 0x45, 0x53, 0x1A];   // "NES" followed by MS-DOS EOF
+pub const HEADER_SIZE: usize = 16;
+
+pub const PRG_BANK_SIZE: usize = 0x4000;
+pub const CHR_BANK_SIZE: usize = 0x2000;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum RomFormat {
+    ArchaicINes,
+    INes,
+    Nes2,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Timing {
+    Ntsc,
+    Pal,
+    MultiRegion,
+    Dendy,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ConsoleType {
+    Nes,
+    VsSystem { ppuType: u8, hardwareType: u8 },
+    Playchoice10,
+    Extended(u8),
+}
+
+// Everything the 16 byte header says about the cartridge. ROM and RAM sizes are in bytes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RomHeader {
+    pub format: RomFormat,
+    pub mapperId: u16,
+    pub subMapper: u8,
+    pub prgRomSize: usize,
+    pub chrRomSize: usize,
+    pub prgRamSize: usize,
+    pub prgNvramSize: usize,
+    pub chrRamSize: usize,
+    pub chrNvramSize: usize,
+    pub mirrorType: MirrorType,
+    pub fourScreen: bool,
+    pub hasBattery: bool,
+    pub hasTrainer: bool,
+    pub timing: Timing,
+    pub consoleType: ConsoleType,
+    pub defaultExpansionDevice: u8,
+    pub miscRomCount: u8,
+}
+
+impl RomHeader {
+    pub fn fromBytes(bytes: &[u8]) -> Result<Self, CartridgeError> {
+        if bytes.len() < 4 || bytes[0..4] != INES_MAGIC {
+            return Err(CartridgeError::BadMagic);
+        }
+
+        if bytes.len() < HEADER_SIZE {
+            return Err(CartridgeError::TruncatedHeader);
+        }
+
+        let format = if bytes[7] & 0x0C == 0x08 {
+            RomFormat::Nes2
+        }
+        else if bytes[7] & 0x0C == 0x00 && bytes[12..16].iter().all(|el| *el == 0) {
+            RomFormat::INes
+        }
+        else {
+            RomFormat::ArchaicINes
+        };
+
+        let mirrorType = if bytes[6] & 0x01 == 1 { MirrorType::Vertical } else { MirrorType::Horizontal };
+        let hasBattery = bytes[6] & 0x02 == 0x02;
+        let hasTrainer = bytes[6] & 0x04 == 0x04;
+        let fourScreen = bytes[6] & 0x08 == 0x08;
+
+        let header = match format {
+            RomFormat::Nes2 => {
+                let prgRomSize = RomHeader::romSize(bytes[4], bytes[9] & 0x0F, PRG_BANK_SIZE)?;
+                let chrRomSize = RomHeader::romSize(bytes[5], bytes[9] >> 4, CHR_BANK_SIZE)?;
+
+                let consoleType = match bytes[7] & 0x03 {
+                    0 => ConsoleType::Nes,
+                    1 => ConsoleType::VsSystem { ppuType: bytes[13] & 0x0F, hardwareType: bytes[13] >> 4 },
+                    2 => ConsoleType::Playchoice10,
+                    _ => ConsoleType::Extended(bytes[13] & 0x0F),
+                };
+
+                let timing = match bytes[12] & 0x03 {
+                    0 => Timing::Ntsc,
+                    1 => Timing::Pal,
+                    2 => Timing::MultiRegion,
+                    _ => Timing::Dendy,
+                };
+
+                RomHeader {
+                    format,
+                    mapperId: ((bytes[8] as u16 & 0x0F) << 8) | (bytes[7] & 0xF0) as u16 | (bytes[6] >> 4) as u16,
+                    subMapper: bytes[8] >> 4,
+                    prgRomSize,
+                    chrRomSize,
+                    prgRamSize: RomHeader::ramSize(bytes[10] & 0x0F),
+                    prgNvramSize: RomHeader::ramSize(bytes[10] >> 4),
+                    chrRamSize: RomHeader::ramSize(bytes[11] & 0x0F),
+                    chrNvramSize: RomHeader::ramSize(bytes[11] >> 4),
+                    mirrorType,
+                    fourScreen,
+                    hasBattery,
+                    hasTrainer,
+                    timing,
+                    consoleType,
+                    defaultExpansionDevice: bytes[15] & 0x3F,
+                    miscRomCount: bytes[14] & 0x03,
+                }
+            }
+            RomFormat::INes | RomFormat::ArchaicINes => {
+                // bytes 7-15 of archaic dumps are usually junk (e.g. "DiskDude!"), so only trust byte 6
+                let archaic = format == RomFormat::ArchaicINes;
+                let mapperId = if archaic { bytes[6] >> 4 } else { (bytes[7] & 0xF0) | (bytes[6] >> 4) };
+
+                let consoleType = match bytes[7] & 0x03 {
+                    _ if archaic => ConsoleType::Nes,
+                    1 => ConsoleType::VsSystem { ppuType: 0, hardwareType: 0 },
+                    2 => ConsoleType::Playchoice10,
+                    _ => ConsoleType::Nes,
+                };
+
+                // a zero here means 8K for compatibility with older dumps
+                let prgRamSize = if archaic || bytes[8] == 0 { 0x2000 } else { bytes[8] as usize * 0x2000 };
+                let chrRomSize = bytes[5] as usize * CHR_BANK_SIZE;
+
+                RomHeader {
+                    format,
+                    mapperId: mapperId as u16,
+                    subMapper: 0,
+                    prgRomSize: bytes[4] as usize * PRG_BANK_SIZE,
+                    chrRomSize,
+                    prgRamSize: if hasBattery { 0 } else { prgRamSize },
+                    prgNvramSize: if hasBattery { prgRamSize } else { 0 },
+                    chrRamSize: if chrRomSize == 0 { CHR_BANK_SIZE } else { 0 },
+                    chrNvramSize: 0,
+                    mirrorType,
+                    fourScreen,
+                    hasBattery,
+                    hasTrainer,
+                    timing: if !archaic && bytes[9] & 0x01 == 1 { Timing::Pal } else { Timing::Ntsc },
+                    consoleType,
+                    defaultExpansionDevice: 0,
+                    miscRomCount: 0,
+                }
+            }
+        };
+
+        // every mapper needs at least one bank to put at the reset vector
+        if header.numPrgBanks() == 0 {
+            return Err(CartridgeError::UnsupportedFormat("no PRG ROM"));
+        }
+        return Ok(header);
+    }
+
+    // NES 2.0 exponent sizes needn't be whole banks, a partial bank counts as one
+    pub fn numPrgBanks(&self) -> usize {
+        return (self.prgRomSize + PRG_BANK_SIZE - 1) / PRG_BANK_SIZE;
+    }
+
+    pub fn numChrBanks(&self) -> usize {
+        return (self.chrRomSize + CHR_BANK_SIZE - 1) / CHR_BANK_SIZE;
+    }
+
+    // An MSB nibble of 0xF switches the LSB byte to exponent-multiplier form: EEEEEEMM gives 2^E * (MM * 2 + 1)
+    fn romSize(lsb: u8, msb: u8, bankSize: usize) -> Result<usize, CartridgeError> {
+        if msb == 0x0F {
+            let exponent = (lsb >> 2) as u32;
+            let multiplier = ((lsb & 0x03) * 2 + 1) as usize;
+            return 1usize.checked_shl(exponent)
+                .and_then(|size| size.checked_mul(multiplier))
+                .ok_or(CartridgeError::UnsupportedFormat("ROM size too large"));
+        }
+
+        return Ok((((msb as usize) << 8) | lsb as usize) * bankSize);
+    }
+
+    // RAM sizes are stored as shift counts: 64 << n bytes, with zero meaning none
+    fn ramSize(shift: u8) -> usize {
+        return if shift == 0 { 0 } else { 64 << shift };
+    }
+}
+
+#[cfg(test)]
+mod RomHeaderSpec {
+    use super::*;
+
+    #[test]
+    fn nes2Header() -> () {
+        let bytes = [0x4E, 0x45, 0x53, 0x1A, 0x02, 0x01, 0x13, 0x48, 0x21, 0x00, 0x70, 0x07, 0x01, 0x00, 0x01, 0x05];
+        let header = RomHeader::fromBytes(&bytes).unwrap();
+
+        assert_eq!(header.format, RomFormat::Nes2);
+        assert_eq!(header.mapperId, 0x141);
+        assert_eq!(header.subMapper, 2);
+        assert_eq!(header.prgRomSize, 0x8000);
+        assert_eq!(header.chrRomSize, 0x2000);
+        assert_eq!(header.prgRamSize, 0);
+        assert_eq!(header.prgNvramSize, 0x2000);
+        assert_eq!(header.chrRamSize, 0x2000);
+        assert_eq!(header.mirrorType, MirrorType::Vertical);
+        assert!(header.hasBattery);
+        assert_eq!(header.timing, Timing::Pal);
+        assert_eq!(header.miscRomCount, 1);
+        assert_eq!(header.defaultExpansionDevice, 5);
+    }
+
+    #[test]
+    fn nes2ExponentSize() -> () {
+        // 2^10 * 3 bytes of PRG
+        let bytes = [0x4E, 0x45, 0x53, 0x1A, 0x29, 0x00, 0x00, 0x08, 0x00, 0x0F, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
+        let header = RomHeader::fromBytes(&bytes).unwrap();
+
+        assert_eq!(header.prgRomSize, 3072);
+        assert_eq!(header.numPrgBanks(), 1);
+    }
+
+    #[test]
+    fn rejectsMissingPrg() -> () {
+        let bytes = [0x4E, 0x45, 0x53, 0x1A, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
+        assert!(matches!(RomHeader::fromBytes(&bytes), Err(CartridgeError::UnsupportedFormat(_))));
+    }
+}
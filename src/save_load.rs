@@ -2,7 +2,8 @@
 #![allow(warnings)]
 
 use std::cell::RefCell;
-use std::fs;
+use std::error::Error;
+use std::{fmt, fs, io};
 use std::path::PathBuf;
 use std::rc::Rc;
 use serde::{Serialize, Deserialize};
@@ -13,6 +14,60 @@ use crate::data_bus::DataBus;
 use crate::ppu::Ppu;
 use crate::ppu_bus::PpuBus;
 
+const STATE_MAGIC: [u8; 4] = *b"RNSS";
+const STATE_HEADER_SIZE: usize = 12;
+
+// bump this whenever any of the structs below change shape, and teach `SaveState::migrate` the old one
+pub const STATE_VERSION: u32 = 2;
+
+#[derive(Debug)]
+pub enum SaveStateError {
+    Io(io::Error),
+    NoCartridge,
+    Corrupt(String),
+    UnsupportedVersion(u32),
+    WrongRom { expected: u32, found: u32 },
+}
+
+impl fmt::Display for SaveStateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SaveStateError::Io(err) => write!(f, "Could not access save state: {}", err),
+            SaveStateError::NoCartridge => write!(f, "No game is loaded"),
+            SaveStateError::Corrupt(reason) => write!(f, "Save state is corrupt: {}", reason),
+            SaveStateError::UnsupportedVersion(version) => {
+                write!(f, "Save state version {} is newer than this build supports", version)
+            }
+            SaveStateError::WrongRom { expected, found } => {
+                write!(f, "Save state belongs to a different game (ROM {:08X}, loaded {:08X})", found, expected)
+            }
+        }
+    }
+}
+
+impl Error for SaveStateError {}
+
+impl From<io::Error> for SaveStateError {
+    fn from(err: io::Error) -> Self {
+        SaveStateError::Io(err)
+    }
+}
+
+impl From<bincode::Error> for SaveStateError {
+    fn from(err: bincode::Error) -> Self {
+        SaveStateError::Corrupt(err.to_string())
+    }
+}
+
+impl From<serde_json::Error> for SaveStateError {
+    fn from(err: serde_json::Error) -> Self {
+        SaveStateError::Corrupt(err.to_string())
+    }
+}
+
+// On disk (and in memory) a state is a 12 byte header followed by the bincode encoded SaveState:
+//   "RNSS" | version: u32 LE | crc32 of the ROM: u32 LE | payload
+// Version 1 states were plain JSON with no header at all.
 #[derive(Serialize, Deserialize, Debug)]
 pub struct SaveState {
     cart: CartData,
@@ -21,7 +76,10 @@ pub struct SaveState {
     ppu: PpuData,
     ppuBus: PpuBusData,
     apu: ApuData,
-    mapper: MapperData
+    mapper: MapperData,
+    // not in v1 states, which load with everything here zeroed
+    #[serde(default)]
+    nes: NesData,
 }
 
 impl SaveState {
@@ -31,7 +89,31 @@ impl SaveState {
         ppu: Rc<RefCell<Ppu>>,
         apu: Rc<RefCell<Apu>>,
         cart: Rc<RefCell<Cartridge>>,
-    ) -> () {
+        nes: NesData,
+    ) -> Result<(), SaveStateError> {
+        let bytes = SaveState::saveToBytes(cpu, ppu, apu, cart, nes)?;
+        fs::write(path.as_path(), bytes)?;
+        return Ok(());
+    }
+
+    pub fn load(
+        path: PathBuf,
+        cpu: Rc<RefCell<Cpu>>,
+        ppu: Rc<RefCell<Ppu>>,
+        apu: Rc<RefCell<Apu>>,
+        cart: Rc<RefCell<Cartridge>>,
+    ) -> Result<NesData, SaveStateError> {
+        let saveFile = fs::read(path.as_path())?;
+        return SaveState::loadFromBytes(saveFile.as_slice(), cpu, ppu, apu, cart);
+    }
+
+    pub fn saveToBytes(
+        cpu: Rc<RefCell<Cpu>>,
+        ppu: Rc<RefCell<Ppu>>,
+        apu: Rc<RefCell<Apu>>,
+        cart: Rc<RefCell<Cartridge>>,
+        nes: NesData,
+    ) -> Result<Vec<u8>, SaveStateError> {
         let saveState = SaveState {
             cart: cart.borrow().saveState(),
             cpu: cpu.borrow().saveState(),
@@ -39,23 +121,29 @@ impl SaveState {
             ppu: ppu.borrow().saveState(),
             ppuBus: ppu.borrow().saveBusState(),
             apu: apu.borrow().saveState(),
-            mapper: cart.borrow().saveMapperState()
+            mapper: cart.borrow().saveMapperState(),
+            nes,
         };
 
-        let result = serde_json::to_string(&saveState).unwrap();
-        fs::write(path.as_path(), result).unwrap()
+        let mut bytes = Vec::with_capacity(0x4000);
+        bytes.extend_from_slice(&STATE_MAGIC);
+        bytes.extend_from_slice(&STATE_VERSION.to_le_bytes());
+        bytes.extend_from_slice(&cart.borrow().getRomHash().to_le_bytes());
+        bincode::serialize_into(&mut bytes, &saveState)?;
+        return Ok(bytes);
     }
 
-    pub fn load(
-        path: PathBuf,
+    // Nothing is touched unless the whole state decodes and matches the loaded ROM. The state that
+    // belongs to Nes itself is handed back for it to restore.
+    pub fn loadFromBytes(
+        bytes: &[u8],
         cpu: Rc<RefCell<Cpu>>,
         ppu: Rc<RefCell<Ppu>>,
         apu: Rc<RefCell<Apu>>,
         cart: Rc<RefCell<Cartridge>>,
-    ) -> () {
-        let saveFile = fs::read(path.as_path()).unwrap();
-        let data: SaveState = serde_json::from_slice(saveFile.as_slice()).unwrap();
-        
+    ) -> Result<NesData, SaveStateError> {
+        let data = SaveState::decode(bytes, cart.borrow().getRomHash())?;
+
         cart.borrow_mut().loadState(&data.cart);
         cpu.borrow_mut().loadState(&data.cpu);
         cpu.borrow_mut().loadBusState(&data.dataBus);
@@ -63,31 +151,39 @@ impl SaveState {
         ppu.borrow_mut().loadBusState(&data.ppuBus);
         apu.borrow_mut().loadState(&data.apu);
         cart.borrow_mut().loadMapperState(&data.mapper);
+        return Ok(data.nes);
     }
-}
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct CartData {
-    pub(crate) header: CartHeaderData,
-    pub(crate) mapperId: u8,
-    pub(crate) numPrgBanks: u8,
-    pub(crate) numChrBanks: u8,
-    pub(crate) vChrMem: Vec<u8>,
-    pub(crate) hasPrgRam: bool,
+    fn decode(bytes: &[u8], romHash: u32) -> Result<SaveState, SaveStateError> {
+        if bytes.len() < STATE_HEADER_SIZE || bytes[0..4] != STATE_MAGIC {
+            // no header, so it's a version 1 JSON state, which can't be checked against the ROM
+            return SaveState::migrate(1, bytes);
+        }
+
+        let version = u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]);
+        let stateHash = u32::from_le_bytes([bytes[8], bytes[9], bytes[10], bytes[11]]);
+        if stateHash != romHash {
+            return Err(SaveStateError::WrongRom { expected: romHash, found: stateHash });
+        }
+
+        return SaveState::migrate(version, &bytes[STATE_HEADER_SIZE..]);
+    }
+
+    // Brings a payload from any older version up to the current SaveState.
+    fn migrate(version: u32, payload: &[u8]) -> Result<SaveState, SaveStateError> {
+        return match version {
+            // v1 only differs in carrying ROM-derived data, which serde skips over
+            1 => Ok(serde_json::from_slice(payload)?),
+            STATE_VERSION => Ok(bincode::deserialize(payload)?),
+            _ => Err(SaveStateError::UnsupportedVersion(version)),
+        };
+    }
 }
 
-#[repr(C, packed)]
+// CHR RAM contents; None when the cart has CHR ROM, which is reloaded from the ROM instead
 #[derive(Serialize, Deserialize, Debug)]
-pub struct CartHeaderData {
-    pub name: [u8; 4],
-    pub prgSize: u8,
-    pub chrSize: u8,
-    pub mapper1: u8,
-    pub mapper2: u8,
-    pub prgRam: u8,
-    pub tvSys1: u8,
-    pub tvSys2: u8,
-    pub unused: [u8; 5],
+pub struct CartData {
+    pub(crate) vChrMem: Option<Vec<u8>>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -126,6 +222,42 @@ pub struct BusData {
     pub cpuMem: Vec<u8>,
 }
 
+// Kept by Nes rather than any one chip
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct NesData {
+    pub ppuFifths: u32,     // PPU dots owed to the CPU, in fifths, for PAL's 3.2 per cycle
+    pub input: InputData,
+}
+
+// How far each attached input device is through shifting out its report
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct InputData {
+    pub controller1: Option<ControllerData>,
+    pub controller2: Option<ControllerData>,
+    pub fourScore: Option<FourScoreData>,
+    pub vaus: Option<VausData>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ControllerData {
+    pub controllerState: u8,
+    pub controllerIdx: u8,
+    pub strobe: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct FourScoreData {
+    pub state: [u8; 4],
+    pub readIdx: [u8; 2],
+    pub strobe: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct VausData {
+    pub shift: u8,
+    pub strobe: bool,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct PpuData {
     pub cycle: u16,
@@ -195,9 +327,6 @@ pub struct ApuData {
     pub fiveStep: bool,
     pub frameInterrupt: bool,
     pub inhibitInterrupt: bool,
-    pub lengthTable: Vec<u8>,
-    pub pulseTable: Vec<f32>,
-    pub tndTable: Vec<f32>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
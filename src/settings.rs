@@ -0,0 +1,162 @@
+#![allow(non_snake_case)]
+#![allow(warnings)]
+
+use std::{fs, io};
+use std::path::{Path, PathBuf};
+use serde::{Serialize, Deserialize};
+use winit::event::VirtualKeyCode;
+use crate::keyboard_input::{KeyBindings, DEFAULT_TURBO_RATE};
+
+// in the order Hotkeys::getKey and setKey index them
+pub const HOTKEY_NAMES: [&str; 10] = [
+    "Save Slot", "Load Slot", "Previous Slot", "Next Slot", "Undo Load",
+    "Pause", "Frame Advance", "Slow Motion", "Fast Forward (hold)", "Rewind (hold)",
+];
+
+// Keys for the emulator itself rather than the game.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Hotkeys {
+    pub saveSlot: VirtualKeyCode,
+    pub loadSlot: VirtualKeyCode,
+    pub previousSlot: VirtualKeyCode,
+    pub nextSlot: VirtualKeyCode,
+    pub undoLoad: VirtualKeyCode,
+    pub pause: VirtualKeyCode,
+    pub frameAdvance: VirtualKeyCode,
+    pub slowMotion: VirtualKeyCode,
+    pub fastForward: VirtualKeyCode,
+    pub rewind: VirtualKeyCode,
+}
+
+impl Hotkeys {
+    pub fn getKey(&self, hotkey: usize) -> Option<VirtualKeyCode> {
+        let keys = [
+            self.saveSlot, self.loadSlot, self.previousSlot, self.nextSlot, self.undoLoad,
+            self.pause, self.frameAdvance, self.slowMotion, self.fastForward, self.rewind,
+        ];
+        return keys.get(hotkey).copied();
+    }
+
+    pub fn setKey(&mut self, hotkey: usize, key: VirtualKeyCode) -> () {
+        let slot = match hotkey {
+            0 => &mut self.saveSlot,
+            1 => &mut self.loadSlot,
+            2 => &mut self.previousSlot,
+            3 => &mut self.nextSlot,
+            4 => &mut self.undoLoad,
+            5 => &mut self.pause,
+            6 => &mut self.frameAdvance,
+            7 => &mut self.slowMotion,
+            8 => &mut self.fastForward,
+            9 => &mut self.rewind,
+            _ => { return; }
+        };
+        *slot = key;
+    }
+}
+
+impl Default for Hotkeys {
+    fn default() -> Self {
+        Hotkeys {
+            saveSlot: VirtualKeyCode::F5,
+            loadSlot: VirtualKeyCode::F8,
+            previousSlot: VirtualKeyCode::F6,
+            nextSlot: VirtualKeyCode::F7,
+            undoLoad: VirtualKeyCode::F9,
+            pause: VirtualKeyCode::P,
+            frameAdvance: VirtualKeyCode::Backslash,
+            slowMotion: VirtualKeyCode::Minus,
+            fastForward: VirtualKeyCode::Tab,
+            rewind: VirtualKeyCode::Back,
+        }
+    }
+}
+
+// A key the rebinding dialog can change
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KeyTarget {
+    Button(u8, usize),  // port, index into BUTTON_NAMES
+    Hotkey(usize),      // index into HOTKEY_NAMES
+}
+
+// Everything the user can configure from the GUI, kept as JSON between runs. Fields missing from
+// the file fall back to their defaults, so older files keep working as settings are added.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub controllers: [KeyBindings; 4],
+    pub hotkeys: Hotkeys,
+    pub turboRate: u32,     // turbo presses per second
+}
+
+impl Settings {
+    // rustynes/settings.json in the platform's config directory (~/.config on Linux, AppData\Roaming
+    // on Windows), None if the platform doesn't have one
+    pub fn defaultPath() -> Option<PathBuf> {
+        return dirs::config_dir().map(|dir| dir.join("rustynes").join("settings.json"));
+    }
+
+    pub fn load(path: &Path) -> io::Result<Settings> {
+        let text = fs::read_to_string(path)?;
+        return Ok(serde_json::from_str(&text)?);
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        return fs::write(path, serde_json::to_string_pretty(self)?);
+    }
+
+    pub fn getKey(&self, target: KeyTarget) -> Option<VirtualKeyCode> {
+        return match target {
+            KeyTarget::Button(port, button) => self.controllers.get(port as usize)?.getKey(button),
+            KeyTarget::Hotkey(hotkey) => self.hotkeys.getKey(hotkey),
+        };
+    }
+
+    pub fn setKey(&mut self, target: KeyTarget, key: VirtualKeyCode) -> () {
+        match target {
+            KeyTarget::Button(port, button) => {
+                if let Some(bindings) = self.controllers.get_mut(port as usize) {
+                    bindings.setKey(button, key);
+                }
+            }
+            KeyTarget::Hotkey(hotkey) => self.hotkeys.setKey(hotkey, key),
+        }
+    }
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            controllers: [KeyBindings::player1(), KeyBindings::player2(), KeyBindings::player3(), KeyBindings::player4()],
+            hotkeys: Hotkeys::default(),
+            turboRate: DEFAULT_TURBO_RATE,
+        }
+    }
+}
+
+#[cfg(test)]
+mod SettingsSpec {
+    use super::*;
+
+    #[test]
+    fn missingFieldsUseDefaults() -> () {
+        let settings: Settings = serde_json::from_str(r#"{"hotkeys": {"pause": "Space"}}"#).unwrap();
+        assert_eq!(settings.hotkeys.pause, VirtualKeyCode::Space);
+        assert_eq!(settings.hotkeys.rewind, VirtualKeyCode::Back);
+        assert_eq!(settings.controllers[0], KeyBindings::player1());
+    }
+
+    #[test]
+    fn rebindRoundTrip() -> () {
+        let mut settings = Settings::default();
+        settings.setKey(KeyTarget::Button(1, 3), VirtualKeyCode::Space);
+        assert_eq!(settings.controllers[1].start, VirtualKeyCode::Space);
+
+        let text = serde_json::to_string(&settings).unwrap();
+        assert_eq!(serde_json::from_str::<Settings>(&text).unwrap(), settings);
+    }
+}
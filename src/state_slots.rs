@@ -0,0 +1,122 @@
+#![allow(non_snake_case)]
+#![allow(warnings)]
+
+use std::{fs, io};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use image::ColorType;
+
+pub const NUM_SLOTS: usize = 10;
+
+// thumbnails are the frame at half size
+pub const THUMBNAIL_WIDTH: usize = 128;
+pub const THUMBNAIL_HEIGHT: usize = 120;
+
+const FRAME_WIDTH: usize = 256;
+
+pub struct SlotInfo {
+    pub modified: SystemTime,
+    pub thumbnail: Option<Vec<u8>>,    // RGBA, THUMBNAIL_WIDTH * THUMBNAIL_HEIGHT
+}
+
+// Quick-save slots for one game. Each slot is a state file plus a PNG thumbnail,
+// kept in a directory named after the ROM and its hash so two games never share slots.
+pub struct StateSlots {
+    directory: PathBuf,
+}
+
+impl StateSlots {
+    pub fn new(root: &Path, romPath: &Path, romHash: u32) -> Self {
+        let romName = romPath.file_stem().map(|stem| stem.to_string_lossy().to_string()).unwrap_or_default();
+        StateSlots {
+            directory: root.join(format!("{}-{:08X}", romName, romHash)),
+        }
+    }
+
+    pub fn getDirectory(&self) -> &Path {
+        return &self.directory;
+    }
+
+    // `frame` is the RGB frame buffer the state was taken on
+    pub fn save(&self, slot: usize, state: &[u8], frame: &[u8]) -> io::Result<()> {
+        fs::create_dir_all(&self.directory)?;
+        fs::write(self.statePath(slot), state)?;
+
+        let mut thumbnail = Vec::with_capacity(THUMBNAIL_WIDTH * THUMBNAIL_HEIGHT * 3);
+        for y in 0..THUMBNAIL_HEIGHT {
+            for x in 0..THUMBNAIL_WIDTH {
+                let idx = ((y * 2) * FRAME_WIDTH + x * 2) * 3;
+                thumbnail.extend_from_slice(&frame[idx..idx + 3]);
+            }
+        }
+
+        // a missing thumbnail isn't worth failing the save over
+        image::save_buffer(self.thumbnailPath(slot), &thumbnail, THUMBNAIL_WIDTH as u32, THUMBNAIL_HEIGHT as u32, ColorType::Rgb8);
+        return Ok(());
+    }
+
+    pub fn load(&self, slot: usize) -> io::Result<Vec<u8>> {
+        return fs::read(self.statePath(slot));
+    }
+
+    pub fn getInfo(&self, slot: usize) -> Option<SlotInfo> {
+        let modified = fs::metadata(self.statePath(slot)).and_then(|meta| meta.modified()).ok()?;
+        let thumbnail = image::open(self.thumbnailPath(slot)).ok().map(|img| img.to_rgba8().into_raw());
+
+        return Some(SlotInfo {
+            modified,
+            thumbnail,
+        });
+    }
+
+    fn statePath(&self, slot: usize) -> PathBuf {
+        return self.directory.join(format!("slot{}.state", slot));
+    }
+
+    fn thumbnailPath(&self, slot: usize) -> PathBuf {
+        return self.directory.join(format!("slot{}.png", slot));
+    }
+}
+
+// e.g. "just now", "5 min ago", "3 h ago", "2 days ago"
+pub fn formatAge(modified: SystemTime, now: SystemTime) -> String {
+    let secs = now.duration_since(modified).map(|age| age.as_secs()).unwrap_or(0);
+    return match secs {
+        0..=59 => String::from("just now"),
+        60..=3599 => format!("{} min ago", secs / 60),
+        3600..=86399 => format!("{} h ago", secs / 3600),
+        _ => format!("{} days ago", secs / 86400),
+    };
+}
+
+#[cfg(test)]
+mod StateSlotsSpec {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn saveAndLoad() -> () {
+        let root = std::env::temp_dir().join(format!("rustynes-slots-{}", std::process::id()));
+        let slots = StateSlots::new(&root, Path::new("/roms/game.nes"), 0xDEADBEEF);
+        assert!(slots.getDirectory().ends_with("game-DEADBEEF"));
+        assert!(slots.getInfo(3).is_none());
+
+        let frame = vec![0x7F; 256 * 240 * 3];
+        slots.save(3, &[1, 2, 3], &frame).unwrap();
+
+        assert_eq!(slots.load(3).unwrap(), vec![1, 2, 3]);
+        let thumbnail = slots.getInfo(3).unwrap().thumbnail.unwrap();
+        assert_eq!(thumbnail.len(), THUMBNAIL_WIDTH * THUMBNAIL_HEIGHT * 4);
+        assert_eq!(thumbnail[0..4], [0x7F, 0x7F, 0x7F, 0xFF]);
+
+        fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn ages() -> () {
+        let now = SystemTime::now();
+        assert_eq!(formatAge(now, now), "just now");
+        assert_eq!(formatAge(now - Duration::from_secs(300), now), "5 min ago");
+        assert_eq!(formatAge(now - Duration::from_secs(3 * 86400), now), "3 days ago");
+    }
+}
@@ -0,0 +1,156 @@
+#![allow(non_snake_case)]
+#![allow(warnings)]
+
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use crate::cpu::CpuRegisters;
+use crate::disasm;
+use crate::disasm::Instruction;
+use crate::opcode_info::{AddressMode, OpMnemonic};
+
+// Which instructions get logged: those starting inside start..=end, until maxLines have been written
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TraceFilter {
+    pub start: u16,
+    pub end: u16,
+    pub maxLines: Option<u64>,  // None for no limit
+}
+
+impl Default for TraceFilter {
+    fn default() -> Self {
+        TraceFilter { start: 0x0000, end: 0xFFFF, maxLines: None }
+    }
+}
+
+// Writes one line per executed instruction in the format of nestest.log, which came out of
+// Nintendulator, so traces can be diffed against it and against other emulators:
+//
+// C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7
+pub struct TraceLogger {
+    writer: Box<dyn Write>,
+    filter: TraceFilter,
+    lines: u64,
+    error: Option<io::Error>,
+}
+
+impl TraceLogger {
+    pub fn new(writer: Box<dyn Write>, filter: TraceFilter) -> Self {
+        TraceLogger {
+            writer,
+            filter,
+            lines: 0,
+            error: None,
+        }
+    }
+
+    pub fn create(path: &Path, filter: TraceFilter) -> io::Result<TraceLogger> {
+        let file = File::create(path)?;
+        return Ok(TraceLogger::new(Box::new(BufWriter::new(file)), filter));
+    }
+
+    pub fn getLineCount(&self) -> u64 {
+        return self.lines;
+    }
+
+    // true once maxLines have been written, nothing more will be
+    pub fn isFull(&self) -> bool {
+        return self.filter.maxLines.map_or(false, |maxLines| self.lines >= maxLines);
+    }
+
+    // Called with the CPU about to run the instruction at registers.pc. `peek` reads CPU memory
+    // without side effects.
+    pub fn log(&mut self, registers: &CpuRegisters, scanline: u16, dot: u16, cycles: u64, peek: impl Fn(u16) -> u8) -> () {
+        if self.error.is_some() || self.isFull() || registers.pc < self.filter.start || registers.pc > self.filter.end {
+            return;
+        }
+
+        let line = formatLine(registers, scanline, dot, cycles, peek);
+        if let Err(err) = writeln!(self.writer, "{}", line) {
+            // stop at the first failure and report it when the log is closed
+            self.error = Some(err);
+            return;
+        }
+        self.lines += 1;
+    }
+
+    // flushes the file, returning the first error hit while writing it
+    pub fn finish(mut self) -> io::Result<()> {
+        if let Some(err) = self.error.take() {
+            return Err(err);
+        }
+        return self.writer.flush();
+    }
+}
+
+pub fn formatLine(registers: &CpuRegisters, scanline: u16, dot: u16, cycles: u64, peek: impl Fn(u16) -> u8) -> String {
+    let instruction = disasm::decode(registers.pc, &peek);
+    let bytes = instruction.bytes.iter().map(|byte| format!("{:02X}", byte)).collect::<Vec<String>>().join(" ");
+    let marker = if instruction.isOfficial() { " " } else { "*" };
+
+    return format!("{:04X}  {:<8} {}{:<31} A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} PPU:{:>3},{:>3} CYC:{}",
+        registers.pc, bytes, marker, nintendulatorText(&instruction, registers, &peek),
+        registers.a, registers.x, registers.y, registers.p, registers.sp, scanline, dot, cycles);
+}
+
+// Nintendulator's disassembly: upper case, with every address the instruction goes through and
+// the value found at the end of it, e.g. `LDA ($89),Y = 0300 @ 0300 = 89`
+fn nintendulatorText(instruction: &Instruction, registers: &CpuRegisters, peek: &impl Fn(u16) -> u8) -> String {
+    let name = match instruction.opCode {
+        OpMnemonic::ISC => String::from("ISB"),
+        opCode => disasm::mnemonic(opCode).to_uppercase(),
+    };
+    let byte = instruction.operand() as u8;
+    let word = instruction.operand();
+    let target = instruction.effectiveAddress(registers, peek).unwrap_or(0);
+    let value = peek(target);
+    let pointer = |zpg: u8| (peek(zpg.wrapping_add(1) as u16) as u16) << 8 | peek(zpg as u16) as u16;
+
+    return match instruction.addrMode {
+        AddressMode::Implied => name,
+        AddressMode::Accumulator => format!("{} A", name),
+        AddressMode::Immediate => format!("{} #${:02X}", name, byte),
+        AddressMode::Relative => format!("{} ${:04X}", name, target),
+        AddressMode::ZeroPage => format!("{} ${:02X} = {:02X}", name, byte, value),
+        AddressMode::ZeroPageX => format!("{} ${:02X},X @ {:02X} = {:02X}", name, byte, target, value),
+        AddressMode::ZeroPageY => format!("{} ${:02X},Y @ {:02X} = {:02X}", name, byte, target, value),
+        AddressMode::Absolute if instruction.opCode == OpMnemonic::JMP || instruction.opCode == OpMnemonic::JSR => {
+            format!("{} ${:04X}", name, word)
+        }
+        AddressMode::Absolute => format!("{} ${:04X} = {:02X}", name, word, value),
+        AddressMode::AbsoluteX => format!("{} ${:04X},X @ {:04X} = {:02X}", name, word, target, value),
+        AddressMode::AbsoluteY => format!("{} ${:04X},Y @ {:04X} = {:02X}", name, word, target, value),
+        AddressMode::Indirect => format!("{} (${:04X}) = {:04X}", name, word, target),
+        AddressMode::IndexedIndirect => {
+            format!("{} (${:02X},X) @ {:02X} = {:04X} = {:02X}", name, byte, byte.wrapping_add(registers.x), target, value)
+        }
+        AddressMode::IndirectIndexed => {
+            format!("{} (${:02X}),Y = {:04X} @ {:04X} = {:02X}", name, byte, pointer(byte), target, value)
+        }
+    };
+}
+
+#[cfg(test)]
+mod TraceLoggerSpec {
+    use super::*;
+
+    #[test]
+    fn matchesNestestLog() -> () {
+        let mut memory = vec![0u8; 0x10000];
+        memory[0xC000..0xC003].copy_from_slice(&[0x4C, 0xF5, 0xC5]);
+        memory[0xD959..0xD95B].copy_from_slice(&[0xB1, 0x89]);
+        memory[0x0089] = 0x00;
+        memory[0x008A] = 0x03;
+        memory[0x0300] = 0x89;
+        let peek = |addr: u16| memory[addr as usize];
+
+        let registers = CpuRegisters { pc: 0xC000, sp: 0xFD, p: 0x24, ..CpuRegisters::default() };
+        assert_eq!(formatLine(&registers, 0, 21, 7, peek),
+            "C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7");
+
+        let registers = CpuRegisters { pc: 0xD959, a: 0x01, x: 0x65, sp: 0xFB, p: 0x27, ..CpuRegisters::default() };
+        assert_eq!(formatLine(&registers, 30, 4, 3337, peek),
+            "D959  B1 89     LDA ($89),Y = 0300 @ 0300 = 89  A:01 X:65 Y:00 P:27 SP:FB PPU: 30,  4 CYC:3337");
+    }
+}
@@ -0,0 +1,146 @@
+#![allow(non_snake_case)]
+#![allow(warnings)]
+
+use crate::ppu::Ppu;
+
+const LIGHT_NOT_DETECTED: u8 = 0x08;
+const TRIGGER_PULLED: u8 = 0x10;
+
+// The photodiode only stays lit for a short while after the beam passes, roughly this many scanlines
+const LIGHT_SCANLINES: u16 = 20;
+
+// how far around the aim point the sensor can see, in NES pixels
+const SENSOR_RADIUS: i32 = 2;
+
+// perceived brightness (0-255) a pixel needs to register
+const BRIGHTNESS_THRESHOLD: u32 = 85;
+
+const SCREEN_WIDTH: i32 = 256;
+const SCREEN_HEIGHT: i32 = 240;
+
+// The Zapper light gun. The sensor looks at whatever the PPU drew around the aim point during the last
+// few scanlines, so a game sees light only while the beam is just past a bright target.
+pub struct Zapper {
+    aim: Option<(u16, u16)>,    // NES pixel, None when pointing off screen
+    trigger: bool,
+}
+
+impl Zapper {
+    pub fn new() -> Self {
+        Zapper {
+            aim: None,
+            trigger: false,
+        }
+    }
+
+    pub fn setAim(&mut self, aim: Option<(u16, u16)>) -> () {
+        self.aim = aim;
+    }
+
+    pub fn setTrigger(&mut self, pulled: bool) -> () {
+        self.trigger = pulled;
+    }
+
+    // D3 is low while light is seen, D4 is high while the trigger is held
+    pub fn getState(&self, ppu: &Ppu) -> u8 {
+        let mut state = 0;
+        if !self.detectsLight(ppu) {
+            state |= LIGHT_NOT_DETECTED;
+        }
+        if self.trigger {
+            state |= TRIGGER_PULLED;
+        }
+        return state;
+    }
+
+    fn detectsLight(&self, ppu: &Ppu) -> bool {
+        let (aimX, aimY) = match self.aim {
+            Some(aim) => (aim.0 as i32, aim.1 as i32),
+            None => { return false; }
+        };
+
+        let scanline = ppu.getScanline() as i32;
+        let beamX = ppu.getCycle() as i32 - 1;
+
+        for y in (aimY - SENSOR_RADIUS).max(0)..=(aimY + SENSOR_RADIUS).min(SCREEN_HEIGHT - 1) {
+            // only pixels the beam has drawn in the last few lines are still glowing
+            let drawn = y < scanline || (y == scanline && beamX >= aimX - SENSOR_RADIUS);
+            if !drawn || scanline - y > LIGHT_SCANLINES as i32 {
+                continue;
+            }
+
+            for x in (aimX - SENSOR_RADIUS).max(0)..=(aimX + SENSOR_RADIUS).min(SCREEN_WIDTH - 1) {
+                if y == scanline && x > beamX {
+                    continue;
+                }
+
+                let colour = ppu.getPixelColour(x as u16, y as u16);
+                let brightness = (colour.red as u32 * 299 + colour.green as u32 * 587 + colour.blue as u32 * 114) / 1000;
+                if brightness >= BRIGHTNESS_THRESHOLD {
+                    return true;
+                }
+            }
+        }
+
+        return false;
+    }
+}
+
+#[cfg(test)]
+mod ZapperSpec {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use crate::data_bus::DataBus;
+    use crate::ppu_bus::PpuBus;
+
+    #[test]
+    fn reportsTrigger() -> () {
+        let ppu = Ppu::new(Rc::new(RefCell::new(DataBus::new())), PpuBus::new());
+        let mut zapper = Zapper::new();
+        assert_eq!(zapper.getState(&ppu), LIGHT_NOT_DETECTED);
+
+        // the screen is still dark grey, so aiming at it sees nothing
+        zapper.setAim(Some((0, 0)));
+        zapper.setTrigger(true);
+        assert_eq!(zapper.getState(&ppu), LIGHT_NOT_DETECTED | TRIGGER_PULLED);
+    }
+
+    // fills the sensor's view around `aim` with one palette entry and moves the beam
+    fn drawTarget(ppu: &mut Ppu, aim: (u16, u16), colour: u8, scanline: u16, cycle: u16) -> () {
+        let mut data = ppu.saveState();
+        for y in aim.1 - SENSOR_RADIUS as u16..=aim.1 + SENSOR_RADIUS as u16 {
+            for x in aim.0 - SENSOR_RADIUS as u16..=aim.0 + SENSOR_RADIUS as u16 {
+                data.vPixelPalette[(x + y * SCREEN_WIDTH as u16) as usize] = colour;
+            }
+        }
+        data.scanLine = scanline;
+        data.cycle = cycle;
+        ppu.loadState(&data);
+    }
+
+    #[test]
+    fn seesLightBehindBeam() -> () {
+        let mut ppu = Ppu::new(Rc::new(RefCell::new(DataBus::new())), PpuBus::new());
+        let mut zapper = Zapper::new();
+        zapper.setAim(Some((100, 50)));
+
+        // white, from before the beam reaches the target until its bottom row has faded
+        let lit = |ppu: &mut Ppu, scanline: u16, cycle: u16| {
+            drawTarget(ppu, (100, 50), 0x30, scanline, cycle);
+            zapper.getState(ppu) & LIGHT_NOT_DETECTED == 0
+        };
+        assert!(!lit(&mut ppu, 40, 100));
+        assert!(!lit(&mut ppu, 48, 98));
+        assert!(lit(&mut ppu, 48, 99));
+        assert!(lit(&mut ppu, 60, 0));
+        assert!(lit(&mut ppu, 52 + LIGHT_SCANLINES, 0));
+        assert!(!lit(&mut ppu, 53 + LIGHT_SCANLINES, 0));
+
+        // light grey is bright enough, the dark grey of a blank screen isn't
+        drawTarget(&mut ppu, (100, 50), 0x10, 60, 0);
+        assert_eq!(zapper.getState(&ppu) & LIGHT_NOT_DETECTED, 0);
+        drawTarget(&mut ppu, (100, 50), 0x00, 60, 0);
+        assert_eq!(zapper.getState(&ppu) & LIGHT_NOT_DETECTED, LIGHT_NOT_DETECTED);
+    }
+}
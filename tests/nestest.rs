@@ -0,0 +1,105 @@
+#![allow(non_snake_case)]
+
+// Runs kevtris's nestest.nes in its automated mode, starting at $C000 with no PPU needed, and checks
+// every instruction against the log Nintendulator produced for it. That covers the official opcodes
+// and then the unofficial ones nestest knows about.
+//
+// The ROM and log aren't kept in the repo, so the test is ignored by default. Put nestest.nes and
+// nestest.log in tests/roms and run it with `cargo test --test nestest -- --ignored`.
+
+use std::cell::RefCell;
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::path::PathBuf;
+use std::rc::Rc;
+use rustynes::apu::audio_sink::NullAudioSink;
+use rustynes::nes::Nes;
+use rustynes::trace_logger::{TraceFilter, TraceLogger};
+
+const START_ADDRESS: u16 = 0xC000;
+const MAX_FRAMES: u32 = 60;     // nestest finishes in under 2
+const CONTEXT_LINES: usize = 5;
+
+// lets the test read back what the trace logger wrote
+struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        return self.0.borrow_mut().write(buf);
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        return Ok(());
+    }
+}
+
+// The parts of a log line that say what the CPU did. The disassembly is left out since it's only
+// decoration, and so is the PPU position, which depends on where the PPU was at power on.
+#[derive(Debug, PartialEq)]
+struct Step {
+    pc: String,
+    bytes: String,
+    registers: Vec<String>,     // A, X, Y, P and SP
+    cycles: Option<String>,     // older copies of the log don't have CYC:
+}
+
+fn parseStep(line: &str) -> Step {
+    let fields = &line[line.find("A:").unwrap_or(line.len())..];
+    let field = |name: &str| fields.split_whitespace().find(|token| token.starts_with(name)).map(String::from);
+
+    return Step {
+        pc: line.get(0..4).unwrap_or_default().to_string(),
+        bytes: line.get(6..14).unwrap_or_default().trim().to_string(),
+        registers: ["A:", "X:", "Y:", "P:", "SP:"].iter().map(|name| field(name).unwrap_or_default()).collect(),
+        cycles: field("CYC:"),
+    };
+}
+
+fn fixture(name: &str) -> PathBuf {
+    return PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests").join("roms").join(name);
+}
+
+#[test]
+#[ignore = "needs tests/roms/nestest.{nes,log}"]
+fn nestestMatchesGoldenLog() -> () {
+    let rom = fs::read(fixture("nestest.nes")).expect("tests/roms/nestest.nes should be there");
+    let log = fs::read_to_string(fixture("nestest.log")).expect("tests/roms/nestest.log should be there");
+    let expected: Vec<&str> = log.lines().filter(|line| !line.trim().is_empty()).collect();
+
+    let mut nes = Nes::new(Box::new(NullAudioSink::new()));
+    nes.loadCartridgeFromBytes(&rom).expect("nestest.nes should load");
+    nes.setProgramCounter(START_ADDRESS);
+
+    let buffer = Rc::new(RefCell::new(Vec::new()));
+    let filter = TraceFilter { maxLines: Some(expected.len() as u64), ..TraceFilter::default() };
+    nes.startTrace(TraceLogger::new(Box::new(SharedBuffer(buffer.clone())), filter)).unwrap();
+
+    let mut frames = 0;
+    while nes.getTraceLineCount().unwrap() < expected.len() as u64 && frames < MAX_FRAMES {
+        nes.runFrame();
+        frames += 1;
+    }
+    nes.stopTrace().unwrap();
+
+    let trace = String::from_utf8(buffer.borrow().clone()).unwrap();
+    let actual: Vec<&str> = trace.lines().collect();
+
+    for (idx, expectedLine) in expected.iter().enumerate() {
+        let actualLine = actual.get(idx).copied().unwrap_or("<nothing, the trace ended>");
+        let (expectedStep, actualStep) = (parseStep(expectedLine), parseStep(actualLine));
+        let cyclesDiffer = expectedStep.cycles.is_some() && expectedStep.cycles != actualStep.cycles;
+        if expectedStep.pc == actualStep.pc && expectedStep.bytes == actualStep.bytes
+            && expectedStep.registers == actualStep.registers && !cyclesDiffer {
+            continue;
+        }
+
+        let mut report = format!("nestest diverged from the log at line {}\n", idx + 1);
+        for context in idx.saturating_sub(CONTEXT_LINES)..idx {
+            report += &format!("      {}\n", actual[context]);
+        }
+        report += &format!("want  {}\n", expectedLine);
+        report += &format!("got   {}\n", actualLine);
+        panic!("{}", report);
+    }
+}
@@ -0,0 +1,168 @@
+#![allow(non_snake_case)]
+
+// Tom Harte's ProcessorTests, run against the CPU wired to a FlatBus with no PPU or APU behind it.
+// Each opcode gets a JSON file of randomly generated cases giving the registers and RAM going in, the
+// registers and RAM coming out, and every bus access made on every cycle of the instruction.
+//
+// What's checked: final registers and RAM, the cycle count, that the writes happen in the order
+// hardware makes them, and that nothing is read from an address hardware wouldn't read. What isn't:
+// the core does an instruction's accesses all at once rather than one per cycle, and skips the dummy
+// reads and writes, so the timing and order of reads can't be compared until it models the bus cycle
+// by cycle.
+//
+// The vectors are too big for the repo, so matchesProcessorTests is ignored by default. Copy the NES
+// set (nes6502/v1/00.json to ff.json, decimal mode left out) into tests/processor_tests and run
+// `cargo test --test single_step -- --ignored`.
+
+use std::cell::RefCell;
+use std::fs;
+use std::path::PathBuf;
+use std::rc::Rc;
+use serde::Deserialize;
+use rustynes::clock::Clocked;
+use rustynes::cpu::{Cpu, CpuRegisters};
+use rustynes::cpu_bus::{BusAccess, CpuBus, FlatBus};
+
+// The JAMs lock up the CPU, and the rest do different things on different chips, so there's
+// nothing to hold the core to.
+const SKIPPED_OPCODES: [u8; 19] = [
+    0x02, 0x12, 0x22, 0x32, 0x42, 0x52, 0x62, 0x72, 0x92, 0xB2, 0xD2, 0xF2,     // JAM
+    0x8B, 0xAB, 0x93, 0x9F, 0x9B, 0x9C, 0x9E,                                   // ANE, LXA, SHA, TAS, SHY, SHX
+];
+const MAX_REPORTED: usize = 20;
+
+// B and the unused bit aren't real flags, they only exist in copies of P pushed to the stack
+const FLAG_MASK: u8 = 0xCF;
+
+#[derive(Deserialize)]
+struct CpuState {
+    pc: u16,
+    s: u8,
+    a: u8,
+    x: u8,
+    y: u8,
+    p: u8,
+    ram: Vec<(u16, u8)>,
+}
+
+#[derive(Deserialize)]
+struct SingleStepTest {
+    name: String,
+    initial: CpuState,
+    #[serde(rename = "final")]
+    expected: CpuState,
+    cycles: Vec<(u16, u8, String)>,
+}
+
+fn registers(state: &CpuState) -> CpuRegisters {
+    return CpuRegisters { a: state.a, x: state.x, y: state.y, sp: state.s, pc: state.pc, p: state.p };
+}
+
+// Runs one instruction and says what came out differently, if anything
+fn runTest(cpu: &mut Cpu<FlatBus>, bus: &Rc<RefCell<FlatBus>>, test: &SingleStepTest) -> Result<(), String> {
+    for (addr, val) in test.initial.ram.iter() {
+        bus.borrow_mut().poke(*addr, *val);
+    }
+    cpu.setRegisters(registers(&test.initial));
+    bus.borrow_mut().takeAccesses();
+
+    let mut cycles = 1;
+    cpu.cycle();
+    while !cpu.isAtInstruction() {
+        cpu.cycle();
+        cycles += 1;
+    }
+
+    let mut problems: Vec<String> = Vec::new();
+    let (actual, expected) = (cpu.getRegisters(), registers(&test.expected));
+    let masked = |registers: CpuRegisters| CpuRegisters { p: registers.p & FLAG_MASK, ..registers };
+    if masked(actual) != masked(expected) {
+        problems.push(format!("registers {:02X?}, wanted {:02X?}", actual, expected));
+    }
+
+    for (addr, val) in test.expected.ram.iter() {
+        let actual = bus.borrow().peekCpuMem(*addr);
+        if actual != *val {
+            problems.push(format!("${:04X} = {:02X}, wanted {:02X}", addr, actual, val));
+        }
+    }
+
+    if cycles != test.cycles.len() {
+        problems.push(format!("took {} cycles, wanted {}", cycles, test.cycles.len()));
+    }
+
+    let accesses = bus.borrow_mut().takeAccesses();
+    let mut expectedWrites = test.cycles.iter()
+        .filter(|(_, _, kind)| kind == "write")
+        .map(|(addr, val, _)| (*addr, *val));
+    for access in accesses.iter() {
+        if let BusAccess::Write(addr, val) = *access {
+            if !expectedWrites.any(|write| write == (addr, val)) {
+                problems.push(format!("wrote {:02X} to ${:04X} out of turn, or not at all on hardware", val, addr));
+                break;
+            }
+        }
+    }
+
+    // a stray read can have side effects on a real bus, like clearing the vblank flag
+    for access in accesses.iter() {
+        if let BusAccess::Read(addr, _) = *access {
+            if !test.cycles.iter().any(|(expected, _, kind)| kind == "read" && *expected == addr) {
+                problems.push(format!("read ${:04X}, which hardware doesn't", addr));
+                break;
+            }
+        }
+    }
+
+    return if problems.is_empty() { Ok(()) } else { Err(problems.join("; ")) };
+}
+
+fn newCpu() -> (Cpu<FlatBus>, Rc<RefCell<FlatBus>>) {
+    let bus = Rc::new(RefCell::new(FlatBus::new()));
+    return (Cpu::new(bus.clone()), bus);
+}
+
+#[test]
+fn runsInlineVector() -> () {
+    // STA ($10),Y
+    let test: SingleStepTest = serde_json::from_str(r#"{
+        "name": "91 10 05",
+        "initial": {"pc": 1024, "s": 253, "a": 66, "x": 0, "y": 5, "p": 36, "ram": [[1024, 145], [1025, 16], [16, 0], [17, 3], [773, 0]]},
+        "final": {"pc": 1026, "s": 253, "a": 66, "x": 0, "y": 5, "p": 36, "ram": [[1024, 145], [1025, 16], [16, 0], [17, 3], [773, 66]]},
+        "cycles": [[1024, 145, "read"], [1025, 16, "read"], [16, 0, "read"], [17, 3, "read"], [773, 0, "read"], [773, 66, "write"]]
+    }"#).unwrap();
+
+    let (mut cpu, bus) = newCpu();
+    assert_eq!(runTest(&mut cpu, &bus, &test), Ok(()));
+}
+
+#[test]
+#[ignore = "needs tests/processor_tests/*.json"]
+fn matchesProcessorTests() -> () {
+    let directory = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests").join("processor_tests");
+    let (mut cpu, bus) = newCpu();
+    let mut failures: Vec<String> = Vec::new();
+    let mut missing: Vec<String> = Vec::new();
+
+    for opcode in (0..=255u8).filter(|opcode| !SKIPPED_OPCODES.contains(opcode)) {
+        let text = match fs::read_to_string(directory.join(format!("{:02x}.json", opcode))) {
+            Ok(text) => text,
+            Err(_) => {
+                missing.push(format!("{:02x}.json", opcode));
+                continue;
+            }
+        };
+
+        let tests: Vec<SingleStepTest> = serde_json::from_str(&text).unwrap();
+        let mut failed = tests.iter().filter_map(|test| runTest(&mut cpu, &bus, test).err().map(|err| (test, err)));
+        // one per opcode is enough to go on
+        if let Some((test, err)) = failed.next() {
+            let count = 1 + failed.count();
+            failures.push(format!("{:02X}: {} of {} failed, first \"{}\": {}", opcode, count, tests.len(), test.name, err));
+        }
+    }
+
+    assert!(missing.is_empty(), "tests/processor_tests is missing {} vector files: {}", missing.len(), missing.join(", "));
+    assert!(failures.is_empty(), "{} opcodes failed:\n{}", failures.len(),
+        failures.iter().take(MAX_REPORTED).cloned().collect::<Vec<String>>().join("\n"));
+}